@@ -1,4 +1,4 @@
-{ lib, rustPlatform }:
+{ lib, rustPlatform, libarchive, zip }:
 rustPlatform.buildRustPackage {
   pname = "pmv";
   version = "0.1.0";
@@ -9,6 +9,9 @@ rustPlatform.buildRustPackage {
 
   buildType = "release";
 
+  # The archive tests read and write archives with bsdtar and zip.
+  nativeCheckInputs = [ libarchive zip ];
+
   meta = with lib; {
     description = "Helper for creating a new Rust scripting project";
     homepage = "https://github.com/bzm3r/pmv";
@@ -0,0 +1,589 @@
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use xshell::{cmd, Shell};
+
+use crate::{
+    has_text_file_name, is_text_mime,
+    replace::{Mode, Replacer},
+    report::{confirm, Format, Reporter},
+};
+
+const BLOCK: usize = 512;
+
+/// Where the fields `pmv` reads or rewrites are in a tar header.
+const NAME: std::ops::Range<usize> = 0..100;
+const SIZE: std::ops::Range<usize> = 124..136;
+const CHECKSUM: std::ops::Range<usize> = 148..156;
+const TYPEFLAG: usize = 156;
+const LINKNAME: std::ops::Range<usize> = 157..257;
+const MAGIC: std::ops::Range<usize> = 257..263;
+const PREFIX: std::ops::Range<usize> = 345..500;
+
+/// What is done to the members of an archive.
+#[derive(Default)]
+struct Changes {
+    renamed: Vec<(String, String)>,
+    rewritten: Vec<(String, usize)>,
+}
+
+/// The kinds of archive that can be rewritten, told apart by their first
+/// bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl Kind {
+    fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Kind::TarGz
+        } else if bytes.starts_with(b"PK\x03\x04") {
+            Kind::Zip
+        } else {
+            Kind::Tar
+        }
+    }
+}
+
+/// The members of the archive at `path` (holding `bytes`), as an
+/// uncompressed tar archive. Zip archives are converted with `bsdtar`
+/// (libarchive), which keeps their members' paths, modes and times.
+fn read_as_tar(
+    sh: &Shell,
+    path: &Path,
+    bytes: Vec<u8>,
+    kind: Kind,
+) -> anyhow::Result<Vec<u8>> {
+    Ok(match kind {
+        Kind::Tar => bytes,
+        Kind::TarGz => {
+            cmd!(sh, "gzip -dc")
+                .stdin(&bytes)
+                .output()
+                .with_context(|| {
+                    format!("...while decompressing {}", path.display())
+                })?
+                .stdout
+        }
+        Kind::Zip => {
+            let members = format!("@{}", path.display());
+            cmd!(sh, "bsdtar -cf - --format pax {members}")
+                .output()
+                .with_context(|| {
+                    format!("...while reading {} with bsdtar", path.display())
+                })?
+                .stdout
+        }
+    })
+}
+
+/// Writes the members of the tar archive `tar` to `output`, as an archive
+/// of the given `kind`.
+fn write_as(
+    sh: &Shell,
+    output: &Path,
+    tar: Vec<u8>,
+    kind: Kind,
+) -> anyhow::Result<()> {
+    let contents = match kind {
+        Kind::Tar => tar,
+        Kind::TarGz => {
+            cmd!(sh, "gzip -c")
+                .stdin(&tar)
+                .output()
+                .with_context(|| {
+                    format!("...while compressing {}", output.display())
+                })?
+                .stdout
+        }
+        Kind::Zip => {
+            // Written straight to the file, as bsdtar pads what it writes
+            // to stdout to a whole number of blocks.
+            return cmd!(sh, "bsdtar -cf {output} --format zip @-")
+                .stdin(&tar)
+                .quiet()
+                .run()
+                .with_context(|| {
+                    format!("...while writing {} with bsdtar", output.display())
+                });
+        }
+    };
+    fs::write(output, contents)
+        .with_context(|| format!("Could not write {}", output.display()))
+}
+
+/// Replaces `from` with `to` in the text members of the `.tar`, `.tar.gz`
+/// or `.zip` archive at `archive`, and in the paths (and link targets) of
+/// all of its members, writing the result to `output` (by default, the
+/// archive's own path with `from` replaced in its file name). The archive
+/// is rewritten in memory, never extracted; members keep their modes,
+/// owners and times. Gzip is (de)compressed with the `gzip` program, and
+/// zip archives are read and written with `bsdtar`.
+pub fn rename_in_archive(
+    archive: &Path,
+    from: &str,
+    to: &str,
+    output: Option<PathBuf>,
+    dry_run: bool,
+    yes: bool,
+) -> anyhow::Result<()> {
+    let sh = Shell::new()?;
+    let mut reporter = Reporter::new(false, Format::Text);
+    let bytes = fs::read(archive)
+        .with_context(|| format!("Could not read {}", archive.display()))?;
+    let kind = Kind::detect(&bytes);
+    let tar = read_as_tar(&sh, archive, bytes, kind)?;
+    let replacer = Replacer::new(from, to, Mode::Literal)?;
+    let mut changes = Changes::default();
+    let rewritten = rewrite_tar(&tar, &replacer, &mut changes)
+        .with_context(|| format!("...while reading {}", archive.display()))?;
+    if changes.renamed.is_empty() && changes.rewritten.is_empty() {
+        reporter.info(format_args!(
+            "Nothing in {} mentions {from:?}, so no new archive was written.",
+            archive.display()
+        ))?;
+        reporter.finish()?;
+        return Ok(());
+    }
+
+    let output = match output {
+        Some(output) => output,
+        None => {
+            let name = archive.file_name().unwrap_or_default();
+            let name = name.to_string_lossy();
+            let (new_name, count) = replacer.replace(&name);
+            if count == 0 {
+                bail!(
+                    "The name of {} does not mention {from:?}, so pass --output to say where the new archive goes.",
+                    archive.display()
+                );
+            }
+            archive.with_file_name(new_name.as_ref())
+        }
+    };
+    if output.exists() {
+        bail!("{} already exists!", output.display());
+    }
+    if dry_run {
+        for (old, new) in &changes.renamed {
+            reporter
+                .info(format_args!("Would rename member {old} to {new}."))?;
+        }
+        for (path, count) in &changes.rewritten {
+            reporter.info(format_args!(
+                "Would rewrite member {path} ({count} replacement(s))."
+            ))?;
+        }
+        reporter.info(format_args!("Would write {}.", output.display()))?;
+        reporter.finish()?;
+        return Ok(());
+    }
+    reporter.flush()?;
+    if !yes
+        && !confirm(
+            format_args!(
+                "Write {} with {} member(s) renamed and {} rewritten?",
+                output.display(),
+                changes.renamed.len(),
+                changes.rewritten.len()
+            ),
+            "--yes",
+        )?
+    {
+        reporter.info(format_args!(
+            "Aborted: nothing was written (pass --yes to skip asking)."
+        ))?;
+        reporter.finish()?;
+        return Ok(());
+    }
+    write_as(&sh, &output, rewritten, kind)?;
+    reporter.info(format_args!(
+        "Wrote {}, with {} member(s) renamed and {} rewritten.",
+        output.display(),
+        changes.renamed.len(),
+        changes.rewritten.len()
+    ))?;
+    reporter.finish()?;
+    Ok(())
+}
+
+/// A NUL-padded string field of a tar header.
+fn field(header: &[u8]) -> Cow<'_, str> {
+    let end = header.iter().position(|&b| b == 0).unwrap_or(header.len());
+    String::from_utf8_lossy(&header[..end])
+}
+
+/// Replaces the old name in the string field `range` of `header`, cutting
+/// the new value short if it does not fit. Returns whether it had to be.
+fn replace_field(
+    header: &mut [u8],
+    range: std::ops::Range<usize>,
+    replacer: &Replacer,
+) -> bool {
+    let old = field(&header[range.clone()]).into_owned();
+    let (Cow::Owned(new), _) = replacer.replace(&old) else {
+        return false;
+    };
+    set_field(&mut header[range], &new)
+}
+
+/// Sets a NUL-padded string field, returning whether `value` was cut short.
+fn set_field(field: &mut [u8], value: &str) -> bool {
+    field.fill(0);
+    let n = value.len().min(field.len());
+    field[..n].copy_from_slice(&value.as_bytes()[..n]);
+    n < value.len()
+}
+
+/// A pax extended header record.
+fn pax_record(key: &str, value: &str) -> String {
+    let body = format!(" {key}={value}\n");
+    // The length counts its own digits.
+    let mut len = body.len() + 1;
+    while len.to_string().len() + body.len() != len {
+        len += 1;
+    }
+    format!("{len}{body}")
+}
+
+/// Appends a header and its data to the archive `out`, with the size and
+/// checksum filled in.
+fn push_entry(out: &mut Vec<u8>, mut header: [u8; BLOCK], data: &[u8]) {
+    header[SIZE].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+    header[CHECKSUM].fill(b' ');
+    let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    header[CHECKSUM].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    out.extend_from_slice(&header);
+    out.extend_from_slice(data);
+    out.resize(out.len().div_ceil(BLOCK) * BLOCK, 0);
+}
+
+fn parse_octal(field: &[u8]) -> anyhow::Result<usize> {
+    if field.first().is_some_and(|b| b & 0x80 != 0) {
+        bail!("Members of 8 GiB or more are not supported.");
+    }
+    let text = String::from_utf8_lossy(field);
+    let digits = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    usize::from_str_radix(digits, 8)
+        .with_context(|| format!("{digits:?} is not an octal number"))
+}
+
+/// Rewrites the `path` and `linkpath` records of a pax extended header.
+fn rewrite_pax(data: &[u8], replacer: &Replacer) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(data.len());
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest
+            .iter()
+            .position(|&b| b == b' ')
+            .context("Malformed pax header.")?;
+        let record_len: usize = std::str::from_utf8(&rest[..space])?.parse()?;
+        let record = rest
+            .get(space + 1..record_len)
+            .context("Malformed pax header.")?;
+        let record = std::str::from_utf8(record)?.trim_end_matches('\n');
+        let (key, value) =
+            record.split_once('=').context("Malformed pax header.")?;
+        let value = match key {
+            "path" | "linkpath" => replacer.replace(value).0,
+            _ => Cow::Borrowed(value),
+        };
+        out += &pax_record(key, &value);
+        rest = &rest[record_len..];
+    }
+    Ok(out)
+}
+
+/// Rewrites a whole (uncompressed) tar archive, recording what changed.
+fn rewrite_tar(
+    tar: &[u8],
+    replacer: &Replacer,
+    changes: &mut Changes,
+) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(tar.len());
+    let mut pos = 0;
+    // The (old) path given for the next member by a GNU long name or a pax
+    // header, which takes precedence over its header's own fields (and
+    // whether its link target is given that way too).
+    let mut long_path: Option<String> = None;
+    let mut long_link = false;
+    // The pax header for the next member, held back so that the new names
+    // too long for that member's own fields can be added to it.
+    let mut pax: Option<([u8; BLOCK], String)> = None;
+    while pos + BLOCK <= tar.len() {
+        let mut header: [u8; BLOCK] = tar[pos..pos + BLOCK].try_into()?;
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let size = parse_octal(&header[SIZE])?;
+        let start = pos + BLOCK;
+        let data = tar
+            .get(start..start + size)
+            .context("The archive is truncated.")?;
+        pos = start + size.div_ceil(BLOCK) * BLOCK;
+        let typeflag = header[TYPEFLAG];
+        let posix = &header[MAGIC] == b"ustar\0";
+        match typeflag {
+            b'L' | b'K' => {
+                let old = field(data).into_owned();
+                if typeflag == b'L' {
+                    long_path = Some(old.clone());
+                } else {
+                    long_link = true;
+                }
+                let mut data = replacer.replace(&old).0.into_owned();
+                data.push('\0');
+                push_entry(&mut out, header, data.as_bytes());
+                continue;
+            }
+            b'x' => {
+                let text = std::str::from_utf8(data)?;
+                if let Some(path) = text
+                    .split('\n')
+                    .find_map(|record| pax_value(record, "path"))
+                {
+                    long_path = Some(path.to_string());
+                }
+                long_link |= text
+                    .split('\n')
+                    .any(|record| pax_value(record, "linkpath").is_some());
+                replace_field(&mut header, NAME, replacer);
+                pax = Some((header, rewrite_pax(data, replacer)?));
+                continue;
+            }
+            _ => {}
+        }
+
+        let from_long_path = long_path.is_some();
+        let path = long_path.take().unwrap_or_else(|| {
+            let (prefix, name) = (field(&header[PREFIX]), field(&header[NAME]));
+            if posix && !prefix.is_empty() {
+                format!("{prefix}/{name}")
+            } else {
+                name.into_owned()
+            }
+        });
+        let (new_path, count) = replacer.replace(&path);
+        if count > 0 {
+            changes.renamed.push((path.clone(), new_path.to_string()));
+        }
+        let data = match typeflag {
+            b'0' | 0 | b'7' => rewrite_member(&path, data, replacer, changes),
+            _ => Cow::Borrowed(data),
+        };
+
+        let old_link = field(&header[LINKNAME]).into_owned();
+        let mut records = String::new();
+        let mut cut = replace_field(&mut header, NAME, replacer);
+        if posix {
+            cut |= replace_field(&mut header, PREFIX, replacer);
+        }
+        if cut && !from_long_path {
+            records += &pax_record("path", &new_path);
+        }
+        if matches!(typeflag, b'1' | b'2')
+            && replace_field(&mut header, LINKNAME, replacer)
+            && !long_link
+        {
+            records += &pax_record("linkpath", &replacer.replace(&old_link).0);
+        }
+        long_link = false;
+        if !records.is_empty() {
+            let (_, pax_records) = pax.get_or_insert_with(|| {
+                let mut pax = header;
+                set_field(&mut pax[NAME], "././@PaxHeader");
+                pax[LINKNAME].fill(0);
+                if posix {
+                    pax[PREFIX].fill(0);
+                }
+                pax[TYPEFLAG] = b'x';
+                (pax, String::new())
+            });
+            *pax_records += &records;
+        }
+        if let Some((pax_header, pax_records)) = pax.take() {
+            push_entry(&mut out, pax_header, pax_records.as_bytes());
+        }
+        push_entry(&mut out, header, &data);
+    }
+    out.resize(out.len() + 2 * BLOCK, 0);
+    Ok(out)
+}
+
+/// The value of the pax record `record` (given without its length) has for
+/// `key`, if it is for that key.
+fn pax_value<'a>(record: &'a str, key: &str) -> Option<&'a str> {
+    record
+        .split_once(' ')?
+        .1
+        .strip_prefix(key)?
+        .strip_prefix('=')
+}
+
+/// The contents of the regular member at `path`, with the old name replaced
+/// if it is text (judged as files on disk are, by name and MIME type).
+fn rewrite_member<'a>(
+    path: &str,
+    data: &'a [u8],
+    replacer: &Replacer,
+    changes: &mut Changes,
+) -> Cow<'a, [u8]> {
+    let is_text = has_text_file_name(Path::new(path))
+        || is_text_mime(tree_magic_mini::from_u8(data), &[]);
+    let Ok(text) = std::str::from_utf8(data) else {
+        return Cow::Borrowed(data);
+    };
+    if !is_text {
+        return Cow::Borrowed(data);
+    }
+    match replacer.replace(text) {
+        (Cow::Owned(new), count) => {
+            changes.rewritten.push((path.to_string(), count));
+            Cow::Owned(new.into_bytes())
+        }
+        (Cow::Borrowed(_), _) => Cow::Borrowed(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    /// Longer than the old name, so that paths grow.
+    const NEW: &str = "a-longer-name";
+
+    /// A member whose name only just fits a ustar header's name field, and
+    /// does not once renamed.
+    fn deep() -> String {
+        format!("old-proj/src/old-proj-{}.txt", "e".repeat(86))
+    }
+
+    /// The project `old-proj` in `dir`: a text member, a binary one, an
+    /// executable script, a symlink through the project's name, and the
+    /// [`deep`] member.
+    fn project(dir: &TempDir) -> Shell {
+        dir.write("old-proj/src/lib.rs", "old-proj here\n");
+        dir.write("old-proj/data.bin", b"\0\x01old-proj\0\xff");
+        let script = dir.write("old-proj/run.sh", "#!/bin/sh\nold-proj\n");
+        dir.write(&deep(), "old-proj\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{symlink, PermissionsExt};
+            fs::set_permissions(script, fs::Permissions::from_mode(0o755))
+                .unwrap();
+            symlink("../old-proj/src/lib.rs", dir.path().join("old-proj/link"))
+                .unwrap();
+        }
+        let sh = Shell::new().unwrap();
+        sh.change_dir(dir.path());
+        sh
+    }
+
+    fn rename(archive: &Path, dry_run: bool) {
+        rename_in_archive(archive, "old-proj", NEW, None, dry_run, true)
+            .unwrap();
+    }
+
+    fn member(sh: &Shell, archive: &str, path: &str) -> Vec<u8> {
+        cmd!(sh, "bsdtar -xOf {archive} {path}")
+            .output()
+            .unwrap()
+            .stdout
+    }
+
+    /// Archives the project as `archive` with the command `make`, renames
+    /// `old-proj` in it, and checks what came out in `renamed`.
+    fn round_trip(archive: &str, renamed: &str, make: &str) {
+        let dir = TempDir::new();
+        let sh = project(&dir);
+        let mut args = make.split(' ');
+        let program = args.next().unwrap();
+        cmd!(sh, "{program} {args...} {archive} old-proj")
+            .quiet()
+            .run()
+            .unwrap();
+        rename(&dir.path().join(archive), false);
+
+        let listing = cmd!(sh, "bsdtar -tvf {renamed}").read().unwrap();
+        assert!(!listing.contains("old-proj/"), "{listing}");
+        assert_eq!(
+            member(&sh, renamed, "a-longer-name/src/lib.rs"),
+            b"a-longer-name here\n"
+        );
+        assert_eq!(
+            member(&sh, renamed, "a-longer-name/data.bin"),
+            b"\0\x01old-proj\0\xff"
+        );
+        let deep = deep().replace("old-proj", NEW);
+        assert_eq!(member(&sh, renamed, &deep), b"a-longer-name\n");
+        #[cfg(unix)]
+        {
+            let line = |name: &str| {
+                listing
+                    .lines()
+                    .find(|line| line.contains(name))
+                    .unwrap_or_else(|| panic!("no {name} in {listing}"))
+                    .to_string()
+            };
+            assert!(line("a-longer-name/run.sh").starts_with("-rwxr-xr-x"));
+            assert!(line("a-longer-name/link")
+                .ends_with("-> ../a-longer-name/src/lib.rs"));
+        }
+    }
+
+    #[test]
+    fn tar_archives_round_trip() {
+        round_trip("old-proj.tar", "a-longer-name.tar", "tar -cf");
+    }
+
+    #[test]
+    fn pax_and_ustar_archives_round_trip() {
+        round_trip("old-proj.tar", "a-longer-name.tar", "tar --format=pax -cf");
+        round_trip(
+            "old-proj.tar",
+            "a-longer-name.tar",
+            "tar --format=ustar -cf",
+        );
+    }
+
+    #[test]
+    fn gzipped_archives_round_trip() {
+        round_trip("old-proj.tar.gz", "a-longer-name.tar.gz", "tar -czf");
+    }
+
+    #[test]
+    fn zip_archives_round_trip() {
+        round_trip("old-proj.zip", "a-longer-name.zip", "zip -qry");
+    }
+
+    #[test]
+    fn dry_runs_write_nothing() {
+        let dir = TempDir::new();
+        let sh = project(&dir);
+        cmd!(sh, "tar -cf old-proj.tar old-proj").run().unwrap();
+        rename(&dir.path().join("old-proj.tar"), true);
+        assert!(!dir.path().join("a-longer-name.tar").exists());
+    }
+
+    #[test]
+    fn archives_not_mentioning_the_name_are_not_rewritten() {
+        let dir = TempDir::new();
+        dir.write("other/file.txt", "nothing to see");
+        let sh = Shell::new().unwrap();
+        sh.change_dir(dir.path());
+        cmd!(sh, "tar -cf old-proj.tar other").run().unwrap();
+        rename(&dir.path().join("old-proj.tar"), false);
+        assert!(!dir.path().join("a-longer-name.tar").exists());
+    }
+}
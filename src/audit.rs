@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// An append-only record of every change made to the filesystem or the
+/// forge, one JSON object per line, written as each change is made so that
+/// an interrupted run still leaves a record of what it did.
+pub struct AuditLog(Mutex<File>);
+
+impl AuditLog {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| {
+                format!("Could not open the audit log {}", path.display())
+            })?;
+        Ok(AuditLog(Mutex::new(file)))
+    }
+
+    /// Appends an `event`, with the details in `fields` (an object) and the
+    /// time it happened, in milliseconds since the Unix epoch.
+    pub fn record(&self, event: &str, fields: Value) -> anyhow::Result<()> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis());
+        let mut entry = json!({ "time": millis, "event": event });
+        if let (Some(entry), Value::Object(fields)) =
+            (entry.as_object_mut(), fields)
+        {
+            entry.extend(fields);
+        }
+        let line = entry.to_string() + "\n";
+        self.0
+            .lock()
+            .unwrap()
+            .write_all(line.as_bytes())
+            .with_context(|| "...while writing to the audit log")
+    }
+}
+
+/// Records `event` in `log`, if there is one.
+pub fn record(
+    log: Option<&AuditLog>,
+    event: &str,
+    fields: Value,
+) -> anyhow::Result<()> {
+    match log {
+        Some(log) => log.record(event, fields),
+        None => Ok(()),
+    }
+}
+
+/// A file's size and modification time, which tell whether it has changed
+/// since it was recorded in the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+    len: u64,
+    /// In nanoseconds since the Unix epoch.
+    modified: u64,
+}
+
+impl Stamp {
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH);
+        Some(Stamp {
+            len: metadata.len(),
+            modified: u64::try_from(modified.ok()?.as_nanos()).ok()?,
+        })
+    }
+}
+
+/// The details recorded for the file at `fp` having just been rewritten with
+/// `count` replacements, its [`Stamp`] included so that `--resume` can tell
+/// whether it is still as it was left.
+pub fn rewrite_fields(fp: &Path, count: usize) -> Value {
+    let mut fields = json!({ "path": fp, "count": count });
+    if let Some(stamp) = Stamp::of(fp) {
+        fields["len"] = stamp.len.into();
+        fields["modified"] = stamp.modified.into();
+    }
+    fields
+}
+
+/// The files the log at `path` records as rewritten, with their stamps as of
+/// then (the latest, for a file rewritten more than once). Malformed lines,
+/// such as the last one of a run that was killed, are skipped.
+pub fn rewritten_files(path: &Path) -> anyhow::Result<HashMap<PathBuf, Stamp>> {
+    let text = fs::read_to_string(path).with_context(|| {
+        format!("Could not read the audit log {}", path.display())
+    })?;
+    let mut files = HashMap::new();
+    for line in text.lines() {
+        let Ok(entry) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        if entry["event"] != "rewrite" {
+            continue;
+        }
+        let (Some(fp), Some(len), Some(modified)) = (
+            entry["path"].as_str(),
+            entry["len"].as_u64(),
+            entry["modified"].as_u64(),
+        ) else {
+            continue;
+        };
+        files.insert(PathBuf::from(fp), Stamp { len, modified });
+    }
+    Ok(files)
+}
+
+/// What a run set out to do, recorded as its `start` event so that
+/// `--resume` can pick it up again even once the project has been moved.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Run {
+    /// Where the project was when the run started.
+    pub dir: PathBuf,
+    pub old_name: String,
+    pub new_name: String,
+    /// The text replaced, and what with.
+    pub from: String,
+    pub to: String,
+    /// Where the project was moved to, if the run got that far.
+    #[serde(skip_serializing, default)]
+    pub moved_to: Option<PathBuf>,
+}
+
+impl Run {
+    pub fn fields(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+}
+
+/// The last run the log at `path` records starting, and whether it moved
+/// the project. Fails if there is none, as then there is no telling what
+/// the names were.
+pub fn last_run(path: &Path) -> anyhow::Result<Run> {
+    let text = fs::read_to_string(path).with_context(|| {
+        format!("Could not read the audit log {}", path.display())
+    })?;
+    let mut run = None;
+    for line in text.lines() {
+        let Ok(entry) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        if entry["event"] == "start" {
+            run = serde_json::from_value::<Run>(entry).ok();
+        } else if let (Some(run), Some("move"), Some(to)) =
+            (&mut run, entry["event"].as_str(), entry["to"].as_str())
+        {
+            run.moved_to = Some(to.into());
+        }
+    }
+    run.with_context(|| {
+        format!(
+            "{} records no run starting, so there is no telling which names to resume renaming.",
+            path.display()
+        )
+    })
+}
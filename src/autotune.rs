@@ -0,0 +1,107 @@
+use std::{
+    fs,
+    io::Read,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::filter::WalkFilter;
+
+/// How many files are sampled from the start of the walk.
+const SAMPLE_FILES: usize = 32;
+/// How much of each sampled file is read (and sniffed), so that one large
+/// file does not skew the sample.
+const SAMPLE_BYTES: u64 = 64 * 1024;
+/// Reading taking this many times longer than sniffing what was read (or
+/// more) means the storage is slow, as on a network mount.
+const IO_BOUND_RATIO: u32 = 4;
+
+/// Threads and IO concurrency chosen by [`tune`].
+pub struct Tuned {
+    pub threads: usize,
+    pub io_concurrency: usize,
+}
+
+/// Picks `--threads` and `--io-concurrency` for the project `filter` walks on a
+/// machine with `cores` cores, by timing how long reading the first files
+/// of the walk takes against how long sniffing their MIME types does. When
+/// reading dominates, fewer threads (and fewer reads at once) are used, so
+/// as not to flood slow storage; when sniffing does, every core is put to
+/// work and reads are not held back. Reports what was chosen, as flags.
+/// Sampling stops early once `cancelled` is set.
+pub fn tune(
+    filter: &WalkFilter,
+    cores: usize,
+    cancelled: &AtomicBool,
+) -> Tuned {
+    let (mut io, mut cpu, mut sampled) = (Duration::ZERO, Duration::ZERO, 0);
+    let files = filter
+        .walker()
+        .build()
+        .take_while(|_| !cancelled.load(Ordering::Relaxed))
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_some_and(|ft| ft.is_file()))
+        .filter(|entry| filter.allows(entry.path()))
+        .take(SAMPLE_FILES);
+    for entry in files {
+        let started = Instant::now();
+        let mut head = Vec::new();
+        let read = fs::File::open(entry.path())
+            .and_then(|file| file.take(SAMPLE_BYTES).read_to_end(&mut head));
+        if read.is_err() {
+            continue;
+        }
+        io += started.elapsed();
+        let started = Instant::now();
+        tree_magic_mini::from_u8(&head);
+        cpu += started.elapsed();
+        sampled += 1;
+    }
+    let tuned = if sampled == 0 {
+        Tuned {
+            threads: cores,
+            io_concurrency: cores.min(8),
+        }
+    } else if io > cpu * IO_BOUND_RATIO {
+        let threads = (cores / 2).max(1);
+        Tuned {
+            threads,
+            io_concurrency: threads.min(4),
+        }
+    } else if io < cpu {
+        Tuned {
+            threads: cores,
+            io_concurrency: cores,
+        }
+    } else {
+        Tuned {
+            threads: cores,
+            io_concurrency: cores.min(8),
+        }
+    };
+    let per_file =
+        |total: Duration| total.as_secs_f64() * 1000.0 / sampled.max(1) as f64;
+    println!(
+        "Autotuned from {sampled} file(s) (reading {:.3}ms and sniffing {:.3}ms per file): --threads {} --io-concurrency {}.",
+        per_file(io),
+        per_file(cpu),
+        tuned.threads,
+        tuned.io_concurrency
+    );
+    tuned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn a_cancelled_run_samples_nothing() {
+        let dir = TempDir::new();
+        dir.write("src/lib.rs", "fn main() {}\n");
+        let filter = WalkFilter::new(dir.path(), &[]).unwrap();
+        let tuned = tune(&filter, 6, &AtomicBool::new(true));
+        assert_eq!((tuned.threads, tuned.io_concurrency), (6, 6));
+    }
+}
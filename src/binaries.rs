@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use regex::bytes::Regex;
+
+use crate::{audit, classify_file, write_atomically, Found, RewriteOpts};
+
+/// A planned in-place change to a binary file, relative to the project root.
+pub struct BinaryRewrite {
+    pub path: PathBuf,
+    contents: Vec<u8>,
+    pub count: usize,
+}
+
+/// Finds every binary file under `root` (that `opts.filter` allows) containing
+/// the bytes of `from`, and works out its contents with them replaced by
+/// those of `to`. Only done when the two are the same length, so that
+/// nothing in the file moves: formats that record offsets or sizes would
+/// be corrupted otherwise.
+pub fn plan(
+    root: &Path,
+    from: &str,
+    to: &str,
+    opts: &RewriteOpts,
+) -> anyhow::Result<Vec<BinaryRewrite>> {
+    let filter = &opts.filter;
+    if from.len() != to.len() {
+        bail!(
+            "Refusing to replace {from:?} with {to:?} in binary files: they are {} and {} bytes long, and only same-length replacements leave binary files intact.",
+            from.len(),
+            to.len()
+        );
+    }
+    let pattern = Regex::new(&regex::escape(from))?;
+    let mut rewrites = Vec::new();
+    for result in filter.walker().build() {
+        let entry = result?;
+        if !entry.file_type().is_some_and(|ft| ft.is_file())
+            || !filter.allows(entry.path())
+            || filter.is_done(entry.path())
+        {
+            continue;
+        }
+        let _open = opts.open_files.acquire();
+        let Found::NotText(fp, _) = classify_file(
+            entry.into_path(),
+            true,
+            false,
+            &opts.mime_allow,
+            opts.detect.as_deref(),
+        ) else {
+            continue;
+        };
+        let contents = std::fs::read(&fp).with_context(|| {
+            format!("Could not read file: {}", fp.display())
+        })?;
+        let count = pattern.find_iter(&contents).count();
+        if count == 0 {
+            continue;
+        }
+        let contents =
+            pattern.replace_all(&contents, to.as_bytes()).into_owned();
+        rewrites.push(BinaryRewrite {
+            path: fp.strip_prefix(root).unwrap_or(&fp).to_path_buf(),
+            contents,
+            count,
+        });
+    }
+    rewrites.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(rewrites)
+}
+
+pub fn print_rewrites(rewrites: &[BinaryRewrite]) {
+    for rw in rewrites {
+        println!(
+            "Would rewrite binary file {} ({} replacement(s)).",
+            rw.path.display(),
+            rw.count
+        );
+    }
+}
+
+/// Writes out the planned binary rewrites under `root` (where the project
+/// lives once it has been moved), returning the paths of those written.
+/// Failures are handled as for text files (see [`RewriteOpts::per_file`]).
+pub fn apply(
+    root: &Path,
+    rewrites: Vec<BinaryRewrite>,
+    opts: &RewriteOpts,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut written = Vec::with_capacity(rewrites.len());
+    for rw in rewrites {
+        let fp = root.join(&rw.path);
+        println!(
+            "Rewriting binary file {} ({} replacement(s)).",
+            rw.path.display(),
+            rw.count
+        );
+        let _open = opts.open_files.acquire();
+        let result = write_atomically(&fp, &rw.contents).and_then(|()| {
+            // Same length, by construction.
+            let len = rw.contents.len() as u64;
+            opts.diffstat.record(len, len);
+            let mut fields = audit::rewrite_fields(&fp, rw.count);
+            fields["binary"] = true.into();
+            audit::record(opts.audit.as_ref(), "rewrite", fields)?;
+            opts.emit(|| {
+                serde_json::json!({
+                    "path": rw.path,
+                    "count": rw.count,
+                    "status": "rewritten",
+                    "binary": true,
+                })
+            });
+            Ok(Some(rw.path))
+        });
+        written.extend(opts.per_file(result)?);
+    }
+    Ok(written)
+}
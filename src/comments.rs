@@ -0,0 +1,222 @@
+use std::{ops::Range, path::Path};
+
+/// How comments (and the strings that may hide comment markers) are written
+/// in a language, as far as `--comments-only` needs to know.
+struct Syntax {
+    line: &'static [&'static str],
+    /// Python's triple-quoted strings count as block comments: they are
+    /// (nearly always) docstrings.
+    block: &'static [(&'static str, &'static str)],
+    /// Strings end at their quote or, since they are only tracked to skip
+    /// over comment markers, at the end of the line.
+    quotes: &'static [char],
+    /// `'` starts a character literal only if it is closed right after the
+    /// character: otherwise it is a lifetime (`'a`).
+    lifetimes: bool,
+}
+
+const C_LIKE: Syntax = Syntax {
+    line: &["//"],
+    block: &[("/*", "*/")],
+    quotes: &['"', '\''],
+    lifetimes: false,
+};
+
+const JS_LIKE: Syntax = Syntax {
+    quotes: &['"', '\'', '`'],
+    ..C_LIKE
+};
+
+const RUST: Syntax = Syntax {
+    quotes: &['"'],
+    lifetimes: true,
+    ..C_LIKE
+};
+
+const CSS: Syntax = Syntax {
+    line: &[],
+    ..C_LIKE
+};
+
+const HASH: Syntax = Syntax {
+    line: &["#"],
+    block: &[],
+    quotes: &['"', '\''],
+    lifetimes: false,
+};
+
+const PYTHON: Syntax = Syntax {
+    block: &[("\"\"\"", "\"\"\""), ("'''", "'''")],
+    ..HASH
+};
+
+const INI: Syntax = Syntax {
+    line: &["#", ";"],
+    ..HASH
+};
+
+const DASHES: Syntax = Syntax {
+    line: &["--"],
+    block: &[("/*", "*/")],
+    ..HASH
+};
+
+const HASKELL: Syntax = Syntax {
+    line: &["--"],
+    block: &[("{-", "-}")],
+    quotes: &['"'],
+    lifetimes: false,
+};
+
+const MARKUP: Syntax = Syntax {
+    line: &[],
+    block: &[("<!--", "-->")],
+    quotes: &[],
+    lifetimes: false,
+};
+
+fn syntax_for(path: &Path) -> Option<&'static Syntax> {
+    let name = path.file_name()?.to_str()?;
+    match name {
+        "Makefile" | "makefile" | "GNUmakefile" | "Dockerfile"
+        | "CMakeLists.txt" | "Gemfile" | "Rakefile" => return Some(&HASH),
+        _ => {}
+    }
+    Some(match path.extension()?.to_str()? {
+        "rs" => &RUST,
+        "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" | "hh" | "cs" | "java"
+        | "kt" | "kts" | "scala" | "swift" | "dart" | "proto" | "php"
+        | "scss" | "less" => &C_LIKE,
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "mts" | "cts" | "go" => {
+            &JS_LIKE
+        }
+        "css" => &CSS,
+        "py" | "pyi" => &PYTHON,
+        "sh" | "bash" | "zsh" | "fish" | "rb" | "pl" | "pm" | "r" | "toml"
+        | "yaml" | "yml" | "nix" | "cmake" | "mk" | "ps1" => &HASH,
+        "ini" | "cfg" | "conf" => &INI,
+        "sql" | "lua" => &DASHES,
+        "hs" => &HASKELL,
+        "html" | "htm" | "xml" | "svg" | "vue" | "svelte" => &MARKUP,
+        _ => return None,
+    })
+}
+
+/// Where `text` has its line comments, block comments and docstrings, as
+/// byte ranges (markers included), going by the language `path`'s name or
+/// extension says it is in. `None` if it is not one of the recognized
+/// languages.
+///
+/// This is a lightweight tokenizer, not a parser: it skips over string and
+/// character literals so that the markers inside them are not taken for
+/// comments, but knows nothing of raw strings, nested block comments or
+/// heredocs.
+pub fn ranges(path: &Path, text: &str) -> Option<Vec<Range<usize>>> {
+    let syntax = syntax_for(path)?;
+    let mut ranges = Vec::new();
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        let rest = &text[i..];
+        if syntax.line.iter().any(|marker| rest.starts_with(marker)) {
+            let end = rest.find('\n').map_or(text.len(), |n| i + n);
+            ranges.push(i..end);
+            i = end;
+        } else if let Some((open, close)) =
+            syntax.block.iter().find(|(open, _)| rest.starts_with(open))
+        {
+            let end = rest[open.len()..]
+                .find(close)
+                .map_or(text.len(), |n| i + open.len() + n + close.len());
+            ranges.push(i..end);
+            i = end;
+        } else if c == '\'' && syntax.lifetimes {
+            i += char_literal_len(rest);
+        } else if syntax.quotes.contains(&c) {
+            i += string_len(rest, c);
+        } else {
+            i += c.len_utf8();
+        }
+    }
+    Some(ranges)
+}
+
+/// The length of the string literal `rest` starts with, opened by `quote`.
+fn string_len(rest: &str, quote: char) -> usize {
+    let mut chars = rest.char_indices().skip(1);
+    while let Some((n, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\n' => return n,
+            c if c == quote => return n + c.len_utf8(),
+            _ => {}
+        }
+    }
+    rest.len()
+}
+
+/// The length of the character literal `rest` starts with, or 1 for the
+/// `'` of a lifetime.
+fn char_literal_len(rest: &str) -> usize {
+    let body = &rest[1..];
+    if let Some(escaped) = body.strip_prefix('\\') {
+        let closed = escaped.get(1..).and_then(|after| {
+            after.split('\n').next().and_then(|line| line.find('\''))
+        });
+        return closed.map_or(1, |n| n + 4);
+    }
+    match body.chars().next() {
+        Some(c) if body[c.len_utf8()..].starts_with('\'') => c.len_utf8() + 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The comments `ranges` finds in `text`, taken to be in `file`.
+    fn comments<'a>(file: &str, text: &'a str) -> Vec<&'a str> {
+        ranges(Path::new(file), text)
+            .unwrap()
+            .into_iter()
+            .map(|range| &text[range])
+            .collect()
+    }
+
+    #[test]
+    fn markers_inside_strings_are_not_comments() {
+        let text = "let url = \"http://x /* y */\"; // one\n\
+                    let c = '/'; /* two */ let s = \"\\\"//\";\n";
+        assert_eq!(comments("a.c", text), ["// one", "/* two */"]);
+        assert_eq!(comments("a.js", "f(`//${x}`, '//') // one"), ["// one"]);
+        assert_eq!(
+            comments(
+                "a.py",
+                "x = '#' + \"#\"  # one\n\"\"\"two\nlines\"\"\"\n"
+            ),
+            ["# one", "\"\"\"two\nlines\"\"\""]
+        );
+    }
+
+    #[test]
+    fn rust_lifetimes_do_not_open_strings() {
+        let text = "fn f<'a>(x: &'a str) -> char { '\"' } // one\n\
+                    const Q: [char; 3] = ['\\'', '\\u{2F}', '/']; // two\n\
+                    struct S<'b>(&'b str, \"//\"); /* three */\n";
+        assert_eq!(comments("a.rs", text), ["// one", "// two", "/* three */"]);
+    }
+
+    #[test]
+    fn an_unterminated_string_ends_with_its_line() {
+        assert_eq!(comments("a.c", "x = \"oops\n// one"), ["// one"]);
+        assert_eq!(comments("a.c", "/* never closed"), ["/* never closed"]);
+    }
+
+    #[test]
+    fn unknown_languages_have_no_ranges() {
+        assert!(ranges(Path::new("notes.txt"), "// hi").is_none());
+        assert!(ranges(Path::new("Makefile"), "# hi").is_some());
+    }
+}
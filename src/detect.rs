@@ -0,0 +1,121 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+/// A `--detect-cmd`: a program deciding which files are text, in place of
+/// MIME sniffing. It is run with the file's path as its last argument, and
+/// exiting with 0 means the file is text.
+pub struct DetectCmd {
+    program: String,
+    args: Vec<String>,
+    /// What the program said about each file it was asked about, so that it
+    /// only runs once per file however many times the file is classified
+    /// (once for the walk, once more for `--binary-same-length`...).
+    cache: Mutex<HashMap<PathBuf, bool>>,
+    warned: AtomicBool,
+}
+
+impl DetectCmd {
+    /// `cmd` is the program, followed by any arguments to give it before
+    /// the path, separated by whitespace.
+    pub fn new(cmd: &str) -> anyhow::Result<Self> {
+        let mut words = cmd.split_whitespace().map(str::to_string);
+        let Some(program) = words.next() else {
+            anyhow::bail!("--detect-cmd needs a program to run.");
+        };
+        Ok(DetectCmd {
+            program,
+            args: words.collect(),
+            cache: Mutex::new(HashMap::new()),
+            warned: AtomicBool::new(false),
+        })
+    }
+
+    /// Whether the program says the file at `path` is text, or `None` (after
+    /// warning, the first time) if it could not be run at all.
+    pub fn is_text(&self, path: &Path) -> Option<bool> {
+        if let Some(&text) = self.cache.lock().unwrap().get(path) {
+            return Some(text);
+        }
+        let status = Command::new(&self.program)
+            .args(&self.args)
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        match status {
+            Ok(status) => {
+                let text = status.success();
+                self.cache.lock().unwrap().insert(path.to_path_buf(), text);
+                Some(text)
+            }
+            Err(err) => {
+                if !self.warned.swap(true, Ordering::Relaxed) {
+                    println!(
+                        "Warning: could not run the --detect-cmd {:?} ({err}), so files are told apart by their MIME type instead.",
+                        self.program
+                    );
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use super::*;
+    use crate::testutil::TempDir;
+
+    /// A `--detect-cmd` that logs each path it is asked about to `log`, and
+    /// says only `.txt` files are text.
+    fn logging_detector(dir: &TempDir) -> (DetectCmd, PathBuf) {
+        let log = dir.path().join("log");
+        let script = dir.write(
+            "detect.sh",
+            format!(
+                "#!/bin/sh\necho \"$2\" >> {}\ncase \"$2\" in *.txt) exit 0;; *) exit 1;; esac\n",
+                log.display()
+            ),
+        );
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755))
+            .unwrap();
+        let cmd = format!("{} --path", script.display());
+        (DetectCmd::new(&cmd).unwrap(), log)
+    }
+
+    #[test]
+    fn exiting_with_zero_means_text() {
+        let dir = TempDir::new();
+        let (detect, _) = logging_detector(&dir);
+        assert_eq!(detect.is_text(Path::new("notes.txt")), Some(true));
+        assert_eq!(detect.is_text(Path::new("logo.png")), Some(false));
+    }
+
+    #[test]
+    fn each_file_is_only_asked_about_once() {
+        let dir = TempDir::new();
+        let (detect, log) = logging_detector(&dir);
+        for _ in 0..3 {
+            assert_eq!(detect.is_text(Path::new("a.txt")), Some(true));
+            assert_eq!(detect.is_text(Path::new("b.bin")), Some(false));
+        }
+        assert_eq!(fs::read_to_string(log).unwrap(), "a.txt\nb.bin\n");
+    }
+
+    #[test]
+    fn a_program_that_cannot_be_run_decides_nothing() {
+        let detect = DetectCmd::new("/nonexistent/detect --flag").unwrap();
+        assert_eq!(detect.is_text(Path::new("a.txt")), None);
+        assert!(DetectCmd::new("  ").is_err());
+    }
+}
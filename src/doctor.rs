@@ -0,0 +1,138 @@
+use std::{
+    fs,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+use xshell::{cmd, Shell};
+
+use crate::{forge, git, manifest};
+
+struct Check {
+    ok: bool,
+    /// Failing an optional check is worth knowing about, but not fatal.
+    required: bool,
+    label: String,
+    /// What to do about it, shown for failed checks.
+    hint: &'static str,
+}
+
+/// Whether a file can be created (and removed again) in `dir`.
+fn can_write_in(dir: &Path) -> bool {
+    let probe = dir.join(".pmv-doctor-probe");
+    let ok = fs::write(&probe, b"").is_ok();
+    let _ = fs::remove_file(&probe);
+    ok
+}
+
+fn succeeds(cmd: xshell::Cmd<'_>) -> bool {
+    cmd.quiet().ignore_stdout().ignore_stderr().run().is_ok()
+}
+
+fn checks(sh: &Shell, dir: &Path) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let has_git = succeeds(cmd!(sh, "git --version"));
+    checks.push(Check {
+        ok: has_git,
+        label: "git is installed".to_string(),
+        required: true,
+        hint: "install git and make sure it is on your PATH",
+    });
+    let has_gh = succeeds(cmd!(sh, "gh --version"));
+    checks.push(Check {
+        ok: has_gh,
+        label: "the GitHub CLI (gh) is installed".to_string(),
+        required: true,
+        hint: "see https://cli.github.com for installation",
+    });
+    if has_gh {
+        checks.push(Check {
+            ok: succeeds(cmd!(sh, "gh auth status")),
+            label: "gh is logged in to GitHub".to_string(),
+            required: true,
+            hint: "run `gh auth login`",
+        });
+    }
+
+    checks.push(Check {
+        ok: can_write_in(dir),
+        label: format!("{} is writable", dir.display()),
+        required: true,
+        hint: "file contents cannot be rewritten without it",
+    });
+    if let Some(parent) = dir.parent() {
+        checks.push(Check {
+            ok: can_write_in(parent),
+            label: format!("{} is writable", parent.display()),
+            required: true,
+            hint: "the project directory cannot be renamed without it",
+        });
+    }
+
+    if let (Some(package), Some(name)) = (
+        manifest::detect_project_name(dir),
+        dir.file_name().and_then(|name| name.to_str()),
+    ) {
+        checks.push(Check {
+            ok: package == name,
+            label: format!("the manifest names the project {name:?}"),
+            required: false,
+            hint: "it names it differently; --from-manifest renames that name instead",
+        });
+    }
+
+    if has_git {
+        let in_repo = git::toplevel(sh, dir).is_some();
+        checks.push(Check {
+            ok: in_repo,
+            label: format!("{} is in a git repository", dir.display()),
+            required: false,
+            hint: "only needed for --stage and --remote",
+        });
+        if let Some(name) = dir.file_name().and_then(|name| name.to_str()) {
+            let env_forge = forge::env_forge().ok().flatten();
+            checks.push(Check {
+                ok: forge::origin_forge(sh, dir, name, env_forge).is_some(),
+                label: "the origin remote's repository will be renamed"
+                    .to_string(),
+                required: false,
+                hint: "without it, pass --remote to pick the remote to rename",
+            });
+        }
+    }
+    checks
+}
+
+/// Checks for the tools and permissions a rename of `dir` will need, and
+/// prints the results as a checklist.
+pub fn doctor(sh: &Shell, dir: PathBuf) -> anyhow::Result<()> {
+    let color = std::io::stdout().is_terminal();
+    let paint = |check: &Check| {
+        let (mark, code) = match (check.ok, check.required) {
+            (true, _) => ("✓", "32"),
+            (false, true) => ("✗", "31"),
+            (false, false) => ("!", "33"),
+        };
+        if color {
+            format!("\x1b[{code}m{mark}\x1b[0m")
+        } else {
+            mark.to_string()
+        }
+    };
+    let checks = checks(sh, &dir);
+    for check in &checks {
+        println!("{} {}", paint(check), check.label);
+        if !check.ok {
+            println!("    {}", check.hint);
+        }
+    }
+    let failed = checks
+        .iter()
+        .filter(|check| check.required && !check.ok)
+        .count();
+    if failed > 0 {
+        bail!("{failed} check(s) failed.");
+    }
+    Ok(())
+}
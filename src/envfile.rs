@@ -0,0 +1,349 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    filter::WalkFilter, manifest::changed_lines, replace::Replacer,
+    report::Reporter, Rewrite,
+};
+
+/// Characters that would change what a value means if a replacement added
+/// them, by how the value is quoted: `None` is for unquoted values.
+fn special_chars(quote: Option<char>) -> &'static [char] {
+    match quote {
+        Some('\'') => &['\''],
+        Some('"') => &['"', '\\', '$', '`'],
+        Some(_) => &['`', '\\', '$'],
+        None => &[' ', '\t', '#', '\'', '"', '`', '\\', '$'],
+    }
+}
+
+/// Whether `name` is a `.env` file: `.env` itself, or one of its variants
+/// such as `.env.local` or `.env.example` (but not, say, direnv's `.envrc`,
+/// which is a shell script).
+fn is_env_file(name: &str) -> bool {
+    name == ".env" || name.starts_with(".env.")
+}
+
+/// Whether `key` can name an environment variable.
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// The `.env` files in the project `filter` walks that it lets through.
+/// Being dotfiles (and often ignored by git too), they are never found by
+/// the walk itself, so they are looked for in each directory it would
+/// visit.
+fn env_files(filter: &WalkFilter) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let dirs = filter
+        .walker()
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_some_and(|ft| ft.is_dir()));
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir.path()) else {
+            continue;
+        };
+        files.extend(
+            entries
+                .filter_map(Result::ok)
+                .filter(|entry| {
+                    entry.file_name().to_str().is_some_and(is_env_file)
+                        && entry.file_type().is_ok_and(|ft| ft.is_file())
+                })
+                .map(|entry| entry.path())
+                .filter(|path| filter.allows(path) && !filter.is_done(path)),
+        );
+    }
+    files.sort();
+    files
+}
+
+/// Where the quoted value starting at `start` in `text` (just after its
+/// opening `quote`) ends, if it does. Values may run over several lines.
+fn closing_quote(text: &str, start: usize, quote: char) -> Option<usize> {
+    let mut chars = text[start..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == quote {
+            return Some(start + i);
+        } else if c == '\\' && quote != '\'' {
+            chars.next();
+        }
+    }
+    None
+}
+
+/// Where an unquoted value starting at `start` in `line` ends: at an inline
+/// comment if there is one, with any whitespace before that left out.
+fn unquoted_end(line: &str, start: usize) -> usize {
+    let bytes = line.as_bytes();
+    let end = (start..line.len())
+        .find(|&i| {
+            bytes[i] == b'#' && i > start && bytes[i - 1].is_ascii_whitespace()
+        })
+        .unwrap_or(line.len());
+    start + line[start..end].trim_end().len()
+}
+
+/// Rewrites one `.env` file's `text`, line by line.
+struct EnvRewriter<'a> {
+    path: &'a Path,
+    replacer: &'a Replacer,
+    reporter: &'a mut Reporter,
+    out: String,
+    count: usize,
+}
+
+impl EnvRewriter<'_> {
+    /// Warns that the old name was left in the `what` on line `n` (1-based)
+    /// of the file, because of `why`.
+    fn warn(&mut self, n: usize, what: &str, why: &str) -> io::Result<()> {
+        self.reporter.warn(format_args!(
+            "Warning: the old name was not replaced in the {what} on line {n} of {}, as {why}.",
+            self.path.display()
+        ))
+    }
+
+    /// Replaces the old name in the value `value`, quoted with `quote`,
+    /// unless that would add a character that is special there.
+    fn value(
+        &mut self,
+        n: usize,
+        value: &str,
+        quote: Option<char>,
+    ) -> io::Result<()> {
+        let (replaced, count) = self.replacer.replace(value);
+        let specials = special_chars(quote);
+        let added = specials
+            .iter()
+            .filter(|&&c| {
+                replaced.matches(c).count() > value.matches(c).count()
+            })
+            .collect::<String>();
+        if count == 0 || added.is_empty() {
+            self.out.push_str(&replaced);
+            self.count += count;
+        } else {
+            self.warn(
+                n,
+                "value",
+                &format!(
+                    "that would add {added:?}, which would change its meaning"
+                ),
+            )?;
+            self.out.push_str(value);
+        }
+        Ok(())
+    }
+
+    fn rewrite(mut self, text: &str) -> io::Result<(String, usize)> {
+        let (mut pos, mut n) = (0, 1);
+        while pos < text.len() {
+            let end =
+                text[pos..].find('\n').map_or(text.len(), |i| pos + i + 1);
+            let line = &text[pos..end];
+            let body = line.trim_end_matches(['\n', '\r']);
+            let trimmed = body.trim_start();
+            let key_start = match trimmed.strip_prefix("export") {
+                Some(rest) if rest.starts_with([' ', '\t']) => {
+                    body.len() - rest.trim_start().len()
+                }
+                _ => body.len() - trimmed.len(),
+            };
+            let assignment = body.find('=').filter(|&eq| {
+                eq >= key_start && is_valid_key(body[key_start..eq].trim_end())
+            });
+            let Some(eq) = assignment else {
+                // Blank lines and comments are kept as they are; anything
+                // else that is not an assignment is replaced in as text.
+                if trimmed.is_empty() || trimmed.starts_with('#') {
+                    self.out.push_str(line);
+                } else {
+                    let (replaced, count) = self.replacer.replace(line);
+                    self.out.push_str(&replaced);
+                    self.count += count;
+                }
+                (pos, n) = (end, n + 1);
+                continue;
+            };
+            let key = body[key_start..eq].trim_end();
+            self.out.push_str(&body[..key_start]);
+            let (new_key, count) = self.replacer.replace(key);
+            if count > 0 && !is_valid_key(&new_key) {
+                self.warn(
+                    n,
+                    "key",
+                    &format!("{new_key:?} cannot name an environment variable"),
+                )?;
+                self.out.push_str(key);
+            } else {
+                self.out.push_str(&new_key);
+                self.count += count;
+            }
+            let value_start = eq
+                + 1
+                + (body[eq + 1..].len() - body[eq + 1..].trim_start().len());
+            self.out.push_str(&body[key_start + key.len()..value_start]);
+            let quote = body[value_start..]
+                .chars()
+                .next()
+                .filter(|c| ['\'', '"', '`'].contains(c));
+            let rest_start = match quote {
+                Some(quote) => {
+                    let open = pos + value_start + 1;
+                    let Some(close) = closing_quote(text, open, quote) else {
+                        // An unterminated value: leave the rest of the file
+                        // alone rather than guess where it ends.
+                        self.out.push_str(&text[pos + value_start..]);
+                        break;
+                    };
+                    self.out.push(quote);
+                    self.value(n, &text[open..close], Some(quote))?;
+                    self.out.push(quote);
+                    n += text[open..close].matches('\n').count();
+                    close + 1
+                }
+                None => {
+                    let value_end = unquoted_end(body, value_start);
+                    self.value(n, &body[value_start..value_end], None)?;
+                    pos + value_end
+                }
+            };
+            // Whatever follows the value (such as a comment) is kept as it
+            // is.
+            let end = text[rest_start..]
+                .find('\n')
+                .map_or(text.len(), |i| rest_start + i + 1);
+            self.out.push_str(&text[rest_start..end]);
+            (pos, n) = (end, n + 1);
+        }
+        Ok((self.out, self.count))
+    }
+}
+
+/// Plans rewrites of the `.env` files in the project at `root`, parsing
+/// their `KEY=VALUE` lines so that the old name is replaced in keys only
+/// where the result is still a valid name, and in values only where it does
+/// not break their quoting; comments and blank lines are left alone. Any
+/// rewrite of such a file already in `rewrites` is replaced.
+pub fn rewrite_env_files(
+    root: &Path,
+    replacer: &Replacer,
+    filter: &WalkFilter,
+    rewrites: &mut Vec<Rewrite>,
+    reporter: &mut Reporter,
+) -> anyhow::Result<()> {
+    for fp in env_files(filter) {
+        let path = fp.strip_prefix(root).unwrap_or(&fp).to_path_buf();
+        let Ok(original) = String::from_utf8(fs::read(&fp)?) else {
+            continue;
+        };
+        let (contents, count) = EnvRewriter {
+            path: &fp,
+            replacer: replacer.for_path(&path),
+            reporter,
+            out: String::with_capacity(original.len()),
+            count: 0,
+        }
+        .rewrite(&original)?;
+        rewrites.retain(|rw| rw.path != path);
+        if count > 0 {
+            rewrites.push(Rewrite {
+                lines: changed_lines(&original, &contents),
+                path,
+                contents,
+                count,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replace::Mode;
+
+    /// `text` as an `.env` file, with `from` replaced by `to`, and the number
+    /// of replacements made.
+    fn rewrite(text: &str, from: &str, to: &str) -> (String, usize) {
+        let replacer = Replacer::new(from, to, Mode::Literal).unwrap();
+        EnvRewriter {
+            path: Path::new(".env"),
+            replacer: &replacer,
+            reporter: &mut Reporter::quiet(),
+            out: String::new(),
+            count: 0,
+        }
+        .rewrite(text)
+        .unwrap()
+    }
+
+    #[test]
+    fn keys_and_values_are_replaced_but_comments_are_not() {
+        let text = "# old settings\n\
+                    old_DIR=/srv/old # old comment\n\
+                    export old_URL = 'https://old.example'\n\
+                    \n\
+                    NAME=\"old\"\r\n";
+        assert_eq!(
+            rewrite(text, "old", "new"),
+            (
+                "# old settings\n\
+                 new_DIR=/srv/new # old comment\n\
+                 export new_URL = 'https://new.example'\n\
+                 \n\
+                 NAME=\"new\"\r\n"
+                    .to_string(),
+                5
+            )
+        );
+    }
+
+    #[test]
+    fn multiline_values_are_replaced_as_one() {
+        let text = "DESC=\"old one\nold \\\" two\"  # old\nNEXT=old";
+        assert_eq!(
+            rewrite(text, "old", "new"),
+            (
+                "DESC=\"new one\nnew \\\" two\"  # old\nNEXT=new".to_string(),
+                3
+            )
+        );
+    }
+
+    #[test]
+    fn an_unterminated_value_leaves_the_rest_alone() {
+        let text = "A=old\nB=\"old\nC=old\n";
+        assert_eq!(
+            rewrite(text, "old", "new"),
+            ("A=new\nB=\"old\nC=old\n".to_string(), 1)
+        );
+    }
+
+    #[test]
+    fn replacements_that_would_change_a_meaning_are_refused() {
+        let text = "A=old\nB=\"old\"\nC='old'\nold_KEY=x\n";
+        assert_eq!(
+            rewrite(text, "old", "new name"),
+            (
+                "A=old\nB=\"new name\"\nC='new name'\nold_KEY=x\n".to_string(),
+                2
+            )
+        );
+        assert_eq!(
+            rewrite(text, "old", "$new"),
+            ("A=old\nB=\"old\"\nC='$new'\nold_KEY=x\n".to_string(), 1)
+        );
+        assert_eq!(
+            rewrite(text, "old", "it's"),
+            ("A=old\nB=\"it's\"\nC='old'\nold_KEY=x\n".to_string(), 1)
+        );
+    }
+}
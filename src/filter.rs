@@ -0,0 +1,253 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+
+use crate::audit::Stamp;
+
+/// `pmv`'s own configuration files, which are left alone unless asked for.
+const OWN_FILES: &[&str] = &[".pmv.toml", ".pmvignore"];
+
+/// Version control metadata, which rewriting (or renaming) anything in could
+/// corrupt the repository. Being dot-directories, they are never walked
+/// unless asked for (see [`WalkFilter::extra_roots`]).
+const VCS_DIRS: &[&str] = &[".git", ".hg", ".svn", ".jj"];
+
+/// Decides which of the files found by the walk are candidates for
+/// rewriting, before their contents are even looked at.
+pub struct WalkFilter {
+    root: PathBuf,
+    /// If set, only files matching one of these globs are rewritten.
+    only: Option<GlobSet>,
+    /// If set, only these files (relative to `root`) are rewritten.
+    changed: Option<HashSet<PathBuf>>,
+    include_own_files: bool,
+    include_vcs: bool,
+    /// Other directories to walk, besides the root.
+    also_walk: Vec<PathBuf>,
+    /// Files an earlier run already rewrote, as they were left then.
+    done: HashMap<PathBuf, Stamp>,
+}
+
+/// Compiles `globs` into a single set; an empty list gives `None`.
+pub fn build_globset(globs: &[String]) -> anyhow::Result<Option<GlobSet>> {
+    if globs.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(
+            Glob::new(glob)
+                .with_context(|| format!("...while parsing glob {glob:?}"))?,
+        );
+    }
+    Ok(Some(builder.build()?))
+}
+
+impl WalkFilter {
+    pub fn new(root: &Path, only: &[String]) -> anyhow::Result<Self> {
+        Ok(WalkFilter {
+            root: root.to_path_buf(),
+            only: build_globset(only)?,
+            changed: None,
+            include_own_files: false,
+            include_vcs: false,
+            also_walk: Vec::new(),
+            done: HashMap::new(),
+        })
+    }
+
+    /// Restricts the walk to `changed`, given relative to the root.
+    pub fn changed(self, changed: Vec<PathBuf>) -> Self {
+        WalkFilter {
+            changed: Some(changed.into_iter().collect()),
+            ..self
+        }
+    }
+
+    /// Lets `pmv`'s own configuration files be rewritten too.
+    pub fn include_own_files(self) -> Self {
+        WalkFilter {
+            include_own_files: true,
+            ..self
+        }
+    }
+
+    /// Walks `dirs` as well as the root.
+    pub fn also_walk(self, dirs: Vec<PathBuf>) -> Self {
+        WalkFilter {
+            also_walk: dirs,
+            ..self
+        }
+    }
+
+    /// Skips rewriting the files in `done` (by absolute path) that are still
+    /// as an earlier run left them, for `--resume`.
+    pub fn resume(self, done: HashMap<PathBuf, Stamp>) -> Self {
+        WalkFilter { done, ..self }
+    }
+
+    /// Whether the file at `path` was already rewritten by an earlier run,
+    /// and has not changed since. Such files are still renamed, just not
+    /// looked into again.
+    pub fn is_done(&self, path: &Path) -> bool {
+        self.done
+            .get(path)
+            .is_some_and(|stamp| Stamp::of(path) == Some(*stamp))
+    }
+
+    /// How many of the files an earlier run rewrote are still as it left
+    /// them.
+    pub fn n_done(&self) -> usize {
+        self.done.keys().filter(|path| self.is_done(path)).count()
+    }
+
+    /// Lets files inside version control directories through, which are
+    /// otherwise skipped whatever the ignore rules say.
+    pub fn include_vcs(self) -> Self {
+        WalkFilter {
+            include_vcs: true,
+            ..self
+        }
+    }
+
+    /// The walk over the project and [`WalkFilter::extra_roots`], with the
+    /// ignore rules applied and hidden files and directories skipped. Every
+    /// walk `pmv` makes starts here, which is what keeps it out of dotfiles:
+    /// `pmv`'s own files and version control directories included, which
+    /// are only ever walked when added back by [`WalkFilter::extra_files`]
+    /// and [`WalkFilter::extra_roots`]. Besides `.gitignore` and `.ignore`
+    /// files, `.pmvignore` files (in the same syntax) keep paths out of it.
+    pub fn walker(&self) -> WalkBuilder {
+        let mut walker = WalkBuilder::new(&self.root);
+        walker.hidden(true).add_custom_ignore_filename(".pmvignore");
+        for extra in self.extra_roots() {
+            walker.add(extra);
+        }
+        walker
+    }
+
+    /// Directories to walk besides the root. Hidden like all dot-directories,
+    /// version control directories are only walked if they were asked for.
+    pub fn extra_roots(&self) -> Vec<PathBuf> {
+        let mut roots = self.also_walk.clone();
+        if self.include_vcs {
+            roots.extend(
+                VCS_DIRS
+                    .iter()
+                    .map(|name| self.root.join(name))
+                    .filter(|path| path.is_dir()),
+            );
+        }
+        roots
+    }
+
+    /// Files the walk would not find on its own, but which should be
+    /// considered anyway: being dotfiles, `pmv`'s own files are skipped
+    /// along with all other hidden files unless they were asked for.
+    pub fn extra_files(&self) -> Vec<PathBuf> {
+        if !self.include_own_files {
+            return Vec::new();
+        }
+        OWN_FILES
+            .iter()
+            .map(|name| self.root.join(name))
+            .filter(|path| path.is_file() && self.allows(path))
+            .collect()
+    }
+
+    /// Globs are matched against the path relative to the project root.
+    /// Hidden files never get this far (see [`WalkFilter::walker`]).
+    pub fn allows(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        self.only
+            .as_ref()
+            .is_none_or(|only| only.is_match(relative))
+            && self
+                .changed
+                .as_ref()
+                .is_none_or(|changed| changed.contains(relative))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    /// The files `filter` would hand on to be rewritten, relative to `dir`.
+    fn walked(dir: &TempDir, filter: &WalkFilter) -> Vec<String> {
+        let walked = filter
+            .walker()
+            .build()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.file_type().is_some_and(|ft| ft.is_file()))
+            .map(|entry| entry.into_path())
+            .chain(filter.extra_files())
+            .filter(|path| filter.allows(path));
+        let mut walked: Vec<_> = walked
+            .map(|path| {
+                let relative = path.strip_prefix(dir.path()).unwrap();
+                relative.to_string_lossy().into_owned()
+            })
+            .collect();
+        walked.sort();
+        walked
+    }
+
+    fn project() -> TempDir {
+        let dir = TempDir::new();
+        dir.write("src/main.rs", "");
+        dir.write(".hidden", "");
+        dir.write(".config/settings", "");
+        dir.write(".pmv.toml", "");
+        dir.write(".pmvignore", "");
+        dir.write(".gitmodules", "[submodule \"old\"]\n");
+        dir.write(".git/config", "");
+        dir.write(".hg/store/data", "");
+        dir
+    }
+
+    #[test]
+    fn dotfiles_and_vcs_dirs_are_not_walked() {
+        let dir = project();
+        let filter = WalkFilter::new(dir.path(), &[]).unwrap();
+        assert_eq!(walked(&dir, &filter), ["src/main.rs"]);
+    }
+
+    #[test]
+    fn own_files_are_walked_when_asked_for() {
+        let dir = project();
+        let filter = WalkFilter::new(dir.path(), &[])
+            .unwrap()
+            .include_own_files();
+        assert_eq!(
+            walked(&dir, &filter),
+            [".pmv.toml", ".pmvignore", "src/main.rs"]
+        );
+    }
+
+    #[test]
+    fn vcs_dirs_are_walked_when_asked_for() {
+        let dir = project();
+        let filter = WalkFilter::new(dir.path(), &[]).unwrap().include_vcs();
+        assert_eq!(
+            walked(&dir, &filter),
+            [".git/config", ".hg/store/data", "src/main.rs"]
+        );
+    }
+
+    #[test]
+    fn pmvignore_keeps_paths_out_of_the_walk() {
+        let dir = project();
+        dir.write(".pmvignore", "/vendor/\n*.snap\n");
+        dir.write("vendor/lib.rs", "");
+        dir.write("src/tests/output.snap", "");
+        let filter = WalkFilter::new(dir.path(), &[]).unwrap();
+        assert_eq!(walked(&dir, &filter), ["src/main.rs"]);
+    }
+}
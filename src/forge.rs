@@ -0,0 +1,508 @@
+use std::{fmt, path::Path, str::FromStr};
+
+use anyhow::{bail, Context};
+use xshell::{cmd, Shell};
+
+/// Where the repository is hosted, which decides the CLI used to rename it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Forge {
+    /// GitHub, through `gh`.
+    Github,
+    /// Gitea or Forgejo, through `tea`.
+    Gitea,
+}
+
+impl FromStr for Forge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "github" => Ok(Forge::Github),
+            "gitea" | "forgejo" => Ok(Forge::Gitea),
+            _ => Err(format!(
+                "{s:?} is not a supported forge (expected github or gitea)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Forge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Forge::Github => "GitHub",
+            Forge::Gitea => "Gitea",
+        })
+    }
+}
+
+impl Forge {
+    /// Guesses the forge from the host a remote points at. Self-hosted Gitea
+    /// instances can only be recognized by name (e.g. `gitea.example.com`),
+    /// so anything else needs `--forge`.
+    pub fn detect(host: &str) -> Option<Forge> {
+        let host = host.to_lowercase();
+        if host == "github.com" {
+            Some(Forge::Github)
+        } else if ["gitea", "forgejo", "codeberg"]
+            .iter()
+            .any(|name| host.contains(name))
+        {
+            Some(Forge::Gitea)
+        } else {
+            None
+        }
+    }
+
+    /// The command renaming the repository of `remote` (or, without one, of
+    /// the current directory) to `new`, with `extra_args` appended.
+    pub fn rename_command(
+        self,
+        new: &str,
+        remote: Option<&Remote>,
+        extra_args: &[String],
+    ) -> (&'static str, Vec<String>) {
+        let repo_args = remote
+            .map(|r| {
+                vec!["--repo".to_string(), format!("{}/{}", r.owner, r.repo)]
+            })
+            .unwrap_or_default();
+        let (program, mut args) = match self {
+            Forge::Github => {
+                let mut args = vec!["repo".into(), "rename".into(), new.into()];
+                args.extend(repo_args);
+                args.push("--yes".into());
+                ("gh", args)
+            }
+            Forge::Gitea => {
+                let mut args = vec!["repos".into(), "edit".into()];
+                args.extend(repo_args);
+                args.extend(["--name".into(), new.into()]);
+                ("tea", args)
+            }
+        };
+        args.extend_from_slice(extra_args);
+        (program, args)
+    }
+}
+
+/// The environment variable naming the forge to fall back on, when it is
+/// neither given with `--forge` nor detected from the remote.
+const FORGE_VAR: &str = "PMV_FORGE";
+
+/// The environment variable holding default arguments for the forge's CLI,
+/// quoted as a POSIX shell would: see [`split_args`].
+const ARGS_VAR: &str = "PMV_GH_ARGS";
+
+/// The forge set in `PMV_FORGE`, if any.
+pub fn env_forge() -> anyhow::Result<Option<Forge>> {
+    match std::env::var(FORGE_VAR) {
+        Ok(forge) if !forge.trim().is_empty() => forge
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|err: String| anyhow::anyhow!("{FORGE_VAR}: {err}")),
+        _ => Ok(None),
+    }
+}
+
+/// The arguments set in `PMV_GH_ARGS`, to be passed on to the forge's CLI
+/// ahead of any `--forge-arg`.
+pub fn env_args() -> anyhow::Result<Vec<String>> {
+    match std::env::var(ARGS_VAR) {
+        Ok(args) => split_args(&args)
+            .with_context(|| format!("...while reading {ARGS_VAR}")),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+/// Splits `line` into arguments the way a POSIX shell does (without
+/// expanding anything): at unquoted whitespace, with single quotes keeping
+/// everything between them as it is, double quotes keeping everything but
+/// a backslash before `"`, `\`, `$` or `` ` ``, and a backslash outside
+/// quotes keeping the character after it.
+fn split_args(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut args = Vec::new();
+    // The argument being read, if one has started: `''` starts an empty
+    // one.
+    let mut arg: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => args.extend(arg.take()),
+            '\\' => {
+                let arg = arg.get_or_insert_with(String::new);
+                arg.extend(chars.next());
+            }
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => {
+                            bail!("Found a single quote that is never closed.")
+                        }
+                    }
+                }
+            }
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => arg.push(c),
+                            Some(c) => arg.extend(['\\', c]),
+                            None => bail!(
+                                "Found a double quote that is never closed."
+                            ),
+                        },
+                        Some(c) => arg.push(c),
+                        None => {
+                            bail!("Found a double quote that is never closed.")
+                        }
+                    }
+                }
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+/// A git remote whose repository is the one to rename.
+#[derive(Clone)]
+pub struct Remote {
+    pub name: String,
+    pub url: String,
+    pub host: String,
+    pub owner: String,
+    pub repo: String,
+}
+
+/// Extracts `(host, owner, repo)` from the https, `git@host:` and `ssh://`
+/// forms of a remote URL.
+pub fn parse_remote_url(url: &str) -> Option<(String, String, String)> {
+    let (host, path) = if let Some((_, rest)) = url.split_once("://") {
+        let (authority, path) = rest.split_once('/')?;
+        let host = authority.rsplit('@').next()?;
+        (host.split(':').next()?, path)
+    } else {
+        let (authority, path) = url.split_once(':')?;
+        (authority.rsplit('@').next()?, path)
+    };
+    let path = path.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    let (owner, repo) = path.split_once('/')?;
+    (!host.is_empty()
+        && !owner.is_empty()
+        && !repo.is_empty()
+        && !repo.contains('/'))
+    .then(|| (host.to_string(), owner.to_string(), repo.to_string()))
+}
+
+impl Remote {
+    /// Looks up the URL of the remote called `name` in the repository at
+    /// `dir`.
+    pub fn resolve(sh: &Shell, dir: &Path, name: &str) -> anyhow::Result<Self> {
+        let _guard = sh.push_dir(dir);
+        let url = cmd!(sh, "git remote get-url {name}")
+            .quiet()
+            .read()
+            .with_context(|| {
+                format!("...while looking up git remote {name:?}")
+            })?;
+        let Some((host, owner, repo)) = parse_remote_url(&url) else {
+            bail!("Could not make out which repository remote {name:?} ({url}) points at.");
+        };
+        Ok(Remote {
+            name: name.to_string(),
+            url,
+            host,
+            owner,
+            repo,
+        })
+    }
+
+    /// Points the local remote at the renamed repository.
+    fn update_url(&self, sh: &Shell, new: &str) -> anyhow::Result<()> {
+        match self.renamed_url(new) {
+            Some(new_url) => set_remote_url(sh, &self.name, &new_url),
+            None => Ok(()),
+        }
+    }
+
+    /// The remote's URL, once its repository has been renamed to `new`.
+    pub fn renamed_url(&self, new: &str) -> Option<String> {
+        let at = self.url.rfind(&self.repo)?;
+        Some(format!(
+            "{}{new}{}",
+            &self.url[..at],
+            &self.url[at + self.repo.len()..]
+        ))
+    }
+}
+
+pub fn set_remote_url(
+    sh: &Shell,
+    name: &str,
+    new_url: &str,
+) -> anyhow::Result<()> {
+    cmd!(sh, "git remote set-url {name} {new_url}")
+        .run()
+        .with_context(|| format!("...while updating git remote {name:?}"))
+}
+
+/// Asks `gh` (from `dir`) which repository it is about to rename, and makes
+/// sure it is the one `remote` (or else `origin`, if there is one) points
+/// at, returning it as `owner/name`. Only GitHub can be asked this; for
+/// other forges nothing is checked.
+pub fn check_target(
+    sh: &Shell,
+    dir: &Path,
+    forge: Forge,
+    remote: Option<&Remote>,
+) -> anyhow::Result<Option<String>> {
+    if forge != Forge::Github {
+        return Ok(None);
+    }
+    let _guard = sh.push_dir(dir);
+    let repo_args = remote
+        .map(|r| vec![format!("{}/{}", r.owner, r.repo)])
+        .unwrap_or_default();
+    let jq = r#".owner.login + "/" + .name"#;
+    let target =
+        cmd!(sh, "gh repo view {repo_args...} --json owner,name --jq {jq}")
+            .quiet()
+            .read()
+            .with_context(|| {
+                "...while asking gh which repository it would rename (nothing was renamed)"
+            })?;
+    let origin;
+    let expected = match remote {
+        Some(remote) => Some(remote),
+        None => {
+            origin = Remote::resolve(sh, dir, "origin").ok();
+            origin.as_ref()
+        }
+    };
+    if let Some(expected) = expected {
+        let expected_name = format!("{}/{}", expected.owner, expected.repo);
+        if !target.eq_ignore_ascii_case(&expected_name) {
+            bail!(
+                "gh would rename {target}, but remote {:?} points at {expected_name}; not renaming anything on GitHub.",
+                expected.name
+            );
+        }
+    }
+    Ok(Some(target))
+}
+
+/// For a dry run: checks, without renaming anything, whether renaming the
+/// repository (see [`check_target`]) to `new` would go through, and reports
+/// what would be done, or what stands in the way. Only GitHub can be checked:
+/// `gh` has to be logged in, the repository has to exist, and `new` must not
+/// be taken already by another repository of the same owner.
+pub fn precheck(
+    sh: &Shell,
+    dir: &Path,
+    forge: Forge,
+    remote: Option<&Remote>,
+    new: &str,
+) {
+    if forge != Forge::Github {
+        println!("Would rename the {forge} repository to {new} (not checked).");
+        return;
+    }
+    let blocked = |reason: &str| {
+        println!("Cannot rename the {forge} repository to {new}: {reason}");
+    };
+    let _guard = sh.push_dir(dir);
+    if cmd!(sh, "gh auth status")
+        .quiet()
+        .ignore_stdout()
+        .ignore_stderr()
+        .run()
+        .is_err()
+    {
+        return blocked("gh is not logged in (see `gh auth status`).");
+    }
+    let target = match check_target(sh, dir, forge, remote) {
+        Ok(Some(target)) => target,
+        Ok(None) => {
+            return blocked("it is unknown which repository gh would rename.")
+        }
+        Err(err) => return blocked(&format!("{:#}", err)),
+    };
+    let (owner, old) = target.split_once('/').unwrap_or(("", &target));
+    let renamed = format!("{owner}/{new}");
+    // GitHub's names are case-insensitive: a change of case finds the
+    // repository itself.
+    if !old.eq_ignore_ascii_case(new)
+        && cmd!(sh, "gh repo view {renamed} --json name")
+            .quiet()
+            .ignore_stdout()
+            .ignore_stderr()
+            .run()
+            .is_ok()
+    {
+        return blocked(&format!("{renamed} already exists."));
+    }
+    println!("Would rename the {forge} repository {target} to {renamed} (OK).");
+}
+
+/// The forge to rename the repository of the project at `dir` on, when no
+/// remote or forge was asked for: only if its `origin` remote points at a
+/// repository named `old_name` (in any case, on any owner), on a host that
+/// [`Forge::detect`] recognizes or else on `env_forge`.
+pub fn origin_forge(
+    sh: &Shell,
+    dir: &Path,
+    old_name: &str,
+    env_forge: Option<Forge>,
+) -> Option<Forge> {
+    let _guard = sh.push_dir(dir);
+    let url = cmd!(sh, "git remote get-url origin")
+        .quiet()
+        .ignore_stderr()
+        .read()
+        .ok()?;
+    let (host, _, repo) = parse_remote_url(&url)?;
+    if !repo.eq_ignore_ascii_case(old_name) {
+        return None;
+    }
+    Forge::detect(&host).or(env_forge)
+}
+
+/// Renames the repository of the project in the shell's current directory on
+/// `forge`: that of `remote` if given, otherwise whichever the forge's CLI
+/// picks. `extra_args` are passed on to the CLI as they are. With `quiet`, the
+/// CLI's own output is captured and only shown if it fails; on success just
+/// the repository's new URL is reported. Returns whether the rename went
+/// through.
+pub fn rename_repo(
+    sh: &Shell,
+    forge: Forge,
+    new: &str,
+    remote: Option<&Remote>,
+    extra_args: &[String],
+    quiet: bool,
+) -> bool {
+    let (program, args) = forge.rename_command(new, remote, extra_args);
+    let update_remote = || {
+        if let Some(remote) = remote {
+            if let Err(err) = remote.update_url(sh, new) {
+                println!(
+                    "Error updating git remote {:?}: {err:#}",
+                    remote.name
+                );
+            }
+        }
+    };
+    if !quiet {
+        return match cmd!(sh, "{program} {args...}").run() {
+            Ok(()) => {
+                update_remote();
+                true
+            }
+            Err(err) => {
+                println!("Error renaming the {forge} repository: {err}");
+                false
+            }
+        };
+    }
+    match cmd!(sh, "{program} {args...}")
+        .quiet()
+        .ignore_status()
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            update_remote();
+            // Where the renamed repository is known to be, ask `gh` about
+            // that one rather than whichever it would pick; failing that,
+            // make do with what the remote says, or just the new name.
+            let known = remote.map(|r| {
+                (
+                    format!("{}/{new}", r.owner),
+                    format!("https://{}/{}/{new}", r.host, r.owner),
+                )
+            });
+            let url = match (forge, known) {
+                (Forge::Github, Some((repo, web_url))) => {
+                    cmd!(sh, "gh repo view --repo {repo} --json url --jq .url")
+                        .quiet()
+                        .ignore_stderr()
+                        .read()
+                        .unwrap_or(web_url)
+                }
+                (Forge::Github, None) => {
+                    cmd!(sh, "gh repo view --json url --jq .url")
+                        .quiet()
+                        .ignore_stderr()
+                        .read()
+                        .unwrap_or_else(|_| new.to_string())
+                }
+                (Forge::Gitea, Some((_, web_url))) => web_url,
+                (Forge::Gitea, None) => new.to_string(),
+            };
+            println!("Renamed {forge} repository: {url}");
+            true
+        }
+        Ok(output) => {
+            println!(
+                "Error renaming the {forge} repository: `{program}` {}",
+                output.status
+            );
+            for captured in [&output.stdout, &output.stderr] {
+                let captured = String::from_utf8_lossy(captured);
+                if !captured.trim().is_empty() {
+                    println!("{}", captured.trim_end());
+                }
+            }
+            false
+        }
+        Err(err) => {
+            println!("Error renaming the {forge} repository: {err}");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(line: &str) -> Vec<String> {
+        split_args(line).unwrap()
+    }
+
+    #[test]
+    fn args_are_split_at_whitespace() {
+        assert_eq!(split("  --a  b\t-c\n"), ["--a", "b", "-c"]);
+        assert!(split(" ").is_empty());
+    }
+
+    #[test]
+    fn quotes_keep_args_together() {
+        assert_eq!(
+            split(r#"--description 'a new name' --homepage "x y""#),
+            ["--description", "a new name", "--homepage", "x y"]
+        );
+        assert_eq!(split(r#"a'b c'"d e"f"#), ["ab cd ef"]);
+        assert_eq!(split("'' \"\""), ["", ""]);
+    }
+
+    #[test]
+    fn backslashes_escape_as_in_a_shell() {
+        assert_eq!(split(r"a\ b \'c"), ["a b", "'c"]);
+        assert_eq!(split(r#""\"\\\$ \n""#), [r#""\$ \n"#]);
+        assert_eq!(split(r"'\n'"), [r"\n"]);
+    }
+
+    #[test]
+    fn unclosed_quotes_are_errors() {
+        assert!(split_args("'a").is_err());
+        assert!(split_args(r#""a\""#).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use xshell::{cmd, Shell};
+
+/// The top level of the git work tree containing `dir`, if any.
+pub fn toplevel(sh: &Shell, dir: &Path) -> Option<PathBuf> {
+    let _guard = sh.push_dir(dir);
+    cmd!(sh, "git rev-parse --show-toplevel")
+        .quiet()
+        .ignore_stderr()
+        .read()
+        .ok()
+        .map(PathBuf::from)
+}
+
+/// Files under `dir` that differ from `rev`, relative to `dir`. Untracked
+/// files are not included.
+pub fn changed_since(
+    sh: &Shell,
+    dir: &Path,
+    rev: &str,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let _guard = sh.push_dir(dir);
+    let changed = cmd!(sh, "git diff --name-only --relative {rev} --")
+        .quiet()
+        .read()
+        .with_context(|| {
+            format!("...while listing files changed since {rev}")
+        })?;
+    Ok(changed.lines().map(PathBuf::from).collect())
+}
+
+/// Stages exactly the files `pmv` rewrote or renamed to (given relative to
+/// `new_path`), and the removal of those it renamed away from, leaving
+/// anything else in the work tree as it is. If the project is tracked by an
+/// enclosing repository rather than being one itself, and was moved there
+/// from `old_path`, its tracked files are staged as moved too.
+pub fn stage_changes(
+    sh: &Shell,
+    old_path: &Path,
+    new_path: &Path,
+    changed: &[PathBuf],
+    removed: &[PathBuf],
+) -> anyhow::Result<()> {
+    let Some(top) = toplevel(sh, new_path) else {
+        println!(
+            "Not staging anything: {} is not inside a git repository.",
+            new_path.display()
+        );
+        return Ok(());
+    };
+    let top = top.canonicalize().unwrap_or(top);
+    let Some(new_rel) = relative_to(&top, new_path) else {
+        println!(
+            "Not staging anything: {} is not inside {}.",
+            new_path.display(),
+            top.display()
+        );
+        return Ok(());
+    };
+    let _guard = sh.push_dir(&top);
+    let mut removed: Vec<PathBuf> =
+        removed.iter().map(|path| new_rel.join(path)).collect();
+    let mut changed: Vec<PathBuf> =
+        changed.iter().map(|path| new_rel.join(path)).collect();
+    // Moved within the enclosing repository: what git tracked where the
+    // project was is now where it is, less what was renamed inside it.
+    let old_rel = relative_to(&top, old_path).filter(|old| *old != new_rel);
+    if let Some(old_rel) = old_rel.filter(|old| !old.as_os_str().is_empty()) {
+        let tracked = cmd!(sh, "git ls-files -z -- {old_rel}")
+            .quiet()
+            .read()
+            .with_context(|| "...while listing the moved files")?;
+        for path in tracked.split('\0').filter(|path| !path.is_empty()) {
+            let path = Path::new(path);
+            let Ok(inner) = path.strip_prefix(&old_rel) else {
+                continue;
+            };
+            let moved = new_rel.join(inner);
+            if top.join(&moved).exists() {
+                changed.push(moved);
+            }
+            removed.push(path.to_path_buf());
+        }
+    }
+    changed.sort();
+    changed.dedup();
+    if !removed.is_empty() {
+        cmd!(sh, "git rm -r -q --cached --ignore-unmatch -- {removed...}")
+            .run()
+            .with_context(|| "...while staging the renamed files")?;
+    }
+    if !changed.is_empty() {
+        cmd!(sh, "git add -- {changed...}")
+            .run()
+            .with_context(|| "...while staging the rewritten files")?;
+    }
+    Ok(())
+}
+
+/// `path` relative to `top` (both taken canonically, so that symlinks and
+/// `..` do not get in the way), if it is inside it. `path` itself need not
+/// exist any more, as long as its parent does.
+fn relative_to(top: &Path, path: &Path) -> Option<PathBuf> {
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => {
+            let parent = path.parent()?.canonicalize().ok()?;
+            parent.join(path.file_name()?)
+        }
+    };
+    path.strip_prefix(top).ok().map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testutil::TempDir;
+
+    /// A repository in `dir` with everything in it committed.
+    fn repo(dir: &TempDir) -> Shell {
+        let sh = Shell::new().unwrap();
+        sh.change_dir(dir.path());
+        cmd!(sh, "git init -q").run().unwrap();
+        cmd!(sh, "git add -A").run().unwrap();
+        cmd!(sh, "git -c user.name=pmv -c user.email=pmv@localhost commit -q -m init")
+            .run()
+            .unwrap();
+        sh
+    }
+
+    fn staged(sh: &Shell) -> Vec<String> {
+        let staged =
+            cmd!(sh, "git diff --cached --name-status").read().unwrap();
+        staged.lines().map(|line| line.replace('\t', " ")).collect()
+    }
+
+    #[test]
+    fn only_the_changes_made_are_staged() {
+        let dir = TempDir::new();
+        dir.write("project/old-name.txt", "old-name\n");
+        dir.write("project/other.txt", "other\n");
+        dir.write("unrelated.txt", "unrelated\n");
+        let sh = repo(&dir);
+        let project = dir.path().join("project");
+        dir.write("unrelated.txt", "edited by hand\n");
+        dir.write("project/other.txt", "edited by hand\n");
+        fs::rename(project.join("old-name.txt"), project.join("new-name.txt"))
+            .unwrap();
+        stage_changes(
+            &sh,
+            &project,
+            &project,
+            &[PathBuf::from("new-name.txt")],
+            &[PathBuf::from("old-name.txt")],
+        )
+        .unwrap();
+        assert_eq!(
+            staged(&sh),
+            ["R100 project/old-name.txt project/new-name.txt"]
+        );
+    }
+
+    #[test]
+    fn a_moved_project_is_staged_as_moved() {
+        let dir = TempDir::new();
+        dir.write("old-name/lib.rs", "old-name\n");
+        dir.write("old-name/README", "about\n");
+        let sh = repo(&dir);
+        dir.write("old-name/untracked.txt", "scratch\n");
+        let (old, new) =
+            (dir.path().join("old-name"), dir.path().join("new-name"));
+        fs::rename(&old, &new).unwrap();
+        dir.write("new-name/lib.rs", "new-name\n");
+        stage_changes(&sh, &old, &new, &[PathBuf::from("lib.rs")], &[])
+            .unwrap();
+        assert_eq!(
+            staged(&sh),
+            [
+                "R100 old-name/README new-name/README",
+                "A new-name/lib.rs",
+                "D old-name/lib.rs"
+            ]
+        );
+    }
+}
@@ -0,0 +1,4156 @@
+//! `pmv` renames a project: its directory, the name in its files, and its
+//! repository. Besides [`run`], which is the whole command line tool, the
+//! steps it is built from that are useful on their own are exposed here.
+
+mod archive;
+mod audit;
+mod autotune;
+mod binaries;
+mod comments;
+mod detect;
+mod doctor;
+mod envfile;
+mod filter;
+mod forge;
+mod git;
+mod manifest;
+mod names;
+mod plan;
+mod pool;
+mod priority;
+mod rebrand;
+mod relocate;
+mod renames;
+mod replace;
+mod report;
+#[cfg(feature = "review")]
+mod review;
+mod rollback;
+mod submodules;
+mod tags;
+mod templates;
+#[cfg(test)]
+mod testutil;
+
+use std::{
+    any::Any,
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fs, io,
+    path::{Component, Path, PathBuf},
+    process,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, available_parallelism},
+    time::Instant,
+};
+
+use anyhow::{anyhow, bail, Context};
+use audit::AuditLog;
+use bpaf::Bpaf;
+use crossbeam_channel::{Receiver, Sender};
+use detect::DetectCmd;
+use encoding_rs::Encoding;
+use filter::WalkFilter;
+use forge::Forge;
+use ignore::{DirEntry, WalkState};
+use names::DeriveName;
+use pool::{run_parallel, Semaphore};
+use relocate::Strategy;
+use replace::{mode, Replacer, Substitution};
+use report::{confirm, DiffStat, Format, Reporter, Timeout, Timings};
+use tree_magic_mini::from_filepath;
+use xshell::Shell;
+
+pub use manifest::detect_project_name;
+pub use replace::Mode;
+
+// bpaf docs: https://docs.rs/bpaf/latest/bpaf/index.html
+// xshell docs: https://docs.rs/xshell/latest/xshell/index.html
+
+#[derive(Debug, Clone)]
+enum InputDir {
+    Absolute(PathBuf),
+    Relative(PathBuf),
+}
+
+impl FromStr for InputDir {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<InputDir> {
+        let path = PathBuf::from_str(s).with_context(|| {
+            "...while converting input argument into a PathBuf"
+        })?;
+        if path.is_absolute() {
+            Ok(InputDir::Absolute(path))
+        } else if path.is_relative() {
+            Ok(InputDir::Relative(path))
+        } else {
+            bail!(
+                "Input {} is not an absolute or relative path.",
+                path.display()
+            )
+        }
+    }
+}
+
+/// Taken as a path rather than parsed from a string, since the project's
+/// directory name need not be valid UTF-8.
+impl From<PathBuf> for InputDir {
+    fn from(path: PathBuf) -> Self {
+        if path.is_absolute() {
+            InputDir::Absolute(path)
+        } else {
+            InputDir::Relative(path)
+        }
+    }
+}
+
+impl InputDir {
+    /// The path made absolute against `cwd`. Collecting the components drops
+    /// any trailing slash, which would otherwise make `symlink_metadata`
+    /// look through a link.
+    fn absolute(&self, cwd: &Path) -> PathBuf {
+        match self {
+            InputDir::Absolute(p) => p.components().collect(),
+            InputDir::Relative(p) => cwd.join(p).components().collect(),
+        }
+    }
+
+    /// Resolves a path that has to exist already, such as the project's.
+    fn canonicalize_existing(&self, cwd: &Path) -> anyhow::Result<Directory> {
+        let given = self.absolute(cwd);
+        let symlink = fs::symlink_metadata(&given)
+            .is_ok_and(|meta| meta.file_type().is_symlink())
+            .then(|| given.clone());
+        let path = given
+            .canonicalize()
+            .map_err(|err| canonicalize_error(&given, err))
+            .with_context(|| {
+                format!("...failed to canonicalize input path {:?}", self)
+            })?;
+        let name = path
+            .iter()
+            .next_back()
+            .with_context(|| {
+                format!("Input path {} is empty!", path.display())
+            })?
+            .into();
+        if path.exists() && !path.is_dir() {
+            Err(anyhow!(
+                "Path {} already exists, but it is not a directory.",
+                path.display()
+            ))
+        } else {
+            Ok(Directory {
+                path,
+                name,
+                symlink,
+            })
+        }
+    }
+
+    /// Resolves a path that need not exist yet, such as a destination: the
+    /// longest part of it that does exist is canonicalized, and any `.` and
+    /// `..` in the rest are resolved lexically. The last component is kept
+    /// as given, so that on a case-insensitive filesystem a destination
+    /// differing from an existing entry only in case keeps its new case.
+    fn resolve_intended(&self, cwd: &Path) -> PathBuf {
+        let given = self.absolute(cwd);
+        let mut resolved = PathBuf::new();
+        let mut components = given.components().peekable();
+        while let Some(component) = components.next() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                component => {
+                    resolved.push(component);
+                    if components.peek().is_none() {
+                        break;
+                    }
+                    if let Ok(canonical) = resolved.canonicalize() {
+                        resolved = canonical;
+                    }
+                }
+            }
+        }
+        resolved
+    }
+}
+
+/// The directory next to `path` whose name is most similar to it, if any is
+/// close enough to plausibly be what was meant.
+fn nearby_directory(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let parent = path.parent()?;
+    let max_distance = (name.chars().count() / 3).max(1);
+    fs::read_dir(parent)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let candidate = entry.file_name().into_string().ok()?;
+            let distance = names::edit_distance(name, &candidate);
+            (distance <= max_distance).then(|| (distance, entry.path()))
+        })
+        .min()
+        .map(|(_, path)| path)
+}
+
+/// Turns a failure to canonicalize the project path into a message that says
+/// what to do about it.
+fn canonicalize_error(path: &Path, err: io::Error) -> anyhow::Error {
+    let guidance = match err.kind() {
+        io::ErrorKind::NotFound => {
+            let missing = path
+                .ancestors()
+                .take_while(|p| !p.exists())
+                .last()
+                .unwrap_or(path);
+            let mut msg = format!(
+                "the project path does not exist ({} is missing)",
+                missing.display()
+            );
+            if let Some(suggestion) = nearby_directory(missing) {
+                msg.push_str(&format!(
+                    " — did you mean {}?",
+                    suggestion.display()
+                ));
+            }
+            msg
+        }
+        io::ErrorKind::PermissionDenied => format!(
+            "permission denied while resolving {}: check that you can read and enter every directory along the path",
+            path.display()
+        ),
+        io::ErrorKind::NotADirectory => format!(
+            "a component of {} is a file, not a directory",
+            path.display()
+        ),
+        _ => return err.into(),
+    };
+    anyhow::Error::new(err).context(guidance)
+}
+
+struct Directory {
+    path: PathBuf,
+    name: OsString,
+    /// Set when the project path was given as a symlink to `path`: this is
+    /// the link itself, which has to be repointed once `path` moves.
+    symlink: Option<PathBuf>,
+}
+
+/// The name of the project's directory as text. A name that is not valid
+/// UTF-8 can still be used, if it is valid in the (non-UTF-8) `encoding` the
+/// project's files are in.
+/// Whether the interrupted `run` being resumed had already moved the
+/// project, which is now at `dir`: it has to be either where the run found
+/// it or where it moved it to.
+fn already_moved(run: &audit::Run, dir: &Path) -> anyhow::Result<bool> {
+    let is_dir = |path: &Path| {
+        path == dir || path.canonicalize().is_ok_and(|path| path == dir)
+    };
+    match &run.moved_to {
+        Some(to) if is_dir(to) => Ok(true),
+        None if is_dir(&run.dir) => Ok(false),
+        Some(to) => bail!(
+            "The run being resumed moved the project to {}, not {}.",
+            to.display(),
+            dir.display()
+        ),
+        None => bail!(
+            "The run being resumed started with the project in {}, not {}.",
+            run.dir.display(),
+            dir.display()
+        ),
+    }
+}
+
+fn dir_name(
+    existing: &Directory,
+    encoding: Option<&'static Encoding>,
+) -> anyhow::Result<String> {
+    if let Some(name) = existing.name.to_str() {
+        return Ok(name.to_string());
+    }
+    #[cfg(unix)]
+    if let Some(encoding) = encoding {
+        use std::os::unix::ffi::OsStrExt;
+        let bytes = existing.name.as_bytes();
+        if let Some(name) =
+            encoding.decode_without_bom_handling_and_without_replacement(bytes)
+        {
+            return Ok(name.into_owned());
+        }
+    }
+    bail!(
+        "The name of {} ({:?}) is not valid UTF-8{}, so it cannot be matched in the project's files; pass --encoding with the encoding the name is in.",
+        existing.path.display(),
+        existing.name,
+        encoding.map_or(String::new(), |encoding| format!(
+            " or {}",
+            encoding.name()
+        ))
+    );
+}
+
+fn create_parent(path: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| {
+            format!("Failed to create directory {}.", parent.display())
+        })?;
+    }
+    Ok(())
+}
+
+/// Sets aside those of `renames` (under `root`) that move one of
+/// `submodules`, which have to be done with `git mv` instead (see
+/// [`submodules::apply`]). Those are returned first.
+fn split_submodule_renames(
+    renames: Vec<renames::PathRename>,
+    submodules: &[submodules::Submodule],
+    root: &Path,
+) -> (Vec<renames::PathRename>, Vec<renames::PathRename>) {
+    renames
+        .into_iter()
+        .partition(|rn| submodules::moves_submodule(submodules, root, &rn.from))
+}
+
+/// Moves (or, with [`Strategy::Copy`], copies) the project to `new_path`
+/// (creating its parents, with `parents`), and repoints the symlink it was
+/// given as, if any.
+fn move_project(
+    existing: &Directory,
+    new_path: &Path,
+    parents: bool,
+    strategy: Strategy,
+    deref_symlinks: bool,
+    trash: bool,
+    yes: bool,
+) -> anyhow::Result<()> {
+    if parents {
+        create_parent(new_path)?;
+    }
+    match strategy {
+        _ if relocate::is_same_entry(&existing.path, new_path) => {
+            relocate::rename_case(&existing.path, new_path)?
+        }
+        Strategy::Rename => relocate::move_dir(
+            &existing.path,
+            new_path,
+            deref_symlinks,
+            trash,
+            yes,
+        )?,
+        Strategy::Copy => {
+            relocate::copy_dir(&existing.path, new_path, deref_symlinks)?
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(link) = &existing.symlink {
+        repoint_symlink(link, new_path)?;
+    }
+    Ok(())
+}
+
+/// The root of the project containing `dir`, for `--find-root` (see
+/// [`manifest::find_root`]), which is `dir` itself if it has no such root.
+fn find_project_root(dir: Directory, cwd: &Path) -> anyhow::Result<Directory> {
+    match manifest::find_root(&dir.path) {
+        Some(root) if root == dir.path => {
+            println!("Using {} as the project root.", dir.path.display());
+            Ok(dir)
+        }
+        Some(root) => {
+            println!(
+                "Using {} as the project root, the nearest directory above {} with a manifest or .git.",
+                root.display(),
+                dir.path.display()
+            );
+            InputDir::from(root.to_path_buf()).canonicalize_existing(cwd)
+        }
+        None => {
+            println!(
+                "Found no manifest or .git at or above {}, using it as the project root.",
+                dir.path.display()
+            );
+            Ok(dir)
+        }
+    }
+}
+
+/// Points `link` at the project's new location. A relative link target keeps
+/// being relative, with only its last component swapped for the new name.
+#[cfg(unix)]
+fn repoint_symlink(link: &Path, new_path: &Path) -> anyhow::Result<()> {
+    let old_target = fs::read_link(link).with_context(|| {
+        format!("...while reading the symlink {}", link.display())
+    })?;
+    let new_target = match new_path.file_name() {
+        Some(new_name) if old_target.is_relative() => {
+            old_target.with_file_name(new_name)
+        }
+        _ => new_path.to_path_buf(),
+    };
+    fs::remove_file(link).with_context(|| {
+        format!("...while removing the old symlink {}", link.display())
+    })?;
+    std::os::unix::fs::symlink(&new_target, link).with_context(|| {
+        format!(
+            "Failed to point symlink {} at {}.",
+            link.display(),
+            new_target.display()
+        )
+    })?;
+    println!(
+        "Updated symlink {} -> {}.",
+        link.display(),
+        new_target.display()
+    );
+    Ok(())
+}
+
+impl AsRef<OsStr> for Directory {
+    fn as_ref(&self) -> &OsStr {
+        self.path.as_os_str()
+    }
+}
+
+// `--rename-branch OLD NEW`, taking both names at once.
+#[derive(Bpaf, Debug, Clone)]
+#[bpaf(adjacent)]
+struct RenameBranch {
+    /// Also replace references to the branch OLD with NEW in file contents,
+    /// wherever OLD is a complete identifier (see --identifier).
+    #[bpaf(long("rename-branch"))]
+    _flag: (),
+    #[bpaf(positional("OLD"))]
+    old: String,
+    #[bpaf(positional("NEW"))]
+    new: String,
+}
+
+/// Rename a project, and its GH repository if one exists.
+///
+/// Three names are involved, and each can be set on its own: the name of the
+/// project's directory (NEW_NAME, unless --no-rename-dir), the name replaced
+/// in file contents (the directory's current name, --from or --from-manifest,
+/// replaced by --to or else NEW_NAME), and the repository's name
+/// (--repo-name, or else NEW_NAME).
+#[derive(Bpaf, Debug, Clone)]
+struct Pmv {
+    #[bpaf(external(mode), fallback(Mode::Literal))]
+    mode: Mode,
+
+    /// When the old and new names are written in different styles
+    /// (`my-project` and `new_project`), only replace the old name as it is
+    /// written, rather than every way of writing it that the project uses
+    /// (`my_project`, `MyProject`, `MY_PROJECT`...) with the new name
+    /// written the same way.
+    no_auto_style: bool,
+
+    /// Match the old name in another mode (`literal`, `word`, `identifier`,
+    /// `all-cases` or `regex`, as the options of those names do) in the
+    /// files matching a glob, given as `GLOB:MODE`, or having an extension,
+    /// as `ext=EXT:MODE`: `--rule 'ext=rs:identifier'`. The names replaced
+    /// alongside the old one (in its other styles, or by --rename-branch)
+    /// are matched in that mode too. May be repeated; the first rule
+    /// matching a file applies.
+    #[bpaf(argument("RULE"))]
+    rule: Vec<replace::Rule>,
+
+    /// Regenerate the files matching GLOB (relative to the project) from
+    /// the TEMPLATE file, instead of replacing the old name in them:
+    /// `{old}`, `{new}` and `{owner}` (the owner of the --remote or `origin`
+    /// repository) in it are filled in. May be repeated; the first GLOB
+    /// matching a file applies.
+    #[bpaf(argument("GLOB=TEMPLATE"))]
+    template_file: Vec<templates::TemplateFile>,
+
+    /// Before writing anything, check that applying the replacement to its
+    /// own output changes nothing, and warn about files where it would.
+    idempotent: bool,
+
+    /// Turn warnings from pre-flight checks into errors, and abort on the
+    /// first file that cannot be read or written (by default such files are
+    /// all reported, and nothing is moved if any could not be read).
+    strict: bool,
+
+    /// Carry on with the rename even if some files could not be read,
+    /// leaving them as they are. The exit code is still 2 if any file
+    /// failed.
+    keep_going: bool,
+
+    /// Go ahead under --strict even though the old name is short or common
+    /// enough to match unintended text, and go ahead even though no file
+    /// in the project (nor any of their names) mentions the old name any
+    /// more, as after a rename that was already done.
+    force: bool,
+
+    /// Abort before touching anything if more than N replacements would be
+    /// made in total.
+    #[bpaf(argument("N"))]
+    max_total_replacements: Option<usize>,
+
+    /// Ask before rewriting any single file that would get more than N
+    /// replacements, even with --yes (which only turns the question into a
+    /// warning).
+    #[bpaf(argument("N"))]
+    replace_count_threshold: Option<usize>,
+
+    /// Rename the project containing the given directory rather than the
+    /// directory itself: the nearest directory at or above it with a
+    /// manifest (`Cargo.toml`, `package.json`, `pyproject.toml`) or a
+    /// `.git`. The root chosen is reported.
+    find_root: bool,
+
+    /// Use the given directory as the project, as it is, even if --find-root
+    /// was also given (say, by a shell alias).
+    no_parent_search: bool,
+
+    /// Create any missing parent directories of the destination.
+    parents: bool,
+
+    /// How to get the project to its new location: `rename` it and rewrite
+    /// it in place (the default), or `copy` it over, rewrite the copy, and
+    /// only remove the original once every file was rewritten.
+    #[bpaf(argument("STRATEGY"), fallback(Strategy::Rename))]
+    strategy: Strategy,
+
+    /// When the project is copied (with `--strategy copy`, or to another
+    /// filesystem), copy what its symlinks point at instead of recreating
+    /// them as symlinks.
+    deref_symlinks: bool,
+
+    /// When the destination is on another filesystem (so the project has
+    /// to be copied there), move the original to the trash afterwards,
+    /// instead of asking to delete it for good.
+    trash: bool,
+
+    /// `git add` exactly the files that were rewritten (and the move itself,
+    /// if an enclosing repository tracks the project).
+    stage: bool,
+
+    /// Work out and report everything that would be done, without changing
+    /// anything. Conflicting file or directory renames are all listed.
+    dry_run: bool,
+
+    /// With --dry-run, exit with 10 if anything would be changed, and with
+    /// 0 if nothing would, so that scripts can tell the two apart.
+    dry_run_exit_code: bool,
+
+    /// Once done, write every path that was moved (the project directory
+    /// included) to FILE, as tab-separated old and new paths.
+    #[bpaf(argument("FILE"))]
+    rename_map: Option<PathBuf>,
+
+    /// Only check whether anything still mentions the old name, exiting
+    /// with 5 (and listing what does) if so, or 0 if not; errors exit with
+    /// 1 as usual. Like --dry-run, nothing is changed.
+    check: bool,
+
+    /// Append a record of the names the run starts out with, then of every
+    /// change made (the move, each rewritten or renamed file, and the
+    /// repository rename), to FILE as it is made, one JSON object per line.
+    #[bpaf(argument("FILE"))]
+    audit_log: Option<PathBuf>,
+
+    /// Pick up where a run writing the audit log LOG (see --audit-log) left
+    /// off: the files it records as rewritten are not looked into again, as
+    /// long as their size and modification time are still as it left them.
+    /// The old name and what is replaced come from LOG. Give the project
+    /// where it is now, whether or not it was moved, and the same new name.
+    /// Unless --audit-log says otherwise, this run's changes are appended to
+    /// LOG too.
+    #[bpaf(argument("LOG"))]
+    resume: Option<PathBuf>,
+
+    /// Like --dry-run, but also write what would be done to FILE as JSON,
+    /// for `pmv apply` to carry out later. A plan records the move, the
+    /// rewrites of text files, the renames inside the project and the
+    /// repository rename; runs that would do anything else (rewrite
+    /// binaries, change submodules, move tags, --stage, --fmt and the like)
+    /// are refused.
+    #[bpaf(argument("FILE"))]
+    json_plan: Option<PathBuf>,
+
+    /// Move the project and rewrite its files without asking first. Needed
+    /// when stdin is not a terminal, as in scripts, where `pmv` otherwise
+    /// fails rather than ask.
+    #[bpaf(short, long)]
+    yes: bool,
+
+    /// Rename the GitHub repository without asking first. This is separate
+    /// from --yes, since the remote rename is the one step that cannot
+    /// simply be undone locally.
+    #[bpaf(long("confirm-remote"), long("yes-remote"))]
+    confirm_remote: bool,
+
+    /// Only rename the files and directories inside the project whose names
+    /// contain the old name: leave file contents, the project directory
+    /// itself and the GitHub repository alone.
+    rename_only_files: bool,
+
+    /// Only rename files and directories at most N levels below the project
+    /// directory (1 being its direct children) with --everywhere or
+    /// --rename-only-files. File contents are still rewritten at any depth.
+    #[bpaf(argument("N"))]
+    rename_max_depth: Option<usize>,
+
+    /// Replace the package name from the project's manifest (`Cargo.toml`,
+    /// `pyproject.toml` or `package.json`) in file contents, rather than the
+    /// directory's name.
+    from_manifest: bool,
+
+    /// Replace OLD in file contents (and, with --everywhere, in file
+    /// names), rather than the directory's current name. Does not affect
+    /// the directory or repository names.
+    #[bpaf(argument("OLD"))]
+    from: Option<String>,
+
+    /// Replace the old name in file contents (and, with --everywhere, in
+    /// file names) with NEW, rather than with NEW_NAME. Does not affect the
+    /// directory or repository names.
+    #[bpaf(argument("NEW"))]
+    to: Option<String>,
+
+    /// Leave the project's directory where it is, only rewriting its files
+    /// and renaming its repository.
+    no_rename_dir: bool,
+
+    /// Also set a field of a TOML or JSON file in the project to VALUE, such
+    /// as `Cargo.toml:package.repository=https://...`. Can be given more
+    /// than once; the field is added if it does not exist yet.
+    #[bpaf(argument("FILE:KEY=VALUE"))]
+    set: Vec<manifest::FieldSet>,
+
+    /// Parse the JSON files that mention the old name, and replace it only
+    /// in their keys and string values (writing them back out formatted as
+    /// `serde_json` does), rather than anywhere in their text. Files that
+    /// do not parse are rewritten as text. YAML files are not parsed, since
+    /// `pmv` has no YAML parser: they are still rewritten as text, with a
+    /// warning.
+    replace_json_keys: bool,
+
+    /// Parse the `.env` files in the project (`.env`, `.env.local` and so
+    /// on) as `KEY=VALUE` lines, replacing the old name in keys only where
+    /// the result can still name a variable, and in values only where it
+    /// does not break their quoting; comments and blank lines are left
+    /// alone. Being dotfiles, these are otherwise not rewritten at all.
+    env_aware: bool,
+
+    /// Only set the package name in the project's manifests (`Cargo.toml`,
+    /// `pyproject.toml`, `package.json`), rather than replacing the old name
+    /// in every file.
+    manifest_only: bool,
+
+    /// Run the project's formatter (`cargo fmt` or `prettier`) once its files
+    /// have been rewritten.
+    fmt: bool,
+
+    /// Rename the files and directories inside the project whose names
+    /// contain the old name too, on top of everything else: the full
+    /// rebrand in one go.
+    everywhere: bool,
+
+    /// Rename the repository this git remote points at (and update the
+    /// remote's URL), rather than the one `gh` would pick by default.
+    #[bpaf(argument("NAME"))]
+    remote: Option<String>,
+
+    /// Where the repository is hosted: `github` (renamed with `gh`) or
+    /// `gitea`, which also covers Forgejo (renamed with `tea`). Detected
+    /// from --remote's URL when possible, and otherwise taken from the
+    /// `PMV_FORGE` environment variable if set.
+    #[bpaf(argument("FORGE"))]
+    forge: Option<Forge>,
+
+    /// Rename the repository to NAME, rather than to NEW_NAME. Does not
+    /// affect the directory name or file contents.
+    #[bpaf(argument("NAME"))]
+    repo_name: Option<String>,
+
+    /// Lowercase the name the repository is renamed to.
+    lowercase_repo: bool,
+
+    /// Pass ARG on to the forge's CLI when renaming the repository, after
+    /// the arguments `pmv` gives it and those (quoted as in a shell) in the
+    /// `PMV_GH_ARGS` environment variable; may be repeated. Arguments
+    /// starting with `-` need the `--forge-arg=-R` form.
+    #[bpaf(argument("ARG"))]
+    forge_arg: Vec<String>,
+
+    /// Capture `gh`'s output, only showing it if the repository rename
+    /// fails.
+    quiet_gh: bool,
+
+    /// Exit successfully even if the repository rename failed, once the
+    /// error has been reported (by default the exit code is then 3).
+    ignore_gh_errors: bool,
+
+    /// If the repository rename fails, put the project back as it was: move
+    /// the directory back, undo the renames inside it, and restore the files
+    /// that were rewritten (whose original contents are kept in memory until
+    /// then). The repository is then renamed right after the files, before
+    /// --fmt, --stage or --rename-tags do anything. Not available with what
+    /// cannot be undone this way: --gh-async, --ignore-gh-errors,
+    /// `--strategy copy`, submodule changes, or moving a project tracked by
+    /// an enclosing repository.
+    rollback_on_gh_failure: bool,
+
+    /// Rename the repository in the background, while the project is
+    /// formatted and staged, rather than waiting on the network at the end.
+    /// Its outcome is still reported (and reflected in the exit code)
+    /// before `pmv` exits.
+    gh_async: bool,
+
+    /// Only replace the first occurrence of the old name in each file.
+    first_only: bool,
+
+    /// Only replace the old name inside comments (and Python docstrings),
+    /// leaving identifiers and strings in live code alone: a low-risk first
+    /// pass over the human-readable text. Only source files in a recognized
+    /// language (C-like, Rust, JavaScript, Python, shell, TOML, YAML, SQL,
+    /// HTML and the like) are rewritten; other files are left as they are.
+    comments_only: bool,
+
+    #[bpaf(external, optional)]
+    rename_branch: Option<RenameBranch>,
+
+    /// Also rename the local git tags whose names match the regex OLD,
+    /// replacing the match with NEW (sed-style, as in
+    /// `--rename-tags 's/old-name-/new-name-/'`). Every tag is listed, and
+    /// the renames confirmed, before any is touched.
+    #[bpaf(argument("s/OLD/NEW/"))]
+    rename_tags: Option<tags::TagRename>,
+
+    /// With --rename-tags, also push the renamed tags to the --remote (or
+    /// else `origin`), deleting the old ones there.
+    push_tags: bool,
+
+    /// Also rewrite links to the repository (https, git and SSH URLs, such
+    /// as `github.com/OWNER/OLD`) in file contents to point at its new
+    /// name. The repository is the one --remote (or else `origin`) points
+    /// at.
+    replace_urls: bool,
+
+    /// Also report files that were skipped for not being text.
+    #[bpaf(short, long)]
+    verbose: bool,
+
+    /// How to report on the files: `text` (the default), or `jsonl`, one
+    /// JSON object per line as each file is found, skipped, rewritten (or
+    /// planned, in a dry run) or fails, then a summary. Other messages are
+    /// still printed as text.
+    #[bpaf(argument("FORMAT"), fallback(Format::Text))]
+    format: Format,
+
+    /// List the line numbers each rewritten file was changed on.
+    show_lines: bool,
+
+    /// Break the files to rewrite, and their replacements, down by file
+    /// extension (also done with --verbose).
+    count_by_ext: bool,
+
+    /// Once the walk is done, list every file that was skipped for not
+    /// looking like text, with the MIME type it was detected as.
+    report_skips: bool,
+
+    /// Once the files have been scanned, list the text files that did not
+    /// mention the old name, and so were left as they were.
+    report_unchanged: bool,
+
+    /// Report each distinct error the walk ran into once, with how often it
+    /// came up (`(x4)`), instead of every time. They are then listed once
+    /// the walk is done.
+    dedupe_output: bool,
+
+    /// List the text files found once the walk is done, grouped under their
+    /// directories with how many each holds, instead of one by one as they
+    /// are found.
+    group_by_dir: bool,
+
+    /// Also rewrite large text files with very long lines, which are
+    /// otherwise skipped as most likely minified or generated.
+    scan_all: bool,
+
+    /// Also rewrite the files that look generated, which are otherwise
+    /// skipped (and listed): lockfiles (`Cargo.lock`, `package-lock.json`,
+    /// `yarn.lock`...), minified assets (`*.min.*`), and files that say near
+    /// their top that they were generated (`@generated`, `DO NOT EDIT`...).
+    #[bpaf(long("no-skip-generated"), flag(false, true))]
+    skip_generated: bool,
+
+    /// Also rewrite files of the MIME type MIME as text, on top of those
+    /// with "text" in their type and the common textual `application/*`
+    /// ones (JSON, XML, YAML, TOML, scripts, SVG...). `TYPE/*` allows a
+    /// whole type. Can be given more than once.
+    #[bpaf(argument("MIME"))]
+    mime_allow: Vec<String>,
+
+    /// Ask PROGRAM which files are text instead of going by their MIME type
+    /// (and name): it is run with each file's path as its last argument
+    /// (after any arguments given with it, as in `--detect-cmd 'file-is-text
+    /// --strict'`), and exiting with 0 means the file is text. Its answers
+    /// are cached for the run, so it is asked once per file, but that is
+    /// still a process per file: expect this to be much slower than MIME
+    /// sniffing on big projects, and narrow the walk down with --only or
+    /// `.pmvignore` where possible. If PROGRAM cannot be run at all, MIME
+    /// types are used.
+    #[bpaf(argument("PROGRAM"))]
+    detect_cmd: Option<String>,
+
+    /// Also replace the old name in binary files (those otherwise skipped
+    /// for not being text), byte for byte. Only allowed when the old and new
+    /// names are the same length in bytes, so that nothing in the files
+    /// moves. Binary files are reported separately from text ones.
+    binary_same_length: bool,
+
+    /// Report how long the walk, the move, the rewrite and the repository
+    /// rename each took.
+    #[bpaf(long("timings"))]
+    show_timings: bool,
+
+    /// Review the planned changes in a terminal UI, choosing which files to
+    /// rewrite, before anything is touched.
+    #[cfg(feature = "review")]
+    review: bool,
+
+    /// Number of threads for walking the project and rewriting files
+    /// (defaults to the number of available cores).
+    #[bpaf(argument("N"))]
+    threads: Option<usize>,
+
+    /// Maximum number of files read or written at the same time (defaults to
+    /// the number of threads, but at most 8).
+    #[bpaf(argument("N"))]
+    io_concurrency: Option<usize>,
+
+    /// Maximum number of files open at the same time, across the threads
+    /// sniffing, reading and writing them, so that a large parallel run
+    /// does not fail with "too many open files" (defaults to half the
+    /// process's soft limit, `ulimit -n`, leaving the rest for directories
+    /// being walked and the programs `pmv` runs).
+    #[bpaf(argument("N"))]
+    max_open_files: Option<usize>,
+
+    /// Pick --threads and --io-concurrency (unless given) from how long
+    /// reading the first few files of the project takes against sniffing
+    /// their types: fewer threads when reading is slow, as on network
+    /// mounts, and every core when sniffing is the bottleneck. The settings
+    /// chosen are printed as flags, to be passed explicitly next time.
+    concurrency_autotune: bool,
+
+    /// Give up once the run has taken longer than DURATION (such as `90s`
+    /// or `5m`): it is cancelled as with Ctrl-C, leaving no file
+    /// half-written, and exits with 4 after reporting what was done.
+    #[bpaf(argument("DURATION"))]
+    timeout: Option<Timeout>,
+
+    /// Write the rewritten files in batches of 64 per thread, first all of a
+    /// batch's temporary files and then all of its renames, instead of file
+    /// by file. On network filesystems (NFS, SMB), where each operation
+    /// waits on a round trip, this can make rewriting many small files
+    /// noticeably faster.
+    buffer_writes: bool,
+
+    /// Run at a low CPU priority and (on Linux) an idle IO priority, so that
+    /// a big rename does not starve other work on a shared machine.
+    nice: bool,
+
+    /// Read and write files in this encoding (e.g. `shift_jis`), instead of
+    /// UTF-8. Files that are not valid in it are reported and skipped.
+    #[bpaf(argument::<String>("LABEL"), parse(parse_encoding), optional)]
+    encoding: Option<&'static Encoding>,
+
+    /// Convert the line endings of the files that are rewritten anyway to
+    /// `lf` or `crlf`. Files without a match are never touched, and without
+    /// this every file keeps the line endings it had.
+    #[bpaf(argument("EOL"))]
+    normalize_eol: Option<Eol>,
+
+    /// Only rewrite files whose path (relative to the project) matches this
+    /// glob; may be repeated. Matching files must still be text files that
+    /// are not ignored by `.gitignore` and friends.
+    #[bpaf(argument("GLOB"))]
+    only: Vec<String>,
+
+    /// Rewrite files even if they resolve (through a symlink, say) to
+    /// somewhere outside the project, which are otherwise skipped.
+    allow_outside: bool,
+
+    /// Rewrite `pmv`'s own `.pmv.toml` and `.pmvignore` files (at the top of
+    /// the project) too, which are otherwise left alone.
+    include_self: bool,
+
+    /// Also rewrite files in the project's version control directories
+    /// (`.git`, `.hg`, `.svn`, `.jj`), which are otherwise skipped whatever
+    /// the ignore rules say. This can easily corrupt the repository.
+    include_vcs: bool,
+
+    /// Only rewrite files that differ from this git revision, leaving the
+    /// rest alone. Untracked files count as unchanged.
+    #[bpaf(argument("REV"))]
+    since: Option<String>,
+
+    #[bpaf(external(names::derive_name), optional)]
+    derive: Option<DeriveName>,
+
+    /// Print timing spans for the walk, classification and rewrite stages.
+    #[cfg(feature = "trace")]
+    trace: bool,
+
+    /// The project directory to rename.
+    #[bpaf(positional::<PathBuf>("PROJECT_PATH"), map(InputDir::from))]
+    existing: InputDir,
+
+    /// New project name, or a path (relative to the current directory) to
+    /// move the project to, whose last component is the new name. May be
+    /// left out when it is derived from the current one with --add-prefix
+    /// and friends.
+    #[bpaf(positional("NEW_NAME"), optional)]
+    new: Option<String>,
+}
+
+#[derive(Bpaf, Debug, Clone)]
+#[bpaf(options)]
+// Only ever built once, when parsing the command line.
+#[allow(clippy::large_enum_variant)]
+enum Cli {
+    /// Check that git, gh (and its login) and the permissions a rename needs
+    /// are all in place.
+    #[bpaf(command)]
+    Doctor {
+        /// Project to check (defaults to the current directory).
+        #[bpaf(
+            positional::<PathBuf>("PROJECT_PATH"),
+            map(InputDir::from),
+            optional
+        )]
+        existing: Option<InputDir>,
+    },
+    /// Carry out a plan written by --json-plan, exactly as it was planned.
+    #[bpaf(command)]
+    Apply {
+        /// Apply the plan even if files it rewrites or renames have changed
+        /// since it was made. By default it is then refused, as carrying it
+        /// out would undo those changes.
+        #[bpaf(long("no-verify-plan"), flag(false, true))]
+        verify_plan: bool,
+        /// The plan to carry out.
+        #[bpaf(argument("FILE"))]
+        plan: PathBuf,
+    },
+    /// Replace one name with another in the files of several projects at
+    /// once, without renaming any directory or repository.
+    #[bpaf(command)]
+    Rebrand {
+        /// A project directory to rewrite files in. Can be given more than
+        /// once; directories inside another one are only walked once.
+        #[bpaf(argument("DIR"))]
+        root: Vec<PathBuf>,
+        /// The name to replace.
+        #[bpaf(argument("OLD"))]
+        from: String,
+        /// What to replace it with.
+        #[bpaf(argument("NEW"))]
+        to: String,
+        /// Report the files that would be rewritten, without changing them.
+        dry_run: bool,
+        /// Rewrite the files without asking first.
+        #[bpaf(short, long)]
+        yes: bool,
+    },
+    /// Replace one name with another inside a `.tar`, `.tar.gz` or `.zip`
+    /// archive, in its text members and in the paths of all of them,
+    /// writing a new archive rather than extracting anything. Zip archives
+    /// need `bsdtar` (from libarchive).
+    #[bpaf(command)]
+    Archive {
+        /// The name to replace.
+        #[bpaf(argument("OLD"))]
+        from: String,
+        /// What to replace it with.
+        #[bpaf(argument("NEW"))]
+        to: String,
+        /// Where to write the new archive (by default next to the old one,
+        /// named as it is with OLD replaced by NEW).
+        #[bpaf(argument("FILE"))]
+        output: Option<PathBuf>,
+        /// Report the members that would be renamed or rewritten, without
+        /// writing anything.
+        dry_run: bool,
+        /// Write the new archive without asking first.
+        #[bpaf(short, long)]
+        yes: bool,
+        /// The archive to rewrite.
+        #[bpaf(positional("ARCHIVE"))]
+        archive: PathBuf,
+    },
+    Rename(#[bpaf(external(pmv))] Pmv),
+}
+
+fn is_file(dir_entry: &DirEntry) -> bool {
+    dir_entry.file_type().is_some_and(|fty| fty.is_file())
+}
+
+/// Extensionless files that are text, but which MIME sniffing tends to get
+/// wrong.
+const TEXT_FILE_NAMES: &[&str] = &[
+    "AUTHORS",
+    "CHANGELOG",
+    "CODEOWNERS",
+    "CONTRIBUTORS",
+    "COPYING",
+    "Containerfile",
+    "Dockerfile",
+    "Gemfile",
+    "GNUmakefile",
+    "Jenkinsfile",
+    "Justfile",
+    "LICENSE",
+    "Makefile",
+    "NOTICE",
+    "Procfile",
+    "README",
+    "Rakefile",
+    "Vagrantfile",
+    "justfile",
+    "makefile",
+];
+
+fn has_text_file_name(path: &Path) -> bool {
+    path.file_name()
+        .and_then(OsStr::to_str)
+        .is_some_and(|name| TEXT_FILE_NAMES.contains(&name))
+}
+
+/// MIME types without "text" in them that are text all the same. Types with
+/// a `+json` or `+xml` suffix (`application/ld+json`, `image/svg+xml`) are
+/// taken for text too.
+const TEXT_MIME_TYPES: &[&str] = &[
+    "application/ecmascript",
+    "application/graphql",
+    "application/javascript",
+    "application/json",
+    "application/sql",
+    "application/toml",
+    "application/x-awk",
+    "application/x-desktop",
+    "application/x-javascript",
+    "application/x-perl",
+    "application/x-php",
+    "application/x-ruby",
+    "application/x-sh",
+    "application/x-shellscript",
+    "application/x-toml",
+    "application/x-troff-man",
+    "application/x-yaml",
+    "application/xml",
+    "application/yaml",
+];
+
+/// Whether files of type `mime` are rewritten as text: those with "text" in
+/// their type, those in [`TEXT_MIME_TYPES`], and those `mime_allow` lists
+/// (by type, or as `TYPE/*`).
+fn is_text_mime(mime: &str, mime_allow: &[String]) -> bool {
+    mime.contains("text")
+        || TEXT_MIME_TYPES.contains(&mime)
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || mime_allow
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(kind) => {
+                    mime.split_once('/').is_some_and(|(k, _)| k == kind)
+                }
+                None => allowed == mime,
+            })
+}
+
+/// What the walk found out about a file.
+enum Found {
+    Text(PathBuf),
+    /// A file that was left alone, with the MIME type it was detected as.
+    NotText(PathBuf, &'static str),
+    /// A file with nothing in it to rewrite (its name may still matter).
+    Empty(PathBuf),
+    /// A large text file with very long lines, most likely a minified or
+    /// generated bundle that was not meant to be rewritten.
+    Minified(PathBuf),
+    /// A lockfile, or a file that says it was generated (see
+    /// [`looks_generated`]).
+    Generated(PathBuf),
+}
+
+/// Lockfiles, which are generated from the manifests next to them.
+const GENERATED_FILE_NAMES: &[&str] = &[
+    "Cargo.lock",
+    "Gemfile.lock",
+    "composer.lock",
+    "flake.lock",
+    "go.sum",
+    "package-lock.json",
+    "pnpm-lock.yaml",
+    "poetry.lock",
+    "uv.lock",
+    "yarn.lock",
+];
+
+/// What the first lines of a generated file typically say about it.
+const GENERATED_MARKERS: &[&str] = &[
+    "@generated",
+    "auto-generated",
+    "autogenerated",
+    "code generated",
+    "do not edit",
+    "generated by",
+];
+
+/// How much of the start of a file is searched for [`GENERATED_MARKERS`].
+const GENERATED_HEADER_SIZE: u64 = 1024;
+
+/// Whether the file at `path` is a lockfile, a minified asset (`*.min.*`),
+/// or starts by saying it was generated.
+fn looks_generated(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(OsStr::to_str) else {
+        return false;
+    };
+    if GENERATED_FILE_NAMES.contains(&name) || name.contains(".min.") {
+        return true;
+    }
+    let mut header = String::new();
+    let read = fs::File::open(path).and_then(|file| {
+        io::Read::read_to_string(
+            &mut io::Read::take(file, GENERATED_HEADER_SIZE),
+            &mut header,
+        )
+    });
+    // Leave reporting errors (and invalid UTF-8) to whoever reads it next.
+    read.is_ok() && {
+        let header = header.to_lowercase();
+        GENERATED_MARKERS
+            .iter()
+            .any(|marker| header.contains(marker))
+    }
+}
+
+/// Text files smaller than this are never taken for minified.
+const MINIFIED_MIN_SIZE: u64 = 64 * 1024;
+/// Average line length (in bytes) above which a large text file is taken
+/// for minified.
+const MINIFIED_MIN_LINE_LENGTH: u64 = 1000;
+
+/// Whether the large text file at `path` has lines long enough on average
+/// that it was most likely generated rather than written by hand.
+fn looks_minified(path: &Path, size: u64) -> bool {
+    if size < MINIFIED_MIN_SIZE {
+        return false;
+    }
+    let Ok(bytes) = fs::read(path) else {
+        // Leave reporting the error to whoever reads it next.
+        return false;
+    };
+    let lines = bytes.iter().filter(|&&byte| byte == b'\n').count() + 1;
+    size / lines as u64 > MINIFIED_MIN_LINE_LENGTH
+}
+
+#[cfg_attr(
+    feature = "trace",
+    tracing::instrument(
+        level = "trace",
+        skip_all,
+        fields(path = %path.display())
+    )
+)]
+fn classify_file(
+    path: PathBuf,
+    scan_all: bool,
+    skip_generated: bool,
+    mime_allow: &[String],
+    detect: Option<&DetectCmd>,
+) -> Found {
+    let size = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+    if size == 0 {
+        return Found::Empty(path);
+    }
+    match detect.and_then(|detect| detect.is_text(&path)) {
+        Some(true) => {}
+        Some(false) => return Found::NotText(path, "not text to --detect-cmd"),
+        None if has_text_file_name(&path) => {}
+        None => match from_filepath(&path) {
+            Some(mime) if is_text_mime(mime, mime_allow) => {}
+            mime => return Found::NotText(path, mime.unwrap_or("unknown")),
+        },
+    }
+    if skip_generated && looks_generated(&path) {
+        Found::Generated(path)
+    } else if !scan_all && looks_minified(&path, size) {
+        Found::Minified(path)
+    } else {
+        Found::Text(path)
+    }
+}
+
+/// How [`is_text_path`] tells text files apart, as the options of the same
+/// names do for `pmv`'s own walk.
+#[derive(Debug, Clone)]
+pub struct TextDetectOpts {
+    /// Take large files with very long lines for text too, rather than for
+    /// minified or generated bundles.
+    pub scan_all: bool,
+    /// Do not take lockfiles, minified assets (`*.min.*`) and files that say
+    /// they were generated for text.
+    pub skip_generated: bool,
+    /// Further MIME types to take for text, by type or as `TYPE/*`.
+    pub mime_allow: Vec<String>,
+}
+
+impl Default for TextDetectOpts {
+    /// As `pmv` runs without any of those options.
+    fn default() -> Self {
+        TextDetectOpts {
+            scan_all: false,
+            skip_generated: true,
+            mime_allow: Vec::new(),
+        }
+    }
+}
+
+/// Whether `pmv` would rewrite the file at `path` as text: judged by its
+/// name and then its MIME type, leaving out minified and (as `opts` says)
+/// generated files. Empty files, which have nothing to rewrite, are not
+/// taken for text.
+pub fn is_text_path(path: &Path, opts: &TextDetectOpts) -> bool {
+    let found = classify_file(
+        path.to_path_buf(),
+        opts.scan_all,
+        opts.skip_generated,
+        &opts.mime_allow,
+        None,
+    );
+    matches!(found, Found::Text(_))
+}
+
+/// Passes the files the walker finds on to be classified.
+fn send_candidate(
+    tx: &Sender<Result<PathBuf, ignore::Error>>,
+    filter: &WalkFilter,
+    cancelled: &AtomicBool,
+    result: Result<DirEntry, ignore::Error>,
+) -> WalkState {
+    if cancelled.load(Ordering::Relaxed) {
+        return WalkState::Quit;
+    }
+    let payload = match result {
+        Ok(dir_entry)
+            if is_file(&dir_entry)
+                && filter.allows(dir_entry.path())
+                && !filter.is_done(dir_entry.path()) =>
+        {
+            Ok(dir_entry.into_path())
+        }
+        Ok(_) => return WalkState::Continue,
+        Err(err) => Err(err),
+    };
+    // Hanging up means the collector has given up: stop walking.
+    if tx.send(payload).is_err() {
+        return WalkState::Quit;
+    }
+    WalkState::Continue
+}
+
+#[cfg_attr(feature = "trace", tracing::instrument(level = "debug", skip_all))]
+fn walk_files(
+    tx: Sender<Result<PathBuf, ignore::Error>>,
+    threads: usize,
+    filter: &WalkFilter,
+    cancelled: &AtomicBool,
+) {
+    for path in filter.extra_files() {
+        if tx.send(Ok(path)).is_err() {
+            return;
+        }
+    }
+    let mut walker = filter.walker();
+    if threads > 1 {
+        let walker = walker.threads(threads).build_parallel();
+        walker.run(|| {
+            let tx = tx.clone();
+            Box::new(move |result| {
+                send_candidate(&tx, filter, cancelled, result)
+            })
+        });
+    } else {
+        for result in walker.build() {
+            if let WalkState::Quit =
+                send_candidate(&tx, filter, cancelled, result)
+            {
+                break;
+            }
+        }
+    }
+}
+
+/// Classifies the files the walker found with `classify`, until it is done,
+/// the collector hangs up or the run is cancelled.
+fn sniff_files(
+    rx: Receiver<Result<PathBuf, ignore::Error>>,
+    tx: Sender<Result<Found, ignore::Error>>,
+    cancelled: Arc<AtomicBool>,
+    classify: impl Fn(PathBuf) -> Found,
+) {
+    for candidate in rx {
+        let found = candidate.map(&classify);
+        if cancelled.load(Ordering::Relaxed) || tx.send(found).is_err() {
+            return;
+        }
+    }
+}
+
+/// A planned change to a single file: its path relative to the project root,
+/// and the contents it should be rewritten with.
+struct Rewrite {
+    path: PathBuf,
+    contents: String,
+    count: usize,
+    /// The (1-based) lines the replacements were made on.
+    lines: Vec<usize>,
+}
+
+/// The 1-based line numbers of the byte offsets `starts` (which are in
+/// ascending order) in `text`, without repeats.
+fn line_numbers(text: &str, starts: &[usize]) -> Vec<usize> {
+    let mut lines = Vec::new();
+    let (mut line, mut last) = (1, 0);
+    for &start in starts {
+        line += text[last..start].matches('\n').count();
+        last = start;
+        if lines.last() != Some(&line) {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+fn format_lines(lines: &[usize]) -> String {
+    let lines: Vec<String> = lines.iter().map(usize::to_string).collect();
+    lines.join(", ")
+}
+
+/// Warns about (or, under `strict`, refuses) rewrites that would keep
+/// changing if `pmv` were run over its own output a second time.
+fn check_idempotent(
+    replacer: &Replacer,
+    rewrites: &[Rewrite],
+    strict: bool,
+) -> anyhow::Result<()> {
+    let unstable: Vec<&Path> = rewrites
+        .iter()
+        .filter(|rw| !replacer.for_path(&rw.path).is_fixed_point(&rw.contents))
+        .map(|rw| rw.path.as_path())
+        .collect();
+    if unstable.is_empty() {
+        return Ok(());
+    }
+    let listing = unstable
+        .iter()
+        .map(|fp| format!("  {}", fp.display()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let msg = format!(
+        "Re-applying the replacement to its own output would change {} file(s) again:\n{listing}",
+        unstable.len()
+    );
+    if strict {
+        bail!(msg);
+    }
+    println!("Warning: {msg}");
+    Ok(())
+}
+
+/// Reads a text file, decoding it as `encoding` (UTF-8 if not given).
+/// Returns `None` if the file is not valid in that encoding.
+fn read_text(
+    fp: &Path,
+    encoding: Option<&'static Encoding>,
+) -> anyhow::Result<Option<String>> {
+    let bytes = fs::read(fp).with_context(|| {
+        format!("Failed to open and read text file: {}", fp.display())
+    })?;
+    Ok(match encoding {
+        None => String::from_utf8(bytes).ok(),
+        Some(encoding) => encoding
+            .decode_without_bom_handling_and_without_replacement(&bytes)
+            .map(Cow::into_owned),
+    })
+}
+
+/// Encodes `text` as `encoding`, or returns `None` if some of it has no
+/// representation there (or `encoding_rs` cannot encode into it at all).
+fn encode_text(text: &str, encoding: &'static Encoding) -> Option<Vec<u8>> {
+    let (bytes, used, unmappable) = encoding.encode(text);
+    (used == encoding && !unmappable).then(|| bytes.into_owned())
+}
+
+fn parse_encoding(label: String) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| format!("{label:?} is not a known encoding label"))
+}
+
+/// The line endings for `--normalize-eol`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Eol {
+    Lf,
+    Crlf,
+}
+
+impl FromStr for Eol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lf" => Ok(Eol::Lf),
+            "crlf" => Ok(Eol::Crlf),
+            _ => {
+                Err(format!("{s:?} is not a line ending (expected lf or crlf)"))
+            }
+        }
+    }
+}
+
+impl Eol {
+    /// `contents` with every line ending (`\n` or `\r\n`) made this one.
+    fn normalize(self, contents: String) -> String {
+        let lf = if contents.contains("\r\n") {
+            contents.replace("\r\n", "\n")
+        } else {
+            contents
+        };
+        match self {
+            Eol::Lf => lf,
+            Eol::Crlf => lf.replace('\n', "\r\n"),
+        }
+    }
+}
+
+/// The rewrite must only differ from the original by the intended
+/// substitutions: putting back what they replaced has to give the original
+/// again, byte for byte (so that, say, a file that did not end with a newline
+/// still does not).
+fn check_only_substitutions(
+    fp: &Path,
+    original: &str,
+    rewritten: &str,
+    substitutions: &[Substitution],
+) -> anyhow::Result<()> {
+    if replace::undo(original, rewritten, substitutions).as_deref()
+        != Some(original)
+    {
+        bail!(
+            "Refusing to rewrite {}: the rewrite would change more than the {} replacement(s) it makes.",
+            fp.display(),
+            substitutions.len()
+        );
+    }
+    Ok(())
+}
+
+/// Refuses to go ahead when the plan would make more than `max` replacements
+/// in total, which usually means the old name is far too common a string.
+fn check_max_total_replacements(
+    rewrites: &[Rewrite],
+    max: usize,
+) -> anyhow::Result<()> {
+    let total: usize = rewrites.iter().map(|rw| rw.count).sum();
+    if total <= max {
+        return Ok(());
+    }
+    let mut by_count: Vec<&Rewrite> = rewrites.iter().collect();
+    by_count.sort_by_key(|rw| Reverse(rw.count));
+    let listing = by_count
+        .iter()
+        .take(10)
+        .map(|rw| format!("  {:>6}  {}", rw.count, rw.path.display()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    bail!(
+        "Refusing to make {total} replacements across {} file(s) (the limit is {max}). Top offenders:\n{listing}",
+        rewrites.len()
+    );
+}
+
+/// Asks about each file that would get more than `threshold` replacements,
+/// leaving out the ones the user declines. With `yes`, they are all kept,
+/// with a warning for each instead.
+fn confirm_large_rewrites(
+    rewrites: Vec<Rewrite>,
+    threshold: usize,
+    yes: bool,
+) -> io::Result<Vec<Rewrite>> {
+    let mut confirmed = Vec::with_capacity(rewrites.len());
+    for rw in rewrites {
+        if rw.count > threshold {
+            if yes {
+                println!(
+                    "Warning: {} gets {} replacements, more than the threshold of {threshold}; rewriting it anyway because of --yes.",
+                    rw.path.display(),
+                    rw.count
+                );
+            } else if !confirm(
+                format_args!(
+                    "{} would get {} replacements, more than the threshold of {threshold}. Rewrite it?",
+                    rw.path.display(),
+                    rw.count
+                ),
+                "--yes",
+            )? {
+                println!("Leaving {} as it is.", rw.path.display());
+                continue;
+            }
+        }
+        confirmed.push(rw);
+    }
+    Ok(confirmed)
+}
+
+#[cfg_attr(
+    feature = "trace",
+    tracing::instrument(level = "debug", skip_all, fields(n_files = file_paths.len()))
+)]
+fn plan_rewrites(
+    root: &Path,
+    file_paths: Vec<PathBuf>,
+    replacer: &Replacer,
+    opts: &RewriteOpts,
+) -> anyhow::Result<Vec<Rewrite>> {
+    let mut rewrites = run_parallel(file_paths, opts.threads, |fp| {
+        if opts.is_cancelled() {
+            return Ok(None);
+        }
+        opts.per_file(plan_rewrite(root, fp, replacer, opts))
+    })?;
+    if opts.is_cancelled() {
+        bail!("Cancelled: nothing was changed.");
+    }
+    rewrites.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if let Some(max) = opts.max_total_replacements {
+        check_max_total_replacements(&rewrites, max)?;
+    }
+
+    if opts.idempotent && replacer.is_regex() {
+        check_idempotent(replacer, &rewrites, opts.strict)?;
+    }
+
+    Ok(rewrites)
+}
+
+/// Works out the rewrite of a single file, if it needs one.
+fn plan_rewrite(
+    root: &Path,
+    fp: PathBuf,
+    replacer: &Replacer,
+    opts: &RewriteOpts,
+) -> anyhow::Result<Option<Rewrite>> {
+    let contents = {
+        let _permit = opts.io.acquire();
+        let _open = opts.open_files.acquire();
+        read_text(&fp, opts.encoding)?
+    };
+    let Some(contents) = contents else {
+        println!(
+            "Skipping {}: it is not valid {}.",
+            fp.display(),
+            opts.encoding.map_or("UTF-8", Encoding::name)
+        );
+        return Ok(None);
+    };
+    let replacer = replacer.for_path(fp.strip_prefix(root).unwrap_or(&fp));
+    let replaced = if opts.comments_only {
+        let Some(comments) = comments::ranges(&fp, &contents) else {
+            return Ok(None);
+        };
+        replacer.replace_at_within(&contents, &comments)
+    } else {
+        replacer.replace_at(&contents)
+    };
+    let (Cow::Owned(new_contents), substitutions) = replaced else {
+        return Ok(None);
+    };
+    check_only_substitutions(&fp, &contents, &new_contents, &substitutions)?;
+    let starts: Vec<_> =
+        substitutions.iter().map(|sub| sub.from.start).collect();
+    let new_contents = match opts.eol {
+        Some(eol) => eol.normalize(new_contents),
+        None => new_contents,
+    };
+    if let Some(encoding) = opts.encoding {
+        if encode_text(&new_contents, encoding).is_none() {
+            println!(
+                "Skipping {}: the new name cannot be represented in {}.",
+                fp.display(),
+                encoding.name()
+            );
+            return Ok(None);
+        }
+    }
+    let path = fp.strip_prefix(root).unwrap_or(&fp).to_path_buf();
+    Ok(Some(Rewrite {
+        path,
+        contents: new_contents,
+        count: starts.len(),
+        lines: line_numbers(&contents, &starts),
+    }))
+}
+
+/// Whether a write failed because another process has the file open
+/// without sharing it (`ERROR_SHARING_VIOLATION`) or has locked part of it
+/// (`ERROR_LOCK_VIOLATION`), which only happens on Windows.
+fn is_sharing_violation(err: &io::Error) -> bool {
+    cfg!(windows) && matches!(err.raw_os_error(), Some(32 | 33))
+}
+
+/// Replaces the contents of `fp` without it ever being seen half-written:
+/// `contents` go to a temporary file next to it, which is given `fp`'s
+/// permissions (so that e.g. an executable script stays executable) and then
+/// renamed over it. A symlink is written through, rather than replaced.
+fn write_atomically(fp: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let (fp, tmp) = write_temp(fp, contents)?;
+    replace_with_temp(&fp, &tmp)
+}
+
+/// The first half of [`write_atomically`]: writes `contents` to the
+/// temporary file next to `fp`, returning where `fp` resolves to and the
+/// temporary file.
+fn write_temp(
+    fp: &Path,
+    contents: &[u8],
+) -> anyhow::Result<(PathBuf, PathBuf)> {
+    let resolved = fs::canonicalize(fp);
+    let fp = resolved.as_deref().unwrap_or(fp);
+    let permissions = fs::metadata(fp)
+        .with_context(|| format!("Could not stat file: {}", fp.display()))?
+        .permissions();
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(fp.file_name().unwrap_or_default());
+    tmp_name.push(".pmv-tmp");
+    let tmp = fp.with_file_name(tmp_name);
+    let written = fs::write(&tmp, contents)
+        .and_then(|()| fs::set_permissions(&tmp, permissions));
+    if let Err(err) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(write_error(fp, err));
+    }
+    Ok((fp.to_path_buf(), tmp))
+}
+
+/// The second half of [`write_atomically`]: renames the temporary file
+/// `tmp` over `fp`.
+fn replace_with_temp(fp: &Path, tmp: &Path) -> anyhow::Result<()> {
+    if let Err(err) = fs::rename(tmp, fp) {
+        let _ = fs::remove_file(tmp);
+        return Err(write_error(fp, err));
+    }
+    Ok(())
+}
+
+fn write_error(fp: &Path, err: io::Error) -> anyhow::Error {
+    if is_sharing_violation(&err) {
+        return anyhow::anyhow!(
+            "{} is open in another program; close it and re-run to rewrite it.",
+            fp.display()
+        );
+    }
+    anyhow::Error::new(err).context(format!(
+        "Could not write back new contents of file: {}",
+        fp.display()
+    ))
+}
+
+/// With `--buffer-writes`, how many files each thread writes in one go.
+const WRITE_BATCH: usize = 64;
+
+/// The bytes to write for a rewrite's contents.
+fn encoded(contents: String, opts: &RewriteOpts) -> Vec<u8> {
+    match opts.encoding {
+        // Checked to be representable when the rewrite was planned.
+        Some(encoding) => encode_text(&contents, encoding).unwrap(),
+        None => contents.into_bytes(),
+    }
+}
+
+/// Writes a batch of rewrites under a single IO permit: all of their
+/// temporary files first, and then all the renames over the originals, so
+/// that a high-latency filesystem sees the two kinds of operation grouped
+/// together rather than interleaved file by file.
+fn write_batch(
+    root: &Path,
+    batch: Vec<Rewrite>,
+    opts: &RewriteOpts,
+) -> anyhow::Result<Vec<(PathBuf, Vec<usize>)>> {
+    let _permit = opts.io.acquire();
+    let _open = opts.open_files.acquire();
+    let mut staged = Vec::with_capacity(batch.len());
+    for rw in batch {
+        let fp = root.join(&rw.path);
+        let contents = encoded(rw.contents, opts);
+        let sizes = (file_len(&fp), contents.len() as u64);
+        if let Some((fp, tmp)) =
+            opts.per_file(write_temp(&fp, &contents).map(Some))?
+        {
+            staged.push((rw.path, rw.lines, rw.count, sizes, fp, tmp));
+        }
+    }
+    let mut written = Vec::with_capacity(staged.len());
+    for (path, lines, count, (before, after), fp, tmp) in staged {
+        let replaced = replace_with_temp(&fp, &tmp).and_then(|()| {
+            opts.diffstat.record(before, after);
+            audit::record(
+                opts.audit.as_ref(),
+                "rewrite",
+                audit::rewrite_fields(&root.join(&path), count),
+            )?;
+            opts.emit(|| rewritten_event(&path, count));
+            Ok(Some((path, lines)))
+        });
+        written.extend(opts.per_file(replaced)?);
+    }
+    Ok(written)
+}
+
+/// The length of the file at `fp` as it is before being rewritten, for the
+/// [`DiffStat`]. Errors are left to the write to report.
+fn file_len(fp: &Path) -> u64 {
+    fs::metadata(fp).map_or(0, |metadata| metadata.len())
+}
+
+/// The `--format jsonl` event for a file that has been rewritten.
+fn rewritten_event(path: &Path, count: usize) -> serde_json::Value {
+    serde_json::json!({ "path": path, "count": count, "status": "rewritten" })
+}
+
+/// Writes out planned rewrites, resolving their paths against `root` (which
+/// is where the project lives once it has been moved). Returns the relative
+/// paths of the files written.
+#[cfg_attr(
+    feature = "trace",
+    tracing::instrument(level = "debug", skip_all, fields(n_files = rewrites.len()))
+)]
+fn apply_rewrites(
+    root: &Path,
+    rewrites: Vec<Rewrite>,
+    opts: &RewriteOpts,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let total = rewrites.len();
+    if opts.buffer_writes {
+        let mut batches = Vec::new();
+        let mut rewrites = rewrites.into_iter().peekable();
+        while rewrites.peek().is_some() {
+            batches.push(rewrites.by_ref().take(WRITE_BATCH).collect());
+        }
+        let written = run_parallel(batches, opts.threads, |batch| {
+            if opts.is_cancelled() {
+                return Ok(None);
+            }
+            write_batch(root, batch, opts).map(Some)
+        })?;
+        return finish_rewrites(
+            written.into_iter().flatten().collect(),
+            total,
+            opts,
+        );
+    }
+    let written = run_parallel(rewrites, opts.threads, |rw| {
+        if opts.is_cancelled() {
+            return Ok(None);
+        }
+        let fp = root.join(&rw.path);
+        let contents = encoded(rw.contents, opts);
+        let _permit = opts.io.acquire();
+        let _open = opts.open_files.acquire();
+        let before = file_len(&fp);
+        opts.per_file(write_atomically(&fp, &contents).and_then(|()| {
+            opts.diffstat.record(before, contents.len() as u64);
+            audit::record(
+                opts.audit.as_ref(),
+                "rewrite",
+                audit::rewrite_fields(&fp, rw.count),
+            )?;
+            opts.emit(|| rewritten_event(&rw.path, rw.count));
+            Ok(Some((rw.path, rw.lines)))
+        }))
+    })?;
+    finish_rewrites(written, total, opts)
+}
+
+/// Reports on the files [`apply_rewrites`] wrote, of the `total` planned.
+fn finish_rewrites(
+    mut written: Vec<(PathBuf, Vec<usize>)>,
+    total: usize,
+    opts: &RewriteOpts,
+) -> anyhow::Result<Vec<PathBuf>> {
+    written.sort();
+    if opts.is_cancelled() {
+        println!(
+            "Cancelled after rewriting {} of {total} file(s):",
+            written.len()
+        );
+        for (path, _) in &written {
+            println!("  {}", path.display());
+        }
+        bail!("Cancelled: the remaining files were left as they were.");
+    }
+    if opts.show_lines {
+        for (path, lines) in &written {
+            println!(
+                "Rewrote {} on line(s) {}.",
+                path.display(),
+                format_lines(lines)
+            );
+        }
+    }
+    Ok(written.into_iter().map(|(path, _)| path).collect())
+}
+
+/// Leaves out (with a warning) the rewrites of files that resolve, through a
+/// symlink or `..`, to somewhere outside the project at `root`.
+fn drop_outside(root: &Path, rewrites: Vec<Rewrite>) -> Vec<Rewrite> {
+    rewrites
+        .into_iter()
+        .filter(|rw| {
+            let fp = root.join(&rw.path);
+            let inside = fp
+                .canonicalize()
+                .is_ok_and(|resolved| resolved.starts_with(root));
+            if !inside {
+                println!(
+                    "Warning: not rewriting {}, which is outside the project (pass --allow-outside to rewrite it anyway).",
+                    fp.display()
+                );
+            }
+            inside
+        })
+        .collect()
+}
+
+/// Prints how many files, and how many replacements, the rewrites amount to
+/// for each file extension, most replacements first.
+fn print_count_by_ext(rewrites: &[Rewrite]) {
+    let mut by_ext: HashMap<String, (usize, usize)> = HashMap::new();
+    for rw in rewrites {
+        let ext = match rw.path.extension() {
+            Some(ext) => format!(".{}", ext.to_string_lossy()),
+            None => "(none)".to_string(),
+        };
+        let (files, replacements) = by_ext.entry(ext).or_default();
+        *files += 1;
+        *replacements += rw.count;
+    }
+    let mut by_ext: Vec<_> = by_ext.into_iter().collect();
+    by_ext.sort_by(|(a, (_, a_count)), (b, (_, b_count))| {
+        b_count.cmp(a_count).then_with(|| a.cmp(b))
+    });
+    println!("Replacements by extension:");
+    for (ext, (files, replacements)) in by_ext {
+        println!(
+            "  {ext:<12} {files:>5} file(s) / {replacements:>6} replacement(s)"
+        );
+    }
+}
+
+/// Whether a walk error is harmless: a symlink loop that was not followed,
+/// or an ignore file that could only be partly parsed.
+fn is_benign(err: &ignore::Error) -> bool {
+    match err {
+        ignore::Error::Loop { .. } | ignore::Error::Partial(_) => true,
+        ignore::Error::WithLineNumber { err, .. }
+        | ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. } => is_benign(err),
+        _ => false,
+    }
+}
+
+/// Walk errors held back under `--dedupe-output`: each distinct one (and
+/// whether it was harmless), in the order they first came up, with how
+/// often it did.
+#[derive(Default)]
+struct SeenErrors {
+    index: HashMap<String, usize>,
+    errors: Vec<(String, bool, usize)>,
+}
+
+impl SeenErrors {
+    fn note(&mut self, msg: String, benign: bool) {
+        match self.index.get(&msg) {
+            Some(&i) => self.errors[i].2 += 1,
+            None => {
+                self.index.insert(msg.clone(), self.errors.len());
+                self.errors.push((msg, benign, 1));
+            }
+        }
+    }
+
+    fn report(self, reporter: &mut Reporter) -> io::Result<()> {
+        for (msg, benign, count) in self.errors {
+            let count = if count > 1 {
+                format!(" (x{count})")
+            } else {
+                String::new()
+            };
+            if benign {
+                reporter.detail(format_args!("{msg}{count}"))?;
+            } else {
+                reporter.warn(format_args!("{msg}{count}"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Receives what the walker threads found, reporting on it as it arrives.
+/// Returning early (on a write error) drops `rx`, which tells the walkers to
+/// stop.
+/// With `report_skips`, every file that was not considered text is listed
+/// (by MIME type) once the walk is done. With `dedupe`, so are the walk
+/// errors, each distinct one only once. With `group_by_dir`, so are the text
+/// files, under their directories (see [`report_by_dir`]).
+fn collect(
+    rx: Receiver<Result<Found, ignore::Error>>,
+    mut reporter: Reporter,
+    report_skips: bool,
+    dedupe: bool,
+    group_by_dir: bool,
+) -> io::Result<Vec<PathBuf>> {
+    let mut file_paths = Vec::new();
+    let mut skipped = Vec::new();
+    let (mut walk_errors, mut benign_errors) = (0, 0);
+    let mut seen = SeenErrors::default();
+    if reporter.format() == Format::Jsonl {
+        for found in rx {
+            reporter.event(found_event(&found))?;
+            if let Ok(Found::Text(path)) = found {
+                file_paths.push(path);
+            }
+        }
+        reporter.finish()?;
+        return Ok(file_paths);
+    }
+    for found in rx {
+        match found {
+            Ok(Found::Text(path)) => {
+                if !group_by_dir {
+                    reporter
+                        .info(format_args!("Renaming: {}", path.display()))?;
+                }
+                file_paths.push(path);
+            }
+            Ok(Found::NotText(path, mime)) => {
+                reporter.detail(format_args!(
+                    "Skipping ({mime}): {}",
+                    path.display()
+                ))?;
+                if report_skips {
+                    skipped.push((mime, path));
+                }
+            }
+            Ok(Found::Empty(path)) => {
+                reporter.detail(format_args!(
+                    "Empty (skipped content): {}",
+                    path.display()
+                ))?;
+                if report_skips {
+                    skipped.push(("(empty)", path));
+                }
+            }
+            Ok(Found::Minified(path)) => {
+                reporter.info(format_args!(
+                    "Skipping {}, which looks minified (pass --scan-all to rewrite it anyway).",
+                    path.display()
+                ))?;
+                if report_skips {
+                    skipped.push(("(minified)", path));
+                }
+            }
+            Ok(Found::Generated(path)) => {
+                reporter.info(format_args!(
+                    "Skipping {}, which looks generated (pass --no-skip-generated to rewrite it anyway).",
+                    path.display()
+                ))?;
+                if report_skips {
+                    skipped.push(("(generated)", path));
+                }
+            }
+            Err(err) if dedupe => {
+                let benign = is_benign(&err);
+                if benign {
+                    benign_errors += 1;
+                } else {
+                    walk_errors += 1;
+                }
+                seen.note(err.to_string(), benign);
+            }
+            Err(err) if is_benign(&err) => {
+                benign_errors += 1;
+                reporter.detail(format_args!("{err}"))?;
+            }
+            Err(err) => {
+                walk_errors += 1;
+                reporter.warn(format_args!("{err}"))?;
+            }
+        }
+    }
+    seen.report(&mut reporter)?;
+    if group_by_dir {
+        report_by_dir(&mut reporter, &file_paths)?;
+    }
+    if walk_errors + benign_errors > 0 {
+        reporter.info(format_args!(
+            "The walk ran into {walk_errors} error(s), and {benign_errors} harmless problem(s){}.",
+            if benign_errors > 0 && !reporter.is_verbose() {
+                " (shown with -v)"
+            } else {
+                ""
+            }
+        ))?;
+    }
+    if !skipped.is_empty() {
+        skipped.sort();
+        reporter.info(format_args!(
+            "Skipped {} file(s) that were not rewritten as text:",
+            skipped.len()
+        ))?;
+        for (mime, path) in &skipped {
+            reporter.info(format_args!("  {mime:<32} {}", path.display()))?;
+        }
+    }
+    reporter.finish()?;
+    Ok(file_paths)
+}
+
+/// What the walk found about a file, as a `--format jsonl` event.
+fn found_event(found: &Result<Found, ignore::Error>) -> serde_json::Value {
+    match found {
+        Ok(Found::Text(path)) => {
+            serde_json::json!({ "path": path, "status": "found" })
+        }
+        Ok(Found::NotText(path, mime)) => serde_json::json!({
+            "path": path,
+            "status": "skipped",
+            "reason": mime,
+        }),
+        Ok(Found::Empty(path)) => serde_json::json!({
+            "path": path,
+            "status": "skipped",
+            "reason": "empty",
+        }),
+        Ok(Found::Minified(path)) => serde_json::json!({
+            "path": path,
+            "status": "skipped",
+            "reason": "minified",
+        }),
+        Ok(Found::Generated(path)) => serde_json::json!({
+            "path": path,
+            "status": "skipped",
+            "reason": "generated",
+        }),
+        Err(err) => serde_json::json!({
+            "status": "error",
+            "error": err.to_string(),
+            "harmless": is_benign(err),
+        }),
+    }
+}
+
+/// Lists `file_paths` grouped under their directories, each with how many
+/// of them it holds.
+fn report_by_dir(
+    reporter: &mut Reporter,
+    file_paths: &[PathBuf],
+) -> io::Result<()> {
+    let mut by_dir: BTreeMap<&Path, Vec<&OsStr>> = BTreeMap::new();
+    for path in file_paths {
+        if let (Some(dir), Some(name)) = (path.parent(), path.file_name()) {
+            by_dir.entry(dir).or_default().push(name);
+        }
+    }
+    reporter.info(format_args!(
+        "Renaming {} file(s), by directory:",
+        file_paths.len()
+    ))?;
+    for (dir, mut names) in by_dir {
+        names.sort();
+        reporter.info(format_args!("  {} ({})", dir.display(), names.len()))?;
+        for name in names {
+            reporter.info(format_args!("    {}", Path::new(name).display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn panic_message(panic: &Box<dyn Any + Send>) -> &str {
+    if let Some(msg) = panic.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        msg
+    } else {
+        "(no message)"
+    }
+}
+
+/// Options controlling how [`find_and_replace_in_dir`] plans rewrites.
+struct RewriteOpts {
+    idempotent: bool,
+    strict: bool,
+    max_total_replacements: Option<usize>,
+    verbose: bool,
+    format: Format,
+    show_lines: bool,
+    report_skips: bool,
+    report_unchanged: bool,
+    dedupe_output: bool,
+    group_by_dir: bool,
+    /// Rewrite text files even if they look minified.
+    scan_all: bool,
+    /// Skip lockfiles and files that say they were generated.
+    skip_generated: bool,
+    /// Only replace inside comments (see [`comments::ranges`]).
+    comments_only: bool,
+    /// Further MIME types to rewrite as text (see [`is_text_mime`]).
+    mime_allow: Arc<[String]>,
+    /// What decides which files are text instead of their MIME type, if
+    /// anything.
+    detect: Option<Arc<DetectCmd>>,
+    /// Threads used for the walk and for reading/rewriting files.
+    threads: usize,
+    /// Bounds how many of those threads may read or write a file at once.
+    io: Semaphore,
+    /// Bounds how many files are open at once across all the threads, for
+    /// sniffing as well as reading and writing, so as to stay within the
+    /// process's file descriptor limit.
+    open_files: Arc<Semaphore>,
+    /// Decode and re-encode files with this instead of assuming UTF-8.
+    encoding: Option<&'static Encoding>,
+    /// Convert the line endings of rewritten files to this.
+    eol: Option<Eol>,
+    /// Write files in batches (see [`write_batch`]).
+    buffer_writes: bool,
+    /// Which of the walked files are candidates for rewriting at all.
+    filter: WalkFilter,
+    /// Once set, the walk, planning and rewriting all stop at the next file
+    /// boundary; files already being written are finished first.
+    cancelled: Arc<AtomicBool>,
+    /// Where every change is recorded as it is made, if anywhere.
+    audit: Option<AuditLog>,
+    /// How many bytes the files written so far grew and shrank by.
+    diffstat: DiffStat,
+    /// Errors with individual files, which (unless `strict`) are collected
+    /// here to be reported at the end instead of aborting the run.
+    failures: Mutex<Vec<anyhow::Error>>,
+}
+
+impl RewriteOpts {
+    /// Options for a plain rewrite of the files `filter` lets through, with
+    /// every other setting at its default and a thread per core.
+    fn plain(filter: WalkFilter) -> Self {
+        let threads =
+            available_parallelism().map_or(1, |n_cores| n_cores.get());
+        RewriteOpts {
+            idempotent: false,
+            strict: false,
+            max_total_replacements: None,
+            verbose: false,
+            format: Format::Text,
+            show_lines: false,
+            report_skips: false,
+            report_unchanged: false,
+            dedupe_output: false,
+            group_by_dir: false,
+            scan_all: false,
+            skip_generated: true,
+            comments_only: false,
+            mime_allow: Arc::from([]),
+            detect: None,
+            threads,
+            io: Semaphore::new(threads.min(8)),
+            open_files: Arc::new(Semaphore::new(pool::max_open_files())),
+            encoding: None,
+            eol: None,
+            buffer_writes: false,
+            failures: Mutex::new(Vec::new()),
+            filter,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            audit: None,
+            diffstat: DiffStat::default(),
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Reports `event` under `--format jsonl`.
+    fn emit(&self, event: impl FnOnce() -> serde_json::Value) {
+        if self.format == Format::Jsonl {
+            report::emit(event());
+        }
+    }
+
+    /// Under `strict`, passes a per-file error on so the run aborts;
+    /// otherwise sets it aside and carries on with the other files.
+    fn per_file<T>(
+        &self,
+        result: anyhow::Result<Option<T>>,
+    ) -> anyhow::Result<Option<T>> {
+        match result {
+            Err(err) if !self.strict => {
+                self.emit(|| {
+                    serde_json::json!({
+                        "status": "failed",
+                        "error": format!("{err:#}"),
+                    })
+                });
+                self.failures.lock().unwrap().push(err);
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+}
+
+/// Walks `dir` and works out the new contents of every text file that
+/// mentions the old name. Nothing is written: see [`apply_rewrites`].
+fn find_and_replace_in_dir(
+    dir: PathBuf,
+    replacer: &Replacer,
+    opts: &RewriteOpts,
+) -> anyhow::Result<Vec<Rewrite>> {
+    let reporter = Reporter::new(opts.verbose, opts.format);
+    find_and_replace_reporting(dir, replacer, opts, reporter)
+}
+
+/// [`find_and_replace_in_dir`], with the collector reporting to `reporter`.
+fn find_and_replace_reporting(
+    dir: PathBuf,
+    replacer: &Replacer,
+    opts: &RewriteOpts,
+    reporter: Reporter,
+) -> anyhow::Result<Vec<Rewrite>> {
+    let (candidates_tx, candidates_rx) =
+        crossbeam_channel::bounded::<Result<PathBuf, ignore::Error>>(100);
+    let (tx, rx) =
+        crossbeam_channel::bounded::<Result<Found, ignore::Error>>(100);
+
+    let (report_skips, dedupe, group_by_dir) =
+        (opts.report_skips, opts.dedupe_output, opts.group_by_dir);
+    let collector = thread::spawn(move || {
+        collect(rx, reporter, report_skips, dedupe, group_by_dir)
+    });
+    // Sniffing MIME types is far slower than walking, so it happens on its
+    // own threads rather than holding up the walker's.
+    let sniffers: Vec<_> = (0..opts.threads)
+        .map(|_| {
+            let (rx, tx) = (candidates_rx.clone(), tx.clone());
+            let (cancelled, mime_allow) =
+                (opts.cancelled.clone(), opts.mime_allow.clone());
+            let (scan_all, skip_generated) =
+                (opts.scan_all, opts.skip_generated);
+            let (detect, open_files) =
+                (opts.detect.clone(), opts.open_files.clone());
+            thread::spawn(move || {
+                sniff_files(rx, tx, cancelled, |path| {
+                    let _permit = open_files.acquire();
+                    classify_file(
+                        path,
+                        scan_all,
+                        skip_generated,
+                        &mime_allow,
+                        detect.as_deref(),
+                    )
+                })
+            })
+        })
+        .collect();
+    drop((candidates_rx, tx));
+
+    walk_files(candidates_tx, opts.threads, &opts.filter, &opts.cancelled);
+    for sniffer in sniffers {
+        if let Err(panic) = sniffer.join() {
+            bail!("A classifying thread panicked: {}", panic_message(&panic));
+        }
+    }
+    let file_paths = match collector.join() {
+        Ok(file_paths) => file_paths
+            .with_context(|| "...while reporting on the files found")?,
+        Err(panic) => {
+            bail!("The collector thread panicked: {}", panic_message(&panic))
+        }
+    };
+
+    if !opts.report_unchanged {
+        return plan_rewrites(&dir, file_paths, replacer, opts);
+    }
+    let scanned = file_paths.clone();
+    let rewrites = plan_rewrites(&dir, file_paths, replacer, opts)?;
+    report_unchanged(&dir, scanned, &rewrites);
+    Ok(rewrites)
+}
+
+/// Lists those of the `scanned` text files (under `root`) that none of
+/// `rewrites` is for.
+fn report_unchanged(root: &Path, scanned: Vec<PathBuf>, rewrites: &[Rewrite]) {
+    let changed: HashSet<&Path> =
+        rewrites.iter().map(|rw| rw.path.as_path()).collect();
+    let mut unchanged: Vec<PathBuf> = scanned
+        .into_iter()
+        .map(|fp| fp.strip_prefix(root).map(Path::to_path_buf).unwrap_or(fp))
+        .filter(|path| !changed.contains(path.as_path()))
+        .collect();
+    if unchanged.is_empty() {
+        return;
+    }
+    unchanged.sort();
+    println!("Left {} scanned text file(s) unchanged:", unchanged.len());
+    for path in &unchanged {
+        println!("  {}", path.display());
+    }
+}
+
+/// An edit to one file, planned by [`plan_replace`] and made by
+/// [`apply_edits`].
+pub struct PlannedEdit {
+    /// Of the file, relative to the project.
+    pub path: PathBuf,
+    /// The file's size in bytes when the edit was planned.
+    pub original_len: u64,
+    /// A hash of the file's contents when the edit was planned, by which
+    /// [`apply_edits`] tells whether it has changed since.
+    pub original_hash: String,
+    /// What the file is to be rewritten with.
+    pub new_contents: String,
+    /// How many replacements the edit makes.
+    pub count: usize,
+}
+
+/// Works out the edits replacing `from` with `to` (matched as `mode` says)
+/// in the text files of the project at `dir`, picked out as `pmv` itself
+/// would, without writing anything: see [`apply_edits`]. Fails on the first
+/// file that cannot be read.
+pub fn plan_replace(
+    dir: &Path,
+    from: &str,
+    to: &str,
+    mode: Mode,
+) -> anyhow::Result<Vec<PlannedEdit>> {
+    let replacer = Replacer::new(from, to, mode)?;
+    let opts = RewriteOpts {
+        strict: true,
+        ..RewriteOpts::plain(WalkFilter::new(dir, &[])?)
+    };
+    let rewrites = find_and_replace_reporting(
+        dir.to_path_buf(),
+        &replacer,
+        &opts,
+        Reporter::quiet(),
+    )?;
+    rewrites
+        .into_iter()
+        .map(|rw| {
+            let fp = dir.join(&rw.path);
+            let original = fs::read(&fp)
+                .with_context(|| format!("Could not read {}", fp.display()))?;
+            Ok(PlannedEdit {
+                original_len: original.len() as u64,
+                original_hash: plan::hash(&original),
+                path: rw.path,
+                new_contents: rw.contents,
+                count: rw.count,
+            })
+        })
+        .collect()
+}
+
+/// Makes the `edits` planned by [`plan_replace`] to the project at `dir`,
+/// writing each file atomically, and returns the paths of the files
+/// written. Nothing is written if any of the files has changed since, as
+/// the edits are then out of date.
+pub fn apply_edits(
+    dir: &Path,
+    edits: Vec<PlannedEdit>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let changed: Vec<String> = edits
+        .iter()
+        .filter(|edit| {
+            fs::read(dir.join(&edit.path))
+                .map_or(true, |now| plan::hash(&now) != edit.original_hash)
+        })
+        .map(|edit| format!("  {}", edit.path.display()))
+        .collect();
+    if !changed.is_empty() {
+        bail!(
+            "Refusing to write anything, as {} file(s) changed since the edits were planned:\n{}",
+            changed.len(),
+            changed.join("\n")
+        );
+    }
+    let rewrites = edits
+        .into_iter()
+        .map(|edit| Rewrite {
+            path: edit.path,
+            contents: edit.new_contents,
+            count: edit.count,
+            lines: Vec::new(),
+        })
+        .collect();
+    let opts = RewriteOpts {
+        strict: true,
+        ..RewriteOpts::plain(WalkFilter::new(dir, &[])?)
+    };
+    apply_rewrites(dir, rewrites, &opts)
+}
+
+/// The exit code for runs that completed, except for some files that could
+/// not be processed.
+const EXIT_FILES_FAILED: i32 = 2;
+
+/// The exit code for runs whose repository rename failed.
+const EXIT_FORGE_FAILED: i32 = 3;
+
+/// The exit code for runs cut short by `--timeout`.
+const EXIT_TIMED_OUT: i32 = 4;
+
+/// The exit code for `--dry-run --dry-run-exit-code` runs that would change
+/// something.
+const EXIT_CHANGES_PENDING: i32 = 10;
+
+/// The exit code for `--check` runs that found something left to change:
+/// not 1, which is what errors exit with.
+const EXIT_WOULD_CHANGE: i32 = 5;
+
+/// Lists the files and paths that still mention `from`, returning whether
+/// there were any.
+fn report_check(
+    from: &str,
+    rewrites: &[Rewrite],
+    path_renames: &[renames::PathRename],
+) -> bool {
+    if rewrites.is_empty() && path_renames.is_empty() {
+        println!("Nothing mentions {from:?} any more.");
+        return false;
+    }
+    for rw in rewrites {
+        println!(
+            "{} mentions {from:?} {} time(s).",
+            rw.path.display(),
+            rw.count
+        );
+    }
+    for rn in path_renames {
+        println!("{} is named after {from:?}.", rn.from.display());
+    }
+    true
+}
+
+/// Lists the files that were skipped because of errors, and exits with
+/// [`EXIT_FILES_FAILED`] if there were any.
+fn report_failures(failures: Vec<anyhow::Error>) -> anyhow::Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    for err in &failures {
+        println!("Error: {err:#}");
+    }
+    println!("{} file(s) could not be processed.", failures.len());
+    process::exit(EXIT_FILES_FAILED);
+}
+
+/// Runs `pmv` on the command line it was started with.
+pub fn run() -> anyhow::Result<()> {
+    let args = match cli().run() {
+        Cli::Doctor { existing } => {
+            let sh = Shell::new()?;
+            let cwd = sh.current_dir();
+            let dir = match existing {
+                Some(existing) => existing.canonicalize_existing(&cwd)?.path,
+                None => cwd,
+            };
+            return doctor::doctor(&sh, dir);
+        }
+        Cli::Apply { verify_plan, plan } => {
+            return plan::apply(&Shell::new()?, &plan, verify_plan);
+        }
+        Cli::Rebrand {
+            root,
+            from,
+            to,
+            dry_run,
+            yes,
+        } => {
+            return rebrand::rebrand(root, &from, &to, dry_run, yes);
+        }
+        Cli::Archive {
+            from,
+            to,
+            output,
+            dry_run,
+            yes,
+            archive,
+        } => {
+            return archive::rename_in_archive(
+                &archive, &from, &to, output, dry_run, yes,
+            );
+        }
+        Cli::Rename(args) => args,
+    };
+    #[cfg(feature = "trace")]
+    report::init_tracing(args.trace);
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let on_interrupt = cancelled.clone();
+    ctrlc::set_handler(move || {
+        if on_interrupt.swap(true, Ordering::Relaxed) {
+            process::exit(130);
+        }
+        println!(
+            "Cancelling once the files in progress are done (press Ctrl-C again to quit at once)."
+        );
+    })
+    .with_context(|| "...while installing the Ctrl-C handler")?;
+    let result = rename(args, cancelled);
+    if TIMED_OUT.load(Ordering::Relaxed) {
+        if let Err(err) = &result {
+            println!("Error: {err:#}");
+        }
+        process::exit(EXIT_TIMED_OUT);
+    }
+    result
+}
+
+/// Set once a `--timeout` is up.
+static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+/// Renames the project as `args` say, stopping early once `cancelled` is
+/// set.
+fn rename(args: Pmv, cancelled: Arc<AtomicBool>) -> anyhow::Result<()> {
+    let Pmv {
+        existing,
+        new,
+        mode,
+        no_auto_style,
+        rule,
+        template_file,
+        idempotent,
+        strict,
+        max_total_replacements,
+        replace_count_threshold,
+        find_root,
+        no_parent_search,
+        parents,
+        strategy,
+        deref_symlinks,
+        trash,
+        verbose,
+        format,
+        first_only,
+        comments_only,
+        replace_urls,
+        rename_branch,
+        rename_tags,
+        push_tags,
+        stage,
+        rename_only_files,
+        rename_max_depth,
+        everywhere,
+        from_manifest,
+        from,
+        to,
+        no_rename_dir,
+        manifest_only,
+        replace_json_keys,
+        env_aware,
+        set,
+        fmt,
+        threads,
+        io_concurrency,
+        max_open_files,
+        concurrency_autotune,
+        timeout,
+        buffer_writes,
+        nice,
+        quiet_gh,
+        ignore_gh_errors,
+        rollback_on_gh_failure,
+        gh_async,
+        remote,
+        dry_run,
+        dry_run_exit_code,
+        encoding,
+        normalize_eol,
+        only,
+        since,
+        report_skips,
+        report_unchanged,
+        dedupe_output,
+        group_by_dir,
+        scan_all,
+        skip_generated,
+        mime_allow,
+        detect_cmd,
+        binary_same_length,
+        include_self,
+        include_vcs,
+        allow_outside,
+        show_timings,
+        forge,
+        repo_name,
+        lowercase_repo,
+        json_plan,
+        check,
+        rename_map,
+        audit_log,
+        resume,
+        forge_arg,
+        show_lines,
+        count_by_ext,
+        keep_going,
+        force,
+        derive,
+        yes,
+        confirm_remote,
+        ..
+    } = args;
+    // The timeout covers the whole run, the scans before the walk included.
+    if let Some(timeout) = timeout {
+        timeout.start(cancelled.clone(), &TIMED_OUT);
+    }
+    let dry_run = dry_run || json_plan.is_some() || check;
+
+    if nice {
+        priority::be_nice();
+    }
+    let audit = audit_log
+        .or_else(|| resume.clone())
+        .map(|path| AuditLog::open(&path))
+        .transpose()?;
+
+    let sh = Shell::new()?;
+    let cwd = sh.current_dir();
+    let mut existing = existing.canonicalize_existing(&cwd)?;
+    if find_root && !no_parent_search {
+        existing = find_project_root(existing, &cwd)?;
+    }
+    // A resumed run takes its names from the log, as the project may well
+    // have been moved to its new name already.
+    let resumed = resume.as_deref().map(audit::last_run).transpose()?;
+    let moved = resumed
+        .as_ref()
+        .map(|run| already_moved(run, &existing.path))
+        .transpose()?
+        .unwrap_or(false);
+    let no_rename_dir = no_rename_dir || moved;
+    let old_name = &match &resumed {
+        Some(run) => run.old_name.clone(),
+        None => dir_name(&existing, encoding)?,
+    };
+    let new = names::new_name(old_name, new, derive)?;
+    // A new "name" with a separator in it is where to move the project to,
+    // relative to the current directory; its last component is the name.
+    let destination = new
+        .contains(['/', std::path::MAIN_SEPARATOR])
+        .then(|| InputDir::from_str(&new).map(|dir| dir.resolve_intended(&cwd)))
+        .transpose()?;
+    let new = match &destination {
+        Some(destination) => destination
+            .file_name()
+            .and_then(OsStr::to_str)
+            .with_context(|| {
+                format!("{} does not end in a name.", destination.display())
+            })?
+            .to_string(),
+        None => new,
+    };
+    let (from, to) = match &resumed {
+        Some(run) => {
+            if new != run.new_name {
+                bail!(
+                    "The run being resumed renamed {:?} to {:?}, not {new:?}.",
+                    run.old_name,
+                    run.new_name
+                );
+            }
+            if from.is_some_and(|from| from != run.from)
+                || to.is_some_and(|to| to != run.to)
+            {
+                bail!(
+                    "The run being resumed replaced {:?} with {:?}; give the same --from and --to, or neither.",
+                    run.from,
+                    run.to
+                );
+            }
+            (run.from.clone(), run.to.clone())
+        }
+        None => {
+            let from = match (from, from_manifest) {
+                (Some(_), true) => {
+                    bail!("Give either --from or --from-manifest, not both.")
+                }
+                (Some(from), false) => from,
+                (None, true) => manifest::package_name(&existing.path)?,
+                (None, false) => old_name.to_string(),
+            };
+            let to = to.unwrap_or_else(|| new.clone());
+            (from, to)
+        }
+    };
+    if !dry_run && resumed.is_none() {
+        let run = audit::Run {
+            dir: existing.path.clone(),
+            old_name: old_name.clone(),
+            new_name: new.clone(),
+            from: from.clone(),
+            to: to.clone(),
+            moved_to: None,
+        };
+        audit::record(audit.as_ref(), "start", run.fields())?;
+    }
+
+    let mut filter = WalkFilter::new(&existing.path, &only)?;
+    if include_self {
+        filter = filter.include_own_files();
+    }
+    if include_vcs {
+        filter = filter.include_vcs();
+    }
+    if let Some(rev) = since {
+        filter = filter.changed(git::changed_since(&sh, &existing.path, &rev)?);
+    }
+    if let Some(log) = &resume {
+        filter = filter.resume(audit::rewritten_files(log)?);
+        println!(
+            "Resuming from {}: skipping the {} file(s) it records as rewritten that have not changed since.",
+            log.display(),
+            filter.n_done()
+        );
+    }
+
+    // Every style is replaced in, as those the project does not use match
+    // nothing; the ones it does are listed once the files have been walked.
+    let styled = match names::styled_variants(&from, &to) {
+        Some(variants) if mode == Mode::Literal && !no_auto_style => {
+            Some(variants)
+        }
+        _ => None,
+    };
+    let mut replacer = match &styled {
+        Some(variants) => {
+            let mut variants = variants.iter();
+            let (old, new) = variants.next().unwrap();
+            let mut replacer = Replacer::new(old, new, mode)?;
+            for (old, new) in variants {
+                replacer = replacer.also(Replacer::new(old, new, mode)?);
+            }
+            replacer
+        }
+        None => Replacer::new(&from, &to, mode)?,
+    };
+    if let Some(RenameBranch { old, new, .. }) = &rename_branch {
+        replacer = replacer.also(Replacer::new(old, new, Mode::Identifier)?);
+    }
+    if first_only {
+        replacer = replacer.first_only();
+    }
+    if let Some(risk) = names::risk(&from).filter(|_| !replacer.is_regex()) {
+        if strict && !force {
+            bail!(
+                "Refusing to replace {from:?}, since {risk} and would likely match unintended text (pass --force to go ahead)."
+            );
+        }
+        println!(
+            "Warning: {from:?} is risky to replace, since {risk}: check the changes closely."
+        );
+    }
+
+    if rename_only_files {
+        let renames =
+            renames::plan_path_renames(&replacer, &filter, rename_max_depth)?;
+        report_styles(&replacer, styled.as_deref(), &from, &to);
+        renames::check_collisions(&renames)?;
+        let (submodule_renames, renames) = split_submodule_renames(
+            renames,
+            &submodules::list(&sh, &existing.path)?,
+            &existing.path,
+        );
+        if dry_run {
+            renames::print_path_renames(&submodule_renames);
+            renames::print_path_renames(&renames);
+            if dry_run_exit_code
+                && !(renames.is_empty() && submodule_renames.is_empty())
+            {
+                process::exit(EXIT_CHANGES_PENDING);
+            }
+            return Ok(());
+        }
+        renames::apply_path_renames(&renames, audit.as_ref())?;
+        submodules::apply(&sh, &existing.path, &submodule_renames, &[])?;
+        if let Some(file) = rename_map {
+            renames::write_rename_map(
+                &file,
+                &existing.path,
+                &existing.path,
+                &renames,
+            )?;
+        }
+        return Ok(());
+    }
+
+    let remote = remote
+        .map(|name| forge::Remote::resolve(&sh, &existing.path, &name))
+        .transpose()?;
+    let env_forge = forge::env_forge()?;
+    let forge = match (forge, &remote) {
+        (Some(forge), _) => Some(forge),
+        (None, Some(remote)) => match Forge::detect(&remote.host).or(env_forge)
+        {
+            Some(forge) => Some(forge),
+            None => bail!(
+                "Cannot tell which forge {} is; pass --forge (or set PMV_FORGE).",
+                remote.host
+            ),
+        },
+        (None, None) => None,
+    };
+    // Without a --remote or --forge, the repository is only renamed if
+    // `origin` points at one named after the project.
+    let forge = forge.or_else(|| {
+        forge::origin_forge(&sh, &existing.path, old_name, env_forge)
+    });
+    let rename_remote = forge.is_some();
+    let forge = forge.unwrap_or(Forge::Github);
+    let forge_arg: Vec<String> =
+        forge::env_args()?.into_iter().chain(forge_arg).collect();
+    let mut repo_name = repo_name.unwrap_or_else(|| new.clone());
+    if lowercase_repo {
+        repo_name = repo_name.to_lowercase();
+    }
+    if replace_urls {
+        let origin;
+        let linked = match &remote {
+            Some(remote) => remote,
+            None => {
+                origin = forge::Remote::resolve(&sh, &existing.path, "origin")?;
+                &origin
+            }
+        };
+        replacer = replacer.with_urls(
+            &linked.host,
+            &linked.owner,
+            &linked.repo,
+            &repo_name,
+        );
+    }
+    let replacer = replacer.with_rules(&rule)?;
+
+    let new_path = if no_rename_dir {
+        existing.path.clone()
+    } else {
+        destination.unwrap_or_else(|| {
+            InputDir::Absolute(
+                existing
+                    .path
+                    .parent()
+                    .map(|parent| parent.to_path_buf())
+                    .unwrap_or_else(|| existing.path.clone())
+                    .join(&new),
+            )
+            .resolve_intended(&cwd)
+        })
+    };
+
+    // Of the names that normalize to the same one, only a change of case is
+    // a rename in its own right. On a case-insensitive filesystem the new
+    // path then already "exists", as the project itself, and is moved to
+    // through a temporary name.
+    let recase =
+        *old_name != new && old_name.to_lowercase() == new.to_lowercase();
+    let same_entry = !no_rename_dir
+        && existing.path != new_path
+        && relocate::is_same_entry(&existing.path, &new_path);
+    let strategy = if same_entry {
+        Strategy::Rename
+    } else {
+        strategy
+    };
+    if no_rename_dir {
+        // Only the contents and the repository are renamed.
+    } else if existing.path == new_path {
+        println!("New path is the same as current path; nothing to do.");
+        return Ok(());
+    } else if new_path.parent() == existing.path.parent()
+        && !recase
+        && names::normalize(old_name) == names::normalize(&new)
+    {
+        println!(
+            "{new:?} is the same name as {old_name:?} once normalized; nothing to do."
+        );
+        return Ok(());
+    } else if new_path.exists() && !same_entry {
+        bail!("{} already exists!", new_path.display());
+    } else if new_path.starts_with(&existing.path) {
+        bail!(
+            "Cannot move {} into itself ({}).",
+            existing.path.display(),
+            new_path.display()
+        );
+    }
+    if let Some(parent) = new_path.parent() {
+        if !parents && !parent.is_dir() {
+            bail!(
+                "{} does not exist; pass --parents to create it.",
+                parent.display()
+            );
+        }
+    }
+
+    if names::looks_like_typo(old_name, &new) {
+        if dry_run {
+            println!("Warning: {new:?} looks like a typo of {old_name:?}.");
+        } else if !yes
+            && !confirm(
+                format_args!(
+                    "{new:?} looks like a typo of {old_name:?}. Continue?"
+                ),
+                "--yes",
+            )?
+        {
+            println!("Aborted: nothing was changed.");
+            return Ok(());
+        }
+    }
+
+    #[cfg(not(unix))]
+    if let Some(link) = existing.symlink.as_ref().filter(|_| !no_rename_dir) {
+        bail!(
+            "{} is a symlink to {}; moving the target would leave the link dangling. Pass the real directory instead.",
+            link.display(),
+            existing.path.display()
+        );
+    }
+
+    let cores = available_parallelism().map_or(1, |n_cores| n_cores.get());
+    let tuned = concurrency_autotune
+        .then(|| autotune::tune(&filter, cores, &cancelled));
+    let threads = threads
+        .or(tuned.as_ref().map(|tuned| tuned.threads))
+        .unwrap_or(cores);
+    let io_concurrency = io_concurrency
+        .or(tuned.map(|tuned| tuned.io_concurrency))
+        .unwrap_or(threads.min(8));
+    let max_open_files = max_open_files.unwrap_or_else(pool::max_open_files);
+    let opts = RewriteOpts {
+        idempotent,
+        strict,
+        max_total_replacements,
+        verbose,
+        format,
+        show_lines,
+        report_skips,
+        report_unchanged,
+        dedupe_output,
+        group_by_dir,
+        scan_all,
+        skip_generated,
+        comments_only,
+        mime_allow: mime_allow.into(),
+        detect: detect_cmd
+            .map(|cmd| DetectCmd::new(&cmd).map(Arc::new))
+            .transpose()?,
+        threads,
+        io: Semaphore::new(io_concurrency),
+        open_files: Arc::new(Semaphore::new(max_open_files)),
+        encoding,
+        eol: normalize_eol,
+        buffer_writes,
+        failures: Mutex::new(Vec::new()),
+        filter,
+        cancelled,
+        audit,
+        diffstat: DiffStat::default(),
+    };
+    let mut timings = Timings::new(show_timings);
+    let started = Instant::now();
+    let mut rewrites = if manifest_only {
+        manifest::plan_manifest_rewrites(&existing.path, &to)?
+    } else {
+        find_and_replace_in_dir(existing.path.clone(), &replacer, &opts)?
+    };
+    // The collector's reporter is gone by now; the passes over the files it
+    // found report through one of their own.
+    let mut reporter = Reporter::new(verbose, format);
+    if replace_json_keys && !manifest_only {
+        manifest::rewrite_json_structurally(
+            &existing.path,
+            &replacer,
+            &mut rewrites,
+            &mut reporter,
+        )?;
+    }
+    if env_aware && !manifest_only {
+        envfile::rewrite_env_files(
+            &existing.path,
+            &replacer,
+            &opts.filter,
+            &mut rewrites,
+            &mut reporter,
+        )?;
+    }
+    reporter.finish()?;
+    manifest::apply_field_sets(&existing.path, &set, &mut rewrites)?;
+    if !template_file.is_empty() {
+        let owner = match &remote {
+            Some(remote) => Some(remote.owner.clone()),
+            None => forge::Remote::resolve(&sh, &existing.path, "origin")
+                .ok()
+                .map(|origin| origin.owner),
+        };
+        let vars = templates::Vars {
+            old: &from,
+            new: &to,
+            owner: owner.as_deref(),
+        };
+        templates::apply_templates(
+            &existing.path,
+            &template_file,
+            &vars,
+            &opts.filter,
+            &mut rewrites,
+        )?;
+    }
+    if !allow_outside {
+        rewrites = drop_outside(&existing.path, rewrites);
+    }
+    // The submodules' paths and URLs are updated through git. Being a
+    // dotfile, `.gitmodules` is never rewritten as text, which would also
+    // rename the submodules themselves, which git knows them by.
+    let submodules = submodules::list(&sh, &existing.path)?;
+    let url_updates = submodules::plan_url_updates(&submodules, &replacer);
+    let path_renames = if everywhere {
+        let renames = renames::plan_path_renames(
+            &replacer,
+            &opts.filter,
+            rename_max_depth,
+        )?;
+        renames::check_collisions(&renames)?;
+        renames
+    } else {
+        Vec::new()
+    };
+    let (submodule_renames, path_renames) =
+        split_submodule_renames(path_renames, &submodules, &existing.path);
+    let enclosing = if no_rename_dir {
+        None
+    } else {
+        submodules::enclosing(&sh, &existing.path)?
+    };
+    let binary_rewrites = if binary_same_length && !manifest_only {
+        binaries::plan(&existing.path, &from, &to, &opts)?
+    } else {
+        Vec::new()
+    };
+    let tag_moves = match &rename_tags {
+        Some(rename) => tags::plan(&sh, &existing.path, rename)?,
+        None => Vec::new(),
+    };
+    let push_tags = push_tags.then(|| {
+        remote
+            .as_ref()
+            .map_or("origin", |remote| remote.name.as_str())
+    });
+    if rollback_on_gh_failure {
+        let combined = if gh_async {
+            Some("--gh-async")
+        } else if ignore_gh_errors {
+            Some("--ignore-gh-errors")
+        } else if strategy == Strategy::Copy {
+            Some("--strategy copy")
+        } else if !submodule_renames.is_empty() || !url_updates.is_empty() {
+            Some("changes to submodules")
+        } else if enclosing.is_some() {
+            Some("moving a project tracked by an enclosing repository")
+        } else {
+            None
+        };
+        if let Some(combined) = combined {
+            bail!(
+                "--rollback-on-gh-failure cannot be combined with {combined}."
+            );
+        }
+    }
+    timings.record("walk", started);
+    report_styles(&replacer, styled.as_deref(), &from, &to);
+    #[cfg(feature = "review")]
+    let rewrites = if args.review {
+        match review::review(&existing.path, rewrites, opts.encoding)? {
+            Some(rewrites) => rewrites,
+            None => {
+                println!("Aborted: nothing was changed.");
+                return Ok(());
+            }
+        }
+    } else {
+        rewrites
+    };
+
+    if count_by_ext || verbose {
+        print_count_by_ext(&rewrites);
+    }
+    if check {
+        timings.report();
+        if report_check(&from, &rewrites, &path_renames) {
+            process::exit(EXIT_WOULD_CHANGE);
+        }
+        return report_failures(opts.failures.into_inner().unwrap());
+    }
+    // Run a second time, a rename would still move the directory and
+    // rename the repository, though nothing refers to the old name.
+    // Only what was found in the tree counts: the directory's own name
+    // mentions the old name whenever that is where it came from.
+    let mentioned = !rewrites.is_empty()
+        || !binary_rewrites.is_empty()
+        || !path_renames.is_empty()
+        || !submodule_renames.is_empty()
+        || !url_updates.is_empty()
+        || !tag_moves.is_empty()
+        || !opts.failures.lock().unwrap().is_empty();
+    if !mentioned && !force {
+        println!(
+            "Nothing in {} mentions {from:?}, so it looks renamed already: nothing was changed (pass --force to go ahead regardless).",
+            existing.path.display()
+        );
+        return Ok(());
+    }
+
+    if dry_run {
+        for rw in &rewrites {
+            if format == Format::Jsonl {
+                report::emit(serde_json::json!({
+                    "path": rw.path,
+                    "count": rw.count,
+                    "status": "planned",
+                }));
+            } else if show_lines {
+                println!(
+                    "Would rewrite {} ({} replacement(s)) on line(s) {}.",
+                    rw.path.display(),
+                    rw.count,
+                    format_lines(&rw.lines)
+                );
+            } else {
+                println!(
+                    "Would rewrite {} ({} replacement(s)).",
+                    rw.path.display(),
+                    rw.count
+                );
+            }
+        }
+        binaries::print_rewrites(&binary_rewrites);
+        renames::print_path_renames(&path_renames);
+        renames::print_path_renames(&submodule_renames);
+        submodules::print_url_updates(&url_updates);
+        tags::print_moves(&tag_moves, push_tags);
+        if let Some(enclosing) = &enclosing {
+            println!(
+                "Would move {} to {} with git mv, as a submodule of {}.",
+                existing.path.display(),
+                new_path.display(),
+                enclosing.top.display()
+            );
+        } else if !no_rename_dir {
+            println!(
+                "Would move {} to {}.",
+                existing.path.display(),
+                new_path.display()
+            );
+        }
+        if rename_remote {
+            forge::precheck(
+                &sh,
+                &existing.path,
+                forge,
+                remote.as_ref(),
+                &repo_name,
+            );
+        }
+        if let Some(json_plan) = json_plan {
+            let unplannable = [
+                (!binary_rewrites.is_empty(), "rewrite binary files"),
+                (!submodule_renames.is_empty(), "rename submodules"),
+                (!url_updates.is_empty(), "update submodule URLs"),
+                (!tag_moves.is_empty(), "move tags"),
+                (enclosing.is_some(), "move a submodule with git mv"),
+                (stage, "stage the changes (--stage)"),
+                (fmt, "format the project (--fmt)"),
+                (rename_map.is_some(), "write a rename map (--rename-map)"),
+                (strategy == Strategy::Copy, "copy the project (--strategy)"),
+            ];
+            let unplannable: Vec<&str> = unplannable
+                .iter()
+                .filter(|(applies, _)| *applies)
+                .map(|(_, step)| *step)
+                .collect();
+            if !unplannable.is_empty() {
+                bail!(
+                    "Cannot write a plan: it has no room to {}.",
+                    unplannable.join(", or ")
+                );
+            }
+            let forge_step = rename_remote.then(|| {
+                let (program, args) = forge.rename_command(
+                    &repo_name,
+                    remote.as_ref(),
+                    &forge_arg,
+                );
+                plan::ForgeStep {
+                    program: program.to_string(),
+                    args,
+                    remote: remote.as_ref().map(|r| r.name.clone()),
+                    remote_url: remote
+                        .as_ref()
+                        .and_then(|r| r.renamed_url(&repo_name)),
+                }
+            });
+            plan::Plan::new(
+                &existing.path,
+                &new_path,
+                opts.encoding,
+                &rewrites,
+                &path_renames,
+                forge_step,
+            )?
+            .write(&json_plan)?;
+        }
+        timings.report();
+        let pending = !no_rename_dir
+            || rename_remote
+            || !rewrites.is_empty()
+            || !binary_rewrites.is_empty()
+            || !path_renames.is_empty()
+            || !submodule_renames.is_empty()
+            || !url_updates.is_empty()
+            || !tag_moves.is_empty();
+        opts.emit(|| {
+            serde_json::json!({
+                "status": "summary",
+                "planned": rewrites.len() + binary_rewrites.len(),
+                "failed": opts.failures.lock().unwrap().len(),
+            })
+        });
+        report_failures(opts.failures.into_inner().unwrap())?;
+        if dry_run_exit_code && pending {
+            process::exit(EXIT_CHANGES_PENDING);
+        }
+        return Ok(());
+    }
+
+    if !keep_going && !opts.failures.lock().unwrap().is_empty() {
+        println!(
+            "Not moving {}, since some of its files could not be read (pass --keep-going to move it regardless).",
+            existing.path.display()
+        );
+        return report_failures(opts.failures.into_inner().unwrap());
+    }
+
+    if opts.is_cancelled() {
+        bail!("Cancelled: nothing was changed.");
+    }
+    let to_rewrite = rewrites.len() + binary_rewrites.len();
+    let question = if no_rename_dir {
+        format!(
+            "Rewrite {to_rewrite} file(s) in {}?",
+            existing.path.display()
+        )
+    } else {
+        format!(
+            "Move {} to {} and rewrite {to_rewrite} file(s)?",
+            existing.path.display(),
+            new_path.display(),
+        )
+    };
+    if !yes && !confirm(format_args!("{question}"), "--yes")? {
+        println!("Aborted: nothing was changed (pass --yes to skip asking).");
+        return Ok(());
+    }
+    let rewrites = match replace_count_threshold {
+        Some(threshold) => confirm_large_rewrites(rewrites, threshold, yes)?,
+        None => rewrites,
+    };
+
+    if !no_rename_dir {
+        println!(
+            "Moving from {} to {}.",
+            existing.path.display(),
+            new_path.display()
+        );
+    }
+    if opts.is_cancelled() {
+        bail!("Cancelled: nothing was changed.");
+    }
+    if !no_rename_dir {
+        let started = Instant::now();
+        if let Some(enclosing) = &enclosing {
+            if parents {
+                create_parent(&new_path)?;
+            }
+            submodules::move_enclosed(&sh, enclosing, &new_path, &replacer)?;
+        } else {
+            move_project(
+                &existing,
+                &new_path,
+                parents,
+                strategy,
+                deref_symlinks,
+                trash,
+                yes,
+            )?;
+        }
+        audit::record(
+            opts.audit.as_ref(),
+            "move",
+            serde_json::json!({ "from": existing.path, "to": new_path }),
+        )?;
+        timings.record("move", started);
+    }
+
+    let backup = if rollback_on_gh_failure {
+        let paths = rewrites.iter().map(|rw| rw.path.as_path());
+        let binary_paths = binary_rewrites.iter().map(|rw| rw.path.as_path());
+        Some(rollback::Backup::take(
+            &new_path,
+            paths.chain(binary_paths),
+        )?)
+    } else {
+        None
+    };
+    let started = Instant::now();
+    let mut written = apply_rewrites(&new_path, rewrites, &opts)?;
+    written.extend(binaries::apply(&new_path, binary_rewrites, &opts)?);
+    // Contents first, so that renaming the paths inside the project cannot
+    // pull a planned rewrite's file out from under it.
+    let path_renames = renames::rebase(path_renames, &existing.path, &new_path);
+    renames::apply_path_renames(&path_renames, opts.audit.as_ref())?;
+    let submodule_renames =
+        renames::rebase(submodule_renames, &existing.path, &new_path);
+    submodules::apply(&sh, &new_path, &submodule_renames, &url_updates)?;
+    timings.record("rewrite", started);
+    if strategy == Strategy::Copy && !no_rename_dir && enclosing.is_none() {
+        // Only now that the copy is known to be complete is the original
+        // given up.
+        let failed = opts.failures.lock().unwrap().len();
+        if failed == 0 {
+            relocate::remove_original(&existing.path, &new_path, trash, yes)?;
+        } else {
+            println!(
+                "Left the original {} in place, since {failed} file(s) in the copy could not be rewritten.",
+                existing.path.display()
+            );
+        }
+    }
+    let target = if rename_remote {
+        forge::check_target(&sh, &new_path, forge, remote.as_ref())?
+    } else {
+        None
+    };
+    let rename_now = |timings: &mut Timings| -> anyhow::Result<bool> {
+        if !confirm_repo_rename(
+            forge,
+            target.as_deref(),
+            &repo_name,
+            remote.as_ref(),
+            &forge_arg,
+            confirm_remote,
+            opts.audit.as_ref(),
+        )? {
+            return Ok(false);
+        }
+        let started = Instant::now();
+        let failed = !forge::rename_repo(
+            &sh,
+            forge,
+            &repo_name,
+            remote.as_ref(),
+            &forge_arg,
+            quiet_gh,
+        );
+        timings.record("forge", started);
+        Ok(failed)
+    };
+    let mut forge_failed = false;
+    let renamed_early = rename_remote && rollback_on_gh_failure;
+    if renamed_early {
+        sh.change_dir(&new_path);
+        forge_failed = rename_now(&mut timings)?;
+    }
+    if let (true, Some(backup)) = (forge_failed, backup) {
+        backup.restore(
+            &existing.path,
+            &new_path,
+            &path_renames,
+            opts.audit.as_ref(),
+        )?;
+        #[cfg(unix)]
+        if let Some(link) = &existing.symlink {
+            repoint_symlink(link, &existing.path)?;
+        }
+        timings.report();
+        report_failures(opts.failures.into_inner().unwrap())?;
+        process::exit(EXIT_FORGE_FAILED);
+    }
+    if let Some(file) = &rename_map {
+        renames::write_rename_map(
+            file,
+            &existing.path,
+            &new_path,
+            &path_renames,
+        )?;
+    }
+    let background_rename = if rename_remote
+        && gh_async
+        && confirm_repo_rename(
+            forge,
+            target.as_deref(),
+            &repo_name,
+            remote.as_ref(),
+            &forge_arg,
+            confirm_remote,
+            opts.audit.as_ref(),
+        )? {
+        let (forge_sh, repo_name, remote, forge_arg) = (
+            sh.clone(),
+            repo_name.clone(),
+            remote.clone(),
+            forge_arg.clone(),
+        );
+        forge_sh.change_dir(&new_path);
+        println!("Renaming the {forge} repository in the background.");
+        let started = Instant::now();
+        Some((
+            started,
+            thread::spawn(move || {
+                forge::rename_repo(
+                    &forge_sh,
+                    forge,
+                    &repo_name,
+                    remote.as_ref(),
+                    &forge_arg,
+                    quiet_gh,
+                )
+            }),
+        ))
+    } else {
+        None
+    };
+    if fmt {
+        let mut reporter = Reporter::new(verbose, format);
+        manifest::format_project(&sh, &new_path, &mut reporter)?;
+        reporter.finish()?;
+    }
+    if stage {
+        let relative = |path: &Path| {
+            path.strip_prefix(&new_path).unwrap_or(path).to_path_buf()
+        };
+        // Renamed paths are staged as removed from where they were and added
+        // where they are now, so that git can pair the two up.
+        let removed: Vec<PathBuf> =
+            path_renames.iter().map(|rn| relative(&rn.from)).collect();
+        let mut changed: Vec<PathBuf> = written
+            .iter()
+            .chain(&removed)
+            .map(|path| {
+                relative(&renames::renamed(&path_renames, &new_path.join(path)))
+            })
+            .collect();
+        changed.sort();
+        changed.dedup();
+        git::stage_changes(&sh, &existing.path, &new_path, &changed, &removed)?;
+    }
+    tags::apply(
+        &sh,
+        &new_path,
+        &tag_moves,
+        push_tags,
+        yes,
+        opts.audit.as_ref(),
+    )?;
+
+    sh.change_dir(&new_path);
+    if let Some((started, rename)) = background_rename {
+        forge_failed = !rename.join().unwrap_or_else(|panic| {
+            println!(
+                "Error renaming the {forge} repository: {}",
+                panic_message(&panic)
+            );
+            false
+        });
+        timings.record("forge", started);
+    } else if rename_remote && !gh_async && !renamed_early {
+        forge_failed = rename_now(&mut timings)?;
+    }
+
+    timings.report();
+    if opts.format == Format::Text {
+        println!("{}.", opts.diffstat);
+    }
+    opts.emit(|| {
+        serde_json::json!({
+            "status": "summary",
+            "rewritten": written.len(),
+            "added_bytes": opts.diffstat.added(),
+            "removed_bytes": opts.diffstat.removed(),
+            "failed": opts.failures.lock().unwrap().len(),
+            "forge_failed": forge_failed,
+        })
+    });
+    let failures = opts.failures.into_inner().unwrap();
+    if forge_failed && ignore_gh_errors {
+        println!(
+            "Carrying on regardless of the failed repository rename (--ignore-gh-errors)."
+        );
+    } else if forge_failed && failures.is_empty() {
+        process::exit(EXIT_FORGE_FAILED);
+    }
+    report_failures(failures)
+}
+
+/// Lists the ways of writing `from` (its `styled` variants, when it and `to`
+/// are written in different styles) that `replacer` has found in the
+/// project, each of which is replaced in its own style.
+fn report_styles(
+    replacer: &Replacer,
+    styled: Option<&[(String, String)]>,
+    from: &str,
+    to: &str,
+) {
+    let Some(styled) = styled else {
+        return;
+    };
+    let listing: Vec<String> = replacer
+        .used_names()
+        .into_iter()
+        .filter(|&(old, new)| {
+            styled.iter().any(|v| (&*v.0, &*v.1) == (old, new))
+        })
+        .map(|(old, new)| format!("{old} -> {new}"))
+        .collect();
+    if listing.is_empty() {
+        return;
+    }
+    println!(
+        "{from:?} and {to:?} are written in different styles, so each occurrence is replaced in its own style: {} (pass --no-auto-style to only replace {from:?} as it is).",
+        listing.join(", ")
+    );
+}
+
+/// Asks whether to rename the repository (`target`, if it is known which it
+/// is) unless `confirm_remote` already said so, recording the command in the
+/// audit log if it is to go ahead.
+fn confirm_repo_rename(
+    forge: Forge,
+    target: Option<&str>,
+    repo_name: &str,
+    remote: Option<&forge::Remote>,
+    forge_arg: &[String],
+    confirm_remote: bool,
+    audit: Option<&AuditLog>,
+) -> anyhow::Result<bool> {
+    let target = target.map(|t| format!(" {t}")).unwrap_or_default();
+    if !confirm_remote
+        && !confirm(
+            format_args!(
+                "Rename the {forge} repository{target} to {repo_name}?"
+            ),
+            "--confirm-remote",
+        )?
+    {
+        println!(
+            "Not renaming the {forge} repository; it can still be renamed to {repo_name} by hand."
+        );
+        return Ok(false);
+    }
+    let (program, args) = forge.rename_command(repo_name, remote, forge_arg);
+    audit::record(
+        audit,
+        "forge",
+        serde_json::json!({ "program": program, "args": args }),
+    )?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn plan(dir: &TempDir, from: &str, to: &str) -> Vec<Rewrite> {
+        let replacer = Replacer::new(from, to, Mode::Literal).unwrap();
+        let opts =
+            RewriteOpts::plain(WalkFilter::new(dir.path(), &[]).unwrap());
+        let mut rewrites =
+            find_and_replace_in_dir(dir.path().to_path_buf(), &replacer, &opts)
+                .unwrap();
+        rewrites.sort_by(|a, b| a.path.cmp(&b.path));
+        rewrites
+    }
+
+    /// Fails (or panics) on every write, like stdout going away mid-run.
+    #[cfg(not(feature = "trace"))]
+    struct BrokenWriter {
+        panics: bool,
+    }
+
+    #[cfg(not(feature = "trace"))]
+    impl io::Write for BrokenWriter {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            if self.panics {
+                panic!("the writer broke");
+            }
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.write(&[]).map(drop)
+        }
+    }
+
+    /// Enough files that the collector's output overflows its buffer, and
+    /// the walker fills the channels, before the walk is done.
+    #[cfg(not(feature = "trace"))]
+    fn many_files() -> TempDir {
+        let dir = TempDir::new();
+        for i in 0..1000 {
+            dir.write(&format!("dir{}/file{i}.txt", i % 10), "old-name");
+        }
+        dir
+    }
+
+    #[cfg(not(feature = "trace"))]
+    #[test]
+    fn a_failing_writer_ends_the_walk_with_an_error() {
+        let dir = many_files();
+        let replacer =
+            Replacer::new("old-name", "new-name", Mode::Literal).unwrap();
+        let opts =
+            RewriteOpts::plain(WalkFilter::new(dir.path(), &[]).unwrap());
+        let reporter = Reporter::with_writer(
+            false,
+            Format::Text,
+            BrokenWriter { panics: false },
+        );
+        let err = find_and_replace_reporting(
+            dir.path().to_path_buf(),
+            &replacer,
+            &opts,
+            reporter,
+        )
+        .err()
+        .expect("the walk should fail");
+        assert!(
+            format!("{err:#}").contains("while reporting on the files found"),
+            "{err:#}"
+        );
+    }
+
+    #[cfg(not(feature = "trace"))]
+    #[test]
+    fn a_panicking_writer_ends_the_walk_with_an_error() {
+        let dir = many_files();
+        let replacer =
+            Replacer::new("old-name", "new-name", Mode::Literal).unwrap();
+        let opts =
+            RewriteOpts::plain(WalkFilter::new(dir.path(), &[]).unwrap());
+        let reporter = Reporter::with_writer(
+            false,
+            Format::Text,
+            BrokenWriter { panics: true },
+        );
+        let err = find_and_replace_reporting(
+            dir.path().to_path_buf(),
+            &replacer,
+            &opts,
+            reporter,
+        )
+        .err()
+        .expect("the walk should fail");
+        assert_eq!(
+            err.to_string(),
+            "The collector thread panicked: the writer broke"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rewritten_scripts_stay_executable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new();
+        for buffer_writes in [false, true] {
+            let script = dir.write("run.sh", "#!/bin/sh\necho old-name\n");
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755))
+                .unwrap();
+            let rewrites = plan(&dir, "old-name", "new-name");
+            let opts = RewriteOpts {
+                buffer_writes,
+                ..RewriteOpts::plain(WalkFilter::new(dir.path(), &[]).unwrap())
+            };
+            apply_rewrites(dir.path(), rewrites, &opts).unwrap();
+            assert_eq!(
+                fs::read_to_string(&script).unwrap(),
+                "#!/bin/sh\necho new-name\n"
+            );
+            let mode = fs::metadata(&script).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755, "buffer_writes: {buffer_writes}");
+        }
+    }
+
+    #[test]
+    fn rewrites_keep_how_files_end() {
+        let dir = TempDir::new();
+        dir.write("bare.txt", "old-name\nold-name");
+        dir.write("lf.txt", "old-name\n");
+        dir.write("crlf.txt", "old-name\r\nold-name\r\n");
+        let rewrites = plan(&dir, "old-name", "new-name");
+        let contents: Vec<_> =
+            rewrites.iter().map(|rw| rw.contents.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                "new-name\nnew-name",
+                "new-name\r\nnew-name\r\n",
+                "new-name\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn rewrites_only_differ_by_their_substitutions() {
+        let dir = TempDir::new();
+        let original = "a old-name b\n\n  old-name  \n";
+        dir.write("file.txt", original);
+        let rewrites = plan(&dir, "old-name", "a-much-longer-name");
+        let replacer =
+            Replacer::new("old-name", "a-much-longer-name", Mode::Literal)
+                .unwrap();
+        let (_, substitutions) = replacer.replace_at(original);
+        let fp = dir.path().join("file.txt");
+        check_only_substitutions(
+            &fp,
+            original,
+            &rewrites[0].contents,
+            &substitutions,
+        )
+        .unwrap();
+        let tampered = rewrites[0].contents.trim_end();
+        assert!(check_only_substitutions(
+            &fp,
+            original,
+            tampered,
+            &substitutions
+        )
+        .is_err());
+    }
+
+    /// The plan `pmv` makes for renaming the project `old` in `dir` (whose
+    /// one file mentions both its name and `widget`) to `new`, with `args`.
+    fn plan_rename(
+        dir: &TempDir,
+        old: &str,
+        new: &str,
+        args: &[&str],
+    ) -> anyhow::Result<serde_json::Value> {
+        dir.write(&format!("{old}/src/lib.rs"), format!("{old} uses widget\n"));
+        let project = dir.path().join(old);
+        let plan = dir.path().join("plan.json");
+        let mut cli_args = vec![
+            project.to_str().unwrap(),
+            new,
+            "--forge",
+            "gitea",
+            "--json-plan",
+            plan.to_str().unwrap(),
+        ];
+        cli_args.extend_from_slice(args);
+        let Cli::Rename(pmv) = cli().run_inner(&cli_args[..]).unwrap() else {
+            panic!("not parsed as a rename");
+        };
+        rename(pmv, Arc::default())?;
+        Ok(serde_json::from_str(&fs::read_to_string(plan)?)?)
+    }
+
+    /// The plan for renaming `old-name` to `new-name`, with `args`.
+    fn planned(args: &[&str]) -> (PathBuf, serde_json::Value) {
+        let dir = TempDir::new();
+        let plan = plan_rename(&dir, "old-name", "new-name", args).unwrap();
+        (dir.path().join("old-name"), plan)
+    }
+
+    fn destination(plan: &serde_json::Value) -> &str {
+        plan["destination"].as_str().unwrap()
+    }
+
+    fn contents(plan: &serde_json::Value) -> &str {
+        plan["rewrites"][0]["contents"].as_str().unwrap()
+    }
+
+    /// The name the repository would be renamed to, as given to `tea`.
+    fn repo_name(plan: &serde_json::Value) -> &str {
+        let args = plan["forge"]["args"].as_array().unwrap();
+        let name = args.iter().position(|arg| arg == "--name").unwrap();
+        args[name + 1].as_str().unwrap()
+    }
+
+    #[test]
+    fn new_name_renames_all_three_names() {
+        let (project, plan) = planned(&[]);
+        let new_path = project.with_file_name("new-name");
+        assert_eq!(destination(&plan), new_path.to_str().unwrap());
+        assert_eq!(contents(&plan), "new-name uses widget\n");
+        assert_eq!(repo_name(&plan), "new-name");
+    }
+
+    #[test]
+    fn from_and_to_only_change_what_contents_are_rewritten() {
+        let (project, plan) = planned(&["--from", "widget", "--to", "gadget"]);
+        let new_path = project.with_file_name("new-name");
+        assert_eq!(destination(&plan), new_path.to_str().unwrap());
+        assert_eq!(contents(&plan), "old-name uses gadget\n");
+        assert_eq!(repo_name(&plan), "new-name");
+    }
+
+    #[test]
+    fn to_alone_keeps_replacing_the_directory_name() {
+        let (_, plan) = planned(&["--to", "other-name"]);
+        assert_eq!(contents(&plan), "other-name uses widget\n");
+        assert_eq!(repo_name(&plan), "new-name");
+    }
+
+    #[test]
+    fn repo_name_only_changes_the_repository_name() {
+        let (project, plan) = planned(&["--repo-name", "new-repo"]);
+        let new_path = project.with_file_name("new-name");
+        assert_eq!(destination(&plan), new_path.to_str().unwrap());
+        assert_eq!(contents(&plan), "new-name uses widget\n");
+        assert_eq!(repo_name(&plan), "new-repo");
+    }
+
+    #[test]
+    fn no_rename_dir_leaves_the_directory_where_it_is() {
+        let (project, plan) =
+            planned(&["--no-rename-dir", "--repo-name", "new-repo"]);
+        assert_eq!(destination(&plan), project.to_str().unwrap());
+        assert_eq!(contents(&plan), "new-name uses widget\n");
+        assert_eq!(repo_name(&plan), "new-repo");
+    }
+
+    /// Writes a plan renaming `old-name` (mentioned in `src/lib.rs`) to
+    /// `new-name` in `dir`, without a repository to rename.
+    fn plan_without_forge(dir: &TempDir, args: &[&str]) -> PathBuf {
+        dir.write("old-name/src/lib.rs", "old-name uses widget\n");
+        let project = dir.path().join("old-name");
+        let plan = dir.path().join("plan.json");
+        let mut cli_args = vec![
+            project.to_str().unwrap(),
+            "new-name",
+            "--json-plan",
+            plan.to_str().unwrap(),
+        ];
+        cli_args.extend_from_slice(args);
+        let Cli::Rename(pmv) = cli().run_inner(&cli_args[..]).unwrap() else {
+            panic!("not parsed as a rename");
+        };
+        rename(pmv, Arc::default()).unwrap();
+        plan
+    }
+
+    #[test]
+    fn a_plan_is_carried_out_as_it_was_made() {
+        let dir = TempDir::new();
+        dir.write("old-name/old-name.txt", "");
+        let plan = plan_without_forge(&dir, &["--everywhere"]);
+        assert!(dir.path().join("old-name/old-name.txt").exists());
+        plan::apply(&Shell::new().unwrap(), &plan, true).unwrap();
+        assert!(!dir.path().join("old-name").exists());
+        let lib = dir.path().join("new-name/src/lib.rs");
+        assert_eq!(fs::read_to_string(lib).unwrap(), "new-name uses widget\n");
+        assert!(dir.path().join("new-name/new-name.txt").exists());
+    }
+
+    #[test]
+    fn a_plan_made_with_no_rename_dir_can_be_carried_out() {
+        let dir = TempDir::new();
+        let plan = plan_without_forge(&dir, &["--no-rename-dir"]);
+        plan::apply(&Shell::new().unwrap(), &plan, true).unwrap();
+        let lib = dir.path().join("old-name/src/lib.rs");
+        assert_eq!(fs::read_to_string(lib).unwrap(), "new-name uses widget\n");
+    }
+
+    #[test]
+    fn a_plan_is_refused_once_its_files_have_changed() {
+        let dir = TempDir::new();
+        let path = plan_without_forge(&dir, &[]);
+        dir.write("old-name/src/lib.rs", "old-name, edited\n");
+        let args = ["apply", "--plan", path.to_str().unwrap()];
+        let Cli::Apply { verify_plan, plan } =
+            cli().run_inner(&args[..]).unwrap()
+        else {
+            panic!("not parsed as apply");
+        };
+        let err = plan::apply(&Shell::new().unwrap(), &plan, verify_plan)
+            .unwrap_err();
+        assert!(err.to_string().contains("has changed"), "{err}");
+        assert!(dir.path().join("old-name").is_dir());
+    }
+
+    #[test]
+    fn steps_a_plan_cannot_record_are_refused() {
+        let dir = TempDir::new();
+        let err = plan_rename(&dir, "old-name", "new-name", &["--stage"])
+            .unwrap_err();
+        assert!(err.to_string().contains("--stage"), "{err}");
+        assert!(!dir.path().join("plan.json").exists());
+    }
+
+    #[test]
+    fn a_change_of_case_is_a_rename() {
+        let dir = TempDir::new();
+        let plan = plan_rename(&dir, "Old-Name", "old-name", &[]).unwrap();
+        let new_path = dir.path().join("old-name");
+        assert_eq!(destination(&plan), new_path.to_str().unwrap());
+    }
+
+    /// On a case-sensitive filesystem (as this one is taken to be), another
+    /// directory whose name only differs in case is in the way.
+    #[test]
+    fn a_case_only_collision_is_refused_when_case_matters() {
+        let dir = TempDir::new();
+        dir.write("old-name/README", "");
+        let err = plan_rename(&dir, "Old-Name", "old-name", &[]).unwrap_err();
+        assert!(format!("{err:#}").contains("already exists"), "{err:#}");
+    }
+
+    /// Renames the project `old` in `dir` to `new` for real, with `args`.
+    fn run_rename(dir: &TempDir, old: &str, new: &str, args: &[&str]) {
+        let project = dir.path().join(old);
+        let mut cli_args = vec![project.to_str().unwrap(), new, "--yes"];
+        cli_args.extend_from_slice(args);
+        let Cli::Rename(pmv) = cli().run_inner(&cli_args[..]).unwrap() else {
+            panic!("not parsed as a rename");
+        };
+        rename(pmv, Arc::default()).unwrap();
+    }
+
+    /// Writes the audit log `log` of a run renaming `old-name` to `new-name`
+    /// that was interrupted after moving the project and rewriting `done`.
+    fn interrupted_run(dir: &TempDir, log: &Path, done: &Path) {
+        let run = audit::Run {
+            dir: dir.path().join("old-name"),
+            old_name: "old-name".into(),
+            new_name: "new-name".into(),
+            from: "old-name".into(),
+            to: "new-name".into(),
+            moved_to: None,
+        };
+        let audit = AuditLog::open(log).unwrap();
+        audit.record("start", run.fields()).unwrap();
+        let moved = serde_json::json!({
+            "from": run.dir,
+            "to": dir.path().join("new-name"),
+        });
+        audit.record("move", moved).unwrap();
+        audit
+            .record("rewrite", audit::rewrite_fields(done, 1))
+            .unwrap();
+    }
+
+    #[test]
+    fn resuming_a_moved_project_takes_the_names_from_the_log() {
+        let dir = TempDir::new();
+        // The rewrite of `done` was recorded, so it is not looked into again.
+        let done = dir.write("new-name/done.txt", "old-name, left\n");
+        dir.write("new-name/todo.txt", "old-name\n");
+        let log = dir.path().join("audit.jsonl");
+        interrupted_run(&dir, &log, &done);
+        run_rename(
+            &dir,
+            "new-name",
+            "new-name",
+            &["--resume", log.to_str().unwrap()],
+        );
+        let read = |path| fs::read_to_string(dir.path().join(path)).unwrap();
+        assert_eq!(read("new-name/todo.txt"), "new-name\n");
+        assert_eq!(read("new-name/done.txt"), "old-name, left\n");
+        assert!(!dir.path().join("old-name").exists());
+        let run = audit::last_run(&log).unwrap();
+        assert_eq!(run.old_name, "old-name");
+        assert_eq!(run.moved_to, Some(dir.path().join("new-name")));
+    }
+
+    #[test]
+    fn a_run_records_what_it_set_out_to_do() {
+        let dir = TempDir::new();
+        dir.write("old-name/lib.rs", "old-name\n");
+        let log = dir.path().join("audit.jsonl");
+        let args = ["--audit-log", log.to_str().unwrap(), "--to", "new_name"];
+        run_rename(&dir, "old-name", "new-name", &args);
+        let project = dir.path().canonicalize().unwrap().join("old-name");
+        assert_eq!(
+            audit::last_run(&log).unwrap(),
+            audit::Run {
+                moved_to: Some(project.with_file_name("new-name")),
+                dir: project,
+                old_name: "old-name".into(),
+                new_name: "new-name".into(),
+                from: "old-name".into(),
+                to: "new_name".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn resuming_with_other_names_or_without_them_is_refused() {
+        let dir = TempDir::new();
+        let done = dir.write("new-name/done.txt", "");
+        let log = dir.path().join("audit.jsonl");
+        interrupted_run(&dir, &log, &done);
+        let resume = |new: &str, log: &Path| {
+            let project = dir.path().join("new-name");
+            let args = [
+                project.to_str().unwrap(),
+                new,
+                "--yes",
+                "--resume",
+                log.to_str().unwrap(),
+            ];
+            let Cli::Rename(pmv) = cli().run_inner(&args[..]).unwrap() else {
+                panic!("not parsed as a rename");
+            };
+            rename(pmv, Arc::default()).unwrap_err().to_string()
+        };
+        let err = resume("other-name", &log);
+        assert!(err.contains("not \"other-name\""), "{err}");
+        let without = dir.write("other.jsonl", "");
+        let err = resume("new-name", &without);
+        assert!(err.contains("records no run"), "{err}");
+    }
+
+    #[test]
+    fn a_tree_that_no_longer_mentions_the_old_name_is_left_alone() {
+        let dir = TempDir::new();
+        dir.write("old-name/src/lib.rs", "new-name\n");
+        run_rename(&dir, "old-name", "new-name", &[]);
+        assert!(dir.path().join("old-name").is_dir());
+        assert!(!dir.path().join("new-name").exists());
+    }
+
+    #[test]
+    fn force_renames_a_tree_that_no_longer_mentions_the_old_name() {
+        let dir = TempDir::new();
+        dir.write("old-name/src/lib.rs", "new-name\n");
+        run_rename(&dir, "old-name", "new-name", &["--force"]);
+        assert!(dir.path().join("new-name/src/lib.rs").is_file());
+    }
+
+    #[test]
+    fn a_path_mentioning_the_old_name_is_enough_to_rename() {
+        let dir = TempDir::new();
+        dir.write("old-name/old-name.txt", "");
+        run_rename(&dir, "old-name", "new-name", &["--everywhere"]);
+        assert!(dir.path().join("new-name/new-name.txt").is_file());
+    }
+
+    #[test]
+    fn a_name_can_be_rewritten_in_another_style() {
+        let (_, plan) = planned(&["--to", "old_name"]);
+        assert_eq!(contents(&plan), "old_name uses widget\n");
+    }
+
+    #[test]
+    fn planned_edits_are_only_made_once_applied() {
+        let dir = TempDir::new();
+        dir.write("src/lib.rs", "old-name\n");
+        dir.write("README", "nothing to see\n");
+        let edits =
+            plan_replace(dir.path(), "old-name", "new-name", Mode::Literal)
+                .unwrap();
+        assert_eq!(edits.len(), 1);
+        let edit = &edits[0];
+        assert_eq!(edit.path, Path::new("src/lib.rs"));
+        assert_eq!(edit.original_len, 9);
+        assert_eq!(edit.new_contents, "new-name\n");
+        assert_eq!(edit.count, 1);
+        let lib = dir.path().join("src/lib.rs");
+        assert_eq!(fs::read_to_string(&lib).unwrap(), "old-name\n");
+
+        let written = apply_edits(dir.path(), edits).unwrap();
+        assert_eq!(written, [Path::new("src/lib.rs")]);
+        assert_eq!(fs::read_to_string(lib).unwrap(), "new-name\n");
+    }
+
+    #[test]
+    fn edits_to_files_changed_since_are_refused() {
+        let dir = TempDir::new();
+        dir.write("a.txt", "old-name\n");
+        dir.write("b.txt", "old-name\n");
+        let edits =
+            plan_replace(dir.path(), "old-name", "new-name", Mode::Literal)
+                .unwrap();
+        // The same size as before, so only the contents tell.
+        dir.write("b.txt", "new-name\n");
+        let err = apply_edits(dir.path(), edits).unwrap_err();
+        assert!(err.to_string().contains("b.txt"), "{err}");
+        let a = fs::read_to_string(dir.path().join("a.txt")).unwrap();
+        assert_eq!(a, "old-name\n");
+    }
+
+    #[test]
+    fn the_project_name_comes_from_its_manifest() {
+        let dir = TempDir::new();
+        assert_eq!(detect_project_name(dir.path()), None);
+        dir.write("package.json", r#"{ "name": "from-npm" }"#);
+        assert_eq!(detect_project_name(dir.path()).unwrap(), "from-npm");
+        dir.write("Cargo.toml", "[package]\nname = \"from-cargo\"\n");
+        assert_eq!(detect_project_name(dir.path()).unwrap(), "from-cargo");
+    }
+
+    #[test]
+    fn text_is_told_apart_by_name_and_contents() {
+        let dir = TempDir::new();
+        let opts = TextDetectOpts::default();
+        let is_text = |path: PathBuf| is_text_path(&path, &opts);
+        assert!(is_text(dir.write("src/lib.rs", "fn main() {}\n")));
+        assert!(is_text(dir.write("Makefile", "all:\n")));
+        assert!(!is_text(dir.write("logo.png", b"\x89PNG\r\n\x1a\n\0\0")));
+        assert!(!is_text(dir.write("empty.txt", "")));
+    }
+
+    #[test]
+    fn generated_and_minified_files_are_text_only_when_asked() {
+        let dir = TempDir::new();
+        let lock = dir.write("Cargo.lock", "version = 3\n");
+        let bundle = dir.write("bundle.js", "x".repeat(100_000));
+        let default = TextDetectOpts::default();
+        assert!(!is_text_path(&lock, &default));
+        assert!(!is_text_path(&bundle, &default));
+        let everything = TextDetectOpts {
+            scan_all: true,
+            skip_generated: false,
+            ..default
+        };
+        assert!(is_text_path(&lock, &everything));
+        assert!(is_text_path(&bundle, &everything));
+    }
+}
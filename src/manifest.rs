@@ -0,0 +1,567 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{bail, Context};
+use toml_edit::DocumentMut;
+use xshell::{cmd, Shell};
+
+use crate::{replace::Replacer, report::Reporter, Rewrite};
+
+/// The manifests a package name is read from (and written to), in order of
+/// preference, with the keys the name may be declared under.
+const MANIFESTS: &[(&str, &[&[&str]])] = &[
+    ("Cargo.toml", &[&["package", "name"]]),
+    ("package.json", &[&["name"]]),
+    (
+        "pyproject.toml",
+        &[&["project", "name"], &["tool", "poetry", "name"]],
+    ),
+];
+
+/// A parsed manifest, which can be written back out without disturbing its
+/// formatting (beyond what `serde_json` normalizes, for JSON).
+enum Document {
+    Toml(DocumentMut),
+    Json(serde_json::Value),
+}
+
+impl Document {
+    fn parse(file: &str, text: &str) -> anyhow::Result<Self> {
+        Ok(if file.ends_with(".json") {
+            Document::Json(serde_json::from_str(text)?)
+        } else {
+            Document::Toml(text.parse()?)
+        })
+    }
+
+    fn get(&self, keys: &[&str]) -> Option<&str> {
+        match self {
+            Document::Toml(doc) => keys
+                .iter()
+                .try_fold(doc.as_item(), |item, key| item.get(key))?
+                .as_str(),
+            Document::Json(doc) => keys
+                .iter()
+                .try_fold(doc, |value, key| value.get(key))?
+                .as_str(),
+        }
+    }
+
+    /// Sets the string at `keys` to `new`, adding it if the table (or
+    /// object) it belongs in exists. Returns whether it was set.
+    fn set(&mut self, keys: &[&str], new: &str) -> bool {
+        let Some((last, parents)) = keys.split_last() else {
+            return false;
+        };
+        match self {
+            Document::Toml(doc) => {
+                let parent = parents
+                    .iter()
+                    .try_fold(doc.as_item_mut(), |item, key| item.get_mut(key));
+                let Some(table) =
+                    parent.and_then(|item| item.as_table_like_mut())
+                else {
+                    return false;
+                };
+                match table.get_mut(last).and_then(|item| item.as_value_mut()) {
+                    Some(value) => {
+                        let decor = value.decor().clone();
+                        *value = new.into();
+                        *value.decor_mut() = decor;
+                    }
+                    None => {
+                        table.insert(last, toml_edit::value(new));
+                    }
+                }
+                true
+            }
+            Document::Json(doc) => {
+                let parent = parents
+                    .iter()
+                    .try_fold(doc, |value, key| value.get_mut(key));
+                match parent.and_then(|value| value.as_object_mut()) {
+                    Some(object) => {
+                        object.insert(last.to_string(), new.into());
+                        true
+                    }
+                    None => false,
+                }
+            }
+        }
+    }
+
+    fn to_text(&self, original: &str) -> anyhow::Result<String> {
+        Ok(match self {
+            Document::Toml(doc) => doc.to_string(),
+            Document::Json(doc) => {
+                let mut text = serde_json::to_string_pretty(doc)?;
+                if original.ends_with('\n') {
+                    text.push('\n');
+                }
+                text
+            }
+        })
+    }
+}
+
+fn read_manifest(
+    path: &Path,
+    file: &str,
+) -> anyhow::Result<(String, Document)> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Could not read {}", path.display()))?;
+    let doc = Document::parse(file, &text)
+        .with_context(|| format!("...while parsing {}", path.display()))?;
+    Ok((text, doc))
+}
+
+/// The nearest of `dir` and its ancestors that looks like the root of a
+/// project: it has a manifest (`Cargo.toml`, `package.json` or
+/// `pyproject.toml`) or a `.git`.
+pub fn find_root(dir: &Path) -> Option<&Path> {
+    dir.ancestors().find(|dir| {
+        dir.join(".git").exists()
+            || MANIFESTS.iter().any(|(file, _)| dir.join(file).is_file())
+    })
+}
+
+/// The package name declared in the project's manifest: `package.name` in
+/// `Cargo.toml`, `name` in `package.json`, or `project.name` (or Poetry's) in
+/// `pyproject.toml`, whichever is found first.
+pub fn package_name(dir: &Path) -> anyhow::Result<String> {
+    for (file, key_paths) in MANIFESTS {
+        let path = dir.join(file);
+        if !path.is_file() {
+            continue;
+        }
+        let (_, doc) = read_manifest(&path, file)?;
+        if let Some(name) = key_paths.iter().find_map(|keys| doc.get(keys)) {
+            return Ok(name.to_string());
+        }
+    }
+    bail!("Found no package name in a manifest in {}.", dir.display());
+}
+
+/// The project's canonical name, as declared in its manifest, if it has one
+/// that can be read: `package.name` in `Cargo.toml`, `name` in
+/// `package.json`, or `project.name` (or Poetry's) in `pyproject.toml`,
+/// whichever is found first.
+pub fn detect_project_name(dir: &Path) -> Option<String> {
+    package_name(dir).ok()
+}
+
+/// The (1-based) lines that differ between `old` and `new`.
+pub fn changed_lines(old: &str, new: &str) -> Vec<usize> {
+    let (mut old, mut new) = (old.lines(), new.lines());
+    let mut lines = Vec::new();
+    for n in 1.. {
+        match (old.next(), new.next()) {
+            (None, None) => break,
+            (o, r) if o == r => {}
+            _ => lines.push(n),
+        }
+    }
+    lines
+}
+
+/// Plans setting the package name to `new` in each manifest in `root`,
+/// without looking at any other file.
+pub fn plan_manifest_rewrites(
+    root: &Path,
+    new: &str,
+) -> anyhow::Result<Vec<Rewrite>> {
+    let mut found = false;
+    let mut rewrites = Vec::new();
+    for (file, key_paths) in MANIFESTS {
+        let path = root.join(file);
+        if !path.is_file() {
+            continue;
+        }
+        found = true;
+        let (text, mut doc) = read_manifest(&path, file)?;
+        let mut count = 0;
+        for keys in *key_paths {
+            if doc.get(keys).is_some_and(|name| name != new) {
+                doc.set(keys, new);
+                count += 1;
+            }
+        }
+        if count > 0 {
+            let contents = doc.to_text(&text)?;
+            rewrites.push(Rewrite {
+                path: file.into(),
+                lines: changed_lines(&text, &contents),
+                contents,
+                count,
+            });
+        }
+    }
+    if !found {
+        bail!(
+            "Found no Cargo.toml, pyproject.toml or package.json in {}.",
+            root.display()
+        );
+    }
+    Ok(rewrites)
+}
+
+/// Replaces the old name in the keys and strings of the JSON in `value`
+/// with `replacer`, returning how many replacements were made.
+fn replace_in_json(
+    value: &mut serde_json::Value,
+    replacer: &Replacer,
+) -> usize {
+    use serde_json::Value;
+    match value {
+        Value::String(text) => {
+            let (replaced, count) = replacer.replace(text);
+            *text = replaced.into_owned();
+            count
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .map(|item| replace_in_json(item, replacer))
+            .sum(),
+        Value::Object(object) => {
+            let mut count = 0;
+            let entries = std::mem::take(object);
+            for (key, mut item) in entries {
+                let (key, in_key) = replacer.replace(&key);
+                count += in_key + replace_in_json(&mut item, replacer);
+                object.insert(key.into_owned(), item);
+            }
+            count
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => 0,
+    }
+}
+
+/// Redoes the planned `rewrites` of JSON files in the project at `root` by
+/// parsing them and replacing the old name only in their keys and strings,
+/// rather than anywhere in their text. A file that does not parse is left to
+/// the textual rewrite, with a warning, as are YAML files, which there is no
+/// parser for.
+pub fn rewrite_json_structurally(
+    root: &Path,
+    replacer: &Replacer,
+    rewrites: &mut Vec<Rewrite>,
+    reporter: &mut Reporter,
+) -> anyhow::Result<()> {
+    let mut unchanged = Vec::new();
+    for (i, rw) in rewrites.iter_mut().enumerate() {
+        let ext = rw.path.extension().unwrap_or_default();
+        if ext == "yaml" || ext == "yml" {
+            reporter.warn(format_args!(
+                "Warning: {} is YAML, which --replace-json-keys cannot parse, so the old name is replaced in it as text.",
+                rw.path.display()
+            ))?;
+            continue;
+        } else if ext != "json" {
+            continue;
+        }
+        let path = root.join(&rw.path);
+        let original = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        let mut doc = match Document::parse(".json", &original) {
+            Ok(Document::Json(doc)) => doc,
+            _ => {
+                reporter.warn(format_args!(
+                    "Warning: {} is not valid JSON, so the old name is replaced in it as text.",
+                    path.display()
+                ))?;
+                continue;
+            }
+        };
+        let count = replace_in_json(&mut doc, replacer.for_path(&rw.path));
+        if count == 0 {
+            unchanged.push(i);
+            continue;
+        }
+        rw.contents = Document::Json(doc).to_text(&original)?;
+        rw.count = count;
+        rw.lines = changed_lines(&original, &rw.contents);
+    }
+    for i in unchanged.into_iter().rev() {
+        rewrites.remove(i);
+    }
+    Ok(())
+}
+
+/// A `--set FILE:KEY.PATH=VALUE`: one structured field of a TOML or JSON
+/// file in the project, to be set to a string.
+#[derive(Debug, Clone)]
+pub struct FieldSet {
+    file: PathBuf,
+    keys: Vec<String>,
+    value: String,
+}
+
+impl FromStr for FieldSet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (file, rest) = s.split_once(':').with_context(|| {
+            format!("{s:?} is not of the form FILE:KEY=VALUE")
+        })?;
+        let (keys, value) = rest.split_once('=').with_context(|| {
+            format!("{s:?} is not of the form FILE:KEY=VALUE")
+        })?;
+        if file.is_empty() || keys.split('.').any(str::is_empty) {
+            bail!("{s:?} is missing a file, or has an empty key");
+        }
+        Ok(FieldSet {
+            file: file.into(),
+            keys: keys.split('.').map(str::to_string).collect(),
+            value: value.to_string(),
+        })
+    }
+}
+
+/// Applies each of `sets` on top of the planned `rewrites` of the project in
+/// `root`: a file that is already being rewritten has its fields set in the
+/// rewritten contents, and any other file gets a rewrite of its own.
+pub fn apply_field_sets(
+    root: &Path,
+    sets: &[FieldSet],
+    rewrites: &mut Vec<Rewrite>,
+) -> anyhow::Result<()> {
+    for set in sets {
+        let path = root.join(&set.file);
+        let file = set.file.to_string_lossy();
+        let original = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        let i = match rewrites.iter().position(|rw| rw.path == set.file) {
+            Some(i) => i,
+            None => {
+                rewrites.push(Rewrite {
+                    path: set.file.clone(),
+                    contents: original.clone(),
+                    count: 0,
+                    lines: Vec::new(),
+                });
+                rewrites.len() - 1
+            }
+        };
+        let rewrite = &mut rewrites[i];
+        let mut doc = Document::parse(&file, &rewrite.contents)
+            .with_context(|| format!("...while parsing {}", path.display()))?;
+        let keys: Vec<&str> = set.keys.iter().map(String::as_str).collect();
+        if !doc.set(&keys, &set.value) {
+            bail!(
+                "{} has no table for {} to be set in.",
+                path.display(),
+                set.keys.join(".")
+            );
+        }
+        let contents = doc.to_text(&rewrite.contents)?;
+        if contents == rewrite.contents {
+            continue;
+        }
+        rewrite.contents = contents;
+        rewrite.count += 1;
+        rewrite
+            .lines
+            .extend(changed_lines(&original, &rewrite.contents));
+        rewrite.lines.sort_unstable();
+        rewrite.lines.dedup();
+    }
+    rewrites.retain(|rw| rw.count > 0);
+    rewrites.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(())
+}
+
+/// Runs the formatter for the kind of project in `dir` (`cargo fmt` for Rust,
+/// `prettier` for JavaScript), so that renamed identifiers do not leave the
+/// code misaligned. Failing to format only warns.
+pub fn format_project(
+    sh: &Shell,
+    dir: &Path,
+    reporter: &mut Reporter,
+) -> io::Result<()> {
+    let _guard = sh.push_dir(dir);
+    let formatted = if dir.join("Cargo.toml").is_file() {
+        cmd!(sh, "cargo fmt").run()
+    } else if dir.join("package.json").is_file() {
+        cmd!(sh, "npx --no-install prettier --write .").run()
+    } else {
+        return reporter.info(format_args!(
+            "Not formatting {}: could not tell what kind of project it is.",
+            dir.display()
+        ));
+    };
+    if let Err(err) = formatted {
+        reporter.warn(format_args!(
+            "Warning: formatting {} failed: {err}",
+            dir.display()
+        ))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{replace::Mode, testutil::TempDir};
+
+    /// The rewrites of `files` (each written to `dir`) that
+    /// [`rewrite_json_structurally`] makes, replacing `from` with `to`.
+    fn structurally(
+        dir: &TempDir,
+        files: &[(&str, &str)],
+        from: &str,
+        to: &str,
+    ) -> Vec<Rewrite> {
+        let replacer = Replacer::new(from, to, Mode::Literal).unwrap();
+        let mut rewrites: Vec<Rewrite> = files
+            .iter()
+            .map(|&(path, contents)| {
+                dir.write(path, contents);
+                let (replaced, count) = replacer.replace(contents);
+                Rewrite {
+                    path: path.into(),
+                    contents: replaced.into_owned(),
+                    count,
+                    lines: Vec::new(),
+                }
+            })
+            .collect();
+        let mut reporter = Reporter::quiet();
+        rewrite_json_structurally(
+            dir.path(),
+            &replacer,
+            &mut rewrites,
+            &mut reporter,
+        )
+        .unwrap();
+        rewrites
+    }
+
+    #[test]
+    fn keys_and_string_values_are_replaced() {
+        let dir = TempDir::new();
+        let json =
+            r#"{"old-name": {"name": "old-name-cli"}, "bins": ["old-name"]}"#;
+        let rewrites =
+            structurally(&dir, &[("config.json", json)], "old-name", "new");
+        let value: serde_json::Value =
+            serde_json::from_str(&rewrites[0].contents).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"new": {"name": "new-cli"}, "bins": ["new"]})
+        );
+        assert_eq!(rewrites[0].count, 3);
+    }
+
+    #[test]
+    fn numbers_and_bools_are_left_alone() {
+        let dir = TempDir::new();
+        let json = r#"{"port": 1, "label": "1", "on": true, "says": "true"}"#;
+        let rewrites = structurally(&dir, &[("a.json", json)], "1", "2");
+        let value: serde_json::Value =
+            serde_json::from_str(&rewrites[0].contents).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"port": 1, "label": "2", "on": true, "says": "true"})
+        );
+        let rewrites = structurally(&dir, &[("b.json", json)], "true", "yes");
+        let value: serde_json::Value =
+            serde_json::from_str(&rewrites[0].contents).unwrap();
+        assert_eq!(value["on"], true);
+        assert_eq!(value["says"], "yes");
+    }
+
+    #[test]
+    fn other_files_keep_their_textual_rewrite() {
+        let dir = TempDir::new();
+        let files = [
+            ("broken.json", "{ old-name"),
+            ("config.yaml", "name: old-name\n"),
+            ("README", "old-name\n"),
+        ];
+        let rewrites = structurally(&dir, &files, "old-name", "new-name");
+        let contents: Vec<_> =
+            rewrites.iter().map(|rw| rw.contents.as_str()).collect();
+        assert_eq!(contents, ["{ new-name", "name: new-name\n", "new-name\n"]);
+    }
+
+    #[test]
+    fn json_mentioning_the_name_only_in_numbers_is_not_rewritten() {
+        let dir = TempDir::new();
+        let rewrites =
+            structurally(&dir, &[("a.json", r#"{"port": 8080}"#)], "80", "90");
+        assert!(rewrites.is_empty());
+    }
+
+    /// The rewrites [`apply_field_sets`] makes in `dir` for each `--set` in
+    /// `sets`, with nothing else rewritten.
+    fn field_sets(
+        dir: &TempDir,
+        sets: &[&str],
+    ) -> anyhow::Result<Vec<Rewrite>> {
+        let sets: Vec<FieldSet> =
+            sets.iter().map(|set| set.parse().unwrap()).collect();
+        let mut rewrites = Vec::new();
+        apply_field_sets(dir.path(), &sets, &mut rewrites)?;
+        Ok(rewrites)
+    }
+
+    #[test]
+    fn setting_a_toml_field_keeps_its_formatting() {
+        let dir = TempDir::new();
+        let toml =
+            "[package]\n# The crate's name.\nname   =  \"old\"  # here\n\
+                    version = \"0.1.0\"\n";
+        dir.write("Cargo.toml", toml);
+        let rewrites =
+            field_sets(&dir, &["Cargo.toml:package.name=new"]).unwrap();
+        assert_eq!(
+            rewrites[0].contents,
+            "[package]\n# The crate's name.\nname   =  \"new\"  # here\n\
+             version = \"0.1.0\"\n"
+        );
+        assert_eq!(rewrites[0].lines, [3]);
+    }
+
+    #[test]
+    fn a_missing_field_is_added_to_its_table() {
+        let dir = TempDir::new();
+        dir.write("package.json", "{\n  \"name\": \"old\"\n}\n");
+        let rewrites = field_sets(
+            &dir,
+            &["package.json:bin=new", "package.json:name=new"],
+        )
+        .unwrap();
+        let value: serde_json::Value =
+            serde_json::from_str(&rewrites[0].contents).unwrap();
+        assert_eq!(value, serde_json::json!({"name": "new", "bin": "new"}));
+        assert_eq!(rewrites[0].count, 2);
+    }
+
+    #[test]
+    fn setting_a_field_whose_table_is_missing_fails() {
+        let dir = TempDir::new();
+        dir.write("package.json", "{\"name\": \"old\"}\n");
+        dir.write("Cargo.toml", "[package]\nname = \"old\"\n");
+        for set in ["package.json:repository.url=x", "Cargo.toml:lib.name=x"] {
+            let Err(err) = field_sets(&dir, &[set]) else {
+                panic!("{set} was set");
+            };
+            assert!(
+                err.to_string().contains("has no table for"),
+                "{set}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn setting_a_field_to_its_value_rewrites_nothing() {
+        let dir = TempDir::new();
+        dir.write("Cargo.toml", "[package]\nname = \"old\"\n");
+        assert!(field_sets(&dir, &["Cargo.toml:package.name=old"])
+            .unwrap()
+            .is_empty());
+    }
+}
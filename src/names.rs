@@ -0,0 +1,327 @@
+use anyhow::{bail, Context};
+use bpaf::Bpaf;
+use regex::Regex;
+
+/// A new name derived from the current one, instead of given outright.
+#[derive(Bpaf, Debug, Clone)]
+pub enum DeriveName {
+    AddPrefix(
+        /// Rename to the current name with PREFIX added to the front.
+        #[bpaf(long("add-prefix"), argument("PREFIX"))]
+        String,
+    ),
+    StripPrefix(
+        /// Rename to the current name with PREFIX removed from the front.
+        #[bpaf(long("strip-prefix"), argument("PREFIX"))]
+        String,
+    ),
+    AddSuffix(
+        /// Rename to the current name with SUFFIX added to the end.
+        #[bpaf(long("add-suffix"), argument("SUFFIX"))]
+        String,
+    ),
+    StripSuffix(
+        /// Rename to the current name with SUFFIX removed from the end.
+        #[bpaf(long("strip-suffix"), argument("SUFFIX"))]
+        String,
+    ),
+    NameTransform(
+        /// Rename to the current name with a sed-style substitution applied,
+        /// such as `s/-service$//`. The replacement may refer to capture
+        /// groups (`$1`); only the first match is replaced.
+        #[bpaf(long("name-transform"), argument("s/PATTERN/REPLACEMENT/"))]
+        String,
+    ),
+}
+
+/// Applies a `s/PATTERN/REPLACEMENT/` substitution (with any delimiter in
+/// place of `/`) to `old`.
+fn transform(old: &str, substitution: &str) -> anyhow::Result<String> {
+    let malformed = || {
+        format!("{substitution:?} is not of the form s/PATTERN/REPLACEMENT/")
+    };
+    let rest = substitution.strip_prefix('s').with_context(malformed)?;
+    let delimiter = rest.chars().next().with_context(malformed)?;
+    let parts: Vec<&str> =
+        rest[delimiter.len_utf8()..].split(delimiter).collect();
+    let [pattern, replacement, ""] = parts[..] else {
+        bail!(malformed());
+    };
+    let re = Regex::new(pattern).with_context(|| {
+        format!("...while compiling {pattern:?} as a regex")
+    })?;
+    if !re.is_match(old) {
+        bail!("{pattern:?} does not match {old:?}.");
+    }
+    Ok(re.replace(old, replacement).into_owned())
+}
+
+/// The name to rename `old` to: exactly one of `new` and `derive` must have
+/// been given.
+pub fn new_name(
+    old: &str,
+    new: Option<String>,
+    derive: Option<DeriveName>,
+) -> anyhow::Result<String> {
+    let new = match (new, derive) {
+        (Some(new), None) => new,
+        (None, Some(DeriveName::AddPrefix(prefix))) => format!("{prefix}{old}"),
+        (None, Some(DeriveName::AddSuffix(suffix))) => format!("{old}{suffix}"),
+        (None, Some(DeriveName::StripPrefix(prefix))) => old
+            .strip_prefix(&prefix)
+            .with_context(|| {
+                format!("{old:?} does not start with {prefix:?}.")
+            })?
+            .to_string(),
+        (None, Some(DeriveName::NameTransform(substitution))) => {
+            let new = transform(old, &substitution)?;
+            println!("Derived the new name {new:?} from {old:?}.");
+            new
+        }
+        (None, Some(DeriveName::StripSuffix(suffix))) => old
+            .strip_suffix(&suffix)
+            .with_context(|| format!("{old:?} does not end with {suffix:?}."))?
+            .to_string(),
+        (Some(_), Some(_)) => {
+            bail!("Give either NEW_NAME or one of --add-prefix and friends, not both.")
+        }
+        (None, None) => {
+            bail!("Give a NEW_NAME, or one of --add-prefix and friends.")
+        }
+    };
+    if new.is_empty() {
+        bail!("The new name for {old:?} would be empty.");
+    }
+    Ok(new)
+}
+
+/// Names shorter than this (in `char`s) are too likely to turn up inside
+/// unrelated words to replace safely.
+const MIN_SAFE_LENGTH: usize = 3;
+
+/// Short words common enough in code and prose that replacing them is
+/// almost never what was meant.
+const RISKY_NAMES: &[&str] = &[
+    "api", "app", "bin", "cli", "core", "data", "db", "doc", "docs", "in",
+    "io", "lib", "main", "net", "src", "test", "tests", "the", "tmp", "util",
+    "utils", "web",
+];
+
+/// Why replacing `name` wherever it occurs is likely to hit unintended
+/// matches, if it is.
+pub fn risk(name: &str) -> Option<&'static str> {
+    if name.chars().count() < MIN_SAFE_LENGTH {
+        Some("it is very short")
+    } else if RISKY_NAMES.contains(&name.to_lowercase().as_str()) {
+        Some("it is a common word")
+    } else {
+        None
+    }
+}
+
+/// Levenshtein distance between two names, counted in `char`s.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            curr[j + 1] = substitution.min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
+/// Whether `new` is so close to `old` (one or two edits away) that it is
+/// more likely a mistyping of it than a rename. Recasing or repunctuating the
+/// name, or adding to or cutting from it (`foo` to `foo2`), are deliberate;
+/// and names too short for the distance to tell anything are never suspect.
+pub fn looks_like_typo(old: &str, new: &str) -> bool {
+    let distance = edit_distance(old, new);
+    (1..=2).contains(&distance)
+        && distance * 2 < old.chars().count()
+        && normalize(old) != normalize(new)
+        && !old.contains(new)
+        && !new.contains(old)
+}
+
+/// A case-folded slug of `name`: runs of `-`, `_`, `.` and whitespace become
+/// a single `-`, so `My_Project` and `my-project` normalize to the same thing.
+pub fn normalize(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+    for c in name.trim().chars() {
+        if c == '-' || c == '_' || c == '.' || c.is_whitespace() {
+            if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+        } else {
+            slug.extend(c.to_lowercase());
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// A way of writing a name made of several words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Kebab,
+    Snake,
+    ScreamingSnake,
+    Camel,
+    Pascal,
+}
+
+impl Style {
+    const ALL: [Style; 5] = [
+        Style::Kebab,
+        Style::Snake,
+        Style::ScreamingSnake,
+        Style::Camel,
+        Style::Pascal,
+    ];
+
+    /// The (lowercase) `words` written in this style.
+    fn render(self, words: &[String]) -> String {
+        let capitalized = |word: &str| {
+            let mut chars = word.chars();
+            chars.next().map_or_else(String::new, |first| {
+                first.to_uppercase().chain(chars).collect()
+            })
+        };
+        match self {
+            Style::Kebab => words.join("-"),
+            Style::Snake => words.join("_"),
+            Style::ScreamingSnake => words.join("_").to_uppercase(),
+            Style::Camel => words
+                .iter()
+                .enumerate()
+                .map(|(i, word)| {
+                    if i == 0 {
+                        word.clone()
+                    } else {
+                        capitalized(word)
+                    }
+                })
+                .collect(),
+            Style::Pascal => {
+                words.iter().map(|word| capitalized(word)).collect()
+            }
+        }
+    }
+}
+
+/// The words of `name`, lowercased: it is split at `-`, `_`, `.` and
+/// whitespace, and where the case changes (`myProject`, `HTTPServer`).
+fn words(name: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let chars: Vec<char> = name.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '-' || c == '_' || c == '.' || c.is_whitespace() {
+            words.extend((!word.is_empty()).then(|| std::mem::take(&mut word)));
+            continue;
+        }
+        let prev = i.checked_sub(1).map(|i| chars[i]);
+        let next = chars.get(i + 1);
+        let starts_word = c.is_uppercase()
+            && (prev.is_some_and(|p| p.is_lowercase() || p.is_ascii_digit())
+                || prev.is_some_and(char::is_uppercase)
+                    && next.is_some_and(|n| n.is_lowercase()));
+        if starts_word && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        word.extend(c.to_lowercase());
+    }
+    words.extend((!word.is_empty()).then_some(word));
+    words
+}
+
+/// The style `name` is written in, if it has several words and is written
+/// in one of them.
+fn style(name: &str) -> Option<Style> {
+    let words = words(name);
+    if words.len() < 2 {
+        return None;
+    }
+    Style::ALL
+        .into_iter()
+        .find(|style| style.render(&words) == name)
+}
+
+/// When `from` and `to` are written in different styles (`my-project` and
+/// `new_project`), each way of writing `from` paired with `to` written the
+/// same way, starting with `from` itself: so that every occurrence of the old
+/// name, whichever style it is in, is replaced in that style. Ways of writing
+/// it that would be replaced by themselves are left out, so names that only
+/// differ in style (`my-project` and `my_project`) have none.
+pub fn styled_variants(from: &str, to: &str) -> Option<Vec<(String, String)>> {
+    if style(from)? == style(to)? {
+        return None;
+    }
+    let variants = every_style(from, to);
+    (!variants.is_empty()).then_some(variants)
+}
+
+/// Each way of writing `from` (as it is, then in every [`Style`]: `foo`,
+/// `FOO` and `Foo` for a single word) paired with `to` written the same way,
+/// whatever styles the two are written in. `from` as it is comes with `to`
+/// in its style, if it is written in one, or else with `to` as it is. Ways
+/// of writing it that would be replaced by themselves are left out.
+pub fn every_style(from: &str, to: &str) -> Vec<(String, String)> {
+    let (from_words, to_words) = (words(from), words(to));
+    let first = match style(from) {
+        Some(style) => (from.to_string(), style.render(&to_words)),
+        None => (from.to_string(), to.to_string()),
+    };
+    let styled = Style::ALL
+        .into_iter()
+        .map(|style| (style.render(&from_words), style.render(&to_words)));
+    let mut variants: Vec<(String, String)> = Vec::new();
+    for (variant, renamed) in std::iter::once(first).chain(styled) {
+        if variant != renamed && variants.iter().all(|(old, _)| *old != variant)
+        {
+            variants.push((variant, renamed));
+        }
+    }
+    variants
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(variants: &[(&str, &str)]) -> Vec<(String, String)> {
+        variants
+            .iter()
+            .map(|&(old, new)| (old.to_string(), new.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn names_in_the_same_style_have_no_variants() {
+        assert_eq!(styled_variants("my-project", "new-project"), None);
+        assert_eq!(styled_variants("MyProject", "NewProject"), None);
+    }
+
+    #[test]
+    fn names_in_different_styles_are_replaced_in_each_style() {
+        assert_eq!(
+            styled_variants("my-project", "new_project"),
+            Some(pairs(&[
+                ("my-project", "new-project"),
+                ("my_project", "new_project"),
+                ("MY_PROJECT", "NEW_PROJECT"),
+                ("myProject", "newProject"),
+                ("MyProject", "NewProject"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn names_only_differing_in_style_have_no_variants() {
+        assert_eq!(styled_variants("my-project", "my_project"), None);
+        assert_eq!(styled_variants("myProject", "MY_PROJECT"), None);
+    }
+}
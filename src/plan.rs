@@ -0,0 +1,232 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use encoding_rs::Encoding;
+use serde::{Deserialize, Serialize};
+use xshell::{cmd, Shell};
+
+use crate::{
+    encode_text, forge, relocate,
+    renames::{self, PathRename},
+    write_atomically, Rewrite,
+};
+
+/// Everything a rename will do, worked out in advance: written by
+/// `--json-plan`, and carried out as it is by `pmv apply`.
+#[derive(Serialize, Deserialize)]
+pub struct Plan {
+    project: PathBuf,
+    destination: PathBuf,
+    /// The label of the encoding files are written in, if not UTF-8.
+    encoding: Option<String>,
+    rewrites: Vec<PlannedRewrite>,
+    /// Renames of paths inside the project, given under `project`.
+    renames: Vec<PlannedRename>,
+    forge: Option<ForgeStep>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlannedRewrite {
+    /// Relative to the project.
+    path: PathBuf,
+    count: usize,
+    /// Of the file's contents when the plan was made, to tell whether it has
+    /// changed since.
+    original_hash: String,
+    contents: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlannedRename {
+    from: PathBuf,
+    to: PathBuf,
+}
+
+/// The command renaming the repository, and the git remote to point at it
+/// afterwards.
+#[derive(Serialize, Deserialize)]
+pub struct ForgeStep {
+    pub program: String,
+    pub args: Vec<String>,
+    pub remote: Option<String>,
+    pub remote_url: Option<String>,
+}
+
+/// FNV-1a, which unlike `std`'s hasher is stable between builds, so plans
+/// stay verifiable by newer versions of `pmv`.
+pub fn hash(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf29ce484222325_u64, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{hash:016x}")
+}
+
+impl Plan {
+    pub fn new(
+        project: &Path,
+        destination: &Path,
+        encoding: Option<&'static Encoding>,
+        rewrites: &[Rewrite],
+        path_renames: &[PathRename],
+        forge: Option<ForgeStep>,
+    ) -> anyhow::Result<Self> {
+        let rewrites = rewrites
+            .iter()
+            .map(|rw| {
+                let fp = project.join(&rw.path);
+                let original = fs::read(&fp).with_context(|| {
+                    format!("Could not read {}", fp.display())
+                })?;
+                Ok(PlannedRewrite {
+                    path: rw.path.clone(),
+                    count: rw.count,
+                    original_hash: hash(&original),
+                    contents: rw.contents.clone(),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Plan {
+            project: project.to_path_buf(),
+            destination: destination.to_path_buf(),
+            encoding: encoding.map(|encoding| encoding.name().to_string()),
+            rewrites,
+            renames: path_renames
+                .iter()
+                .map(|rn| PlannedRename {
+                    from: rn.from.clone(),
+                    to: rn.to.clone(),
+                })
+                .collect(),
+            forge,
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json + "\n")
+            .with_context(|| format!("Could not write {}", path.display()))?;
+        println!("Wrote the plan to {}.", path.display());
+        Ok(())
+    }
+
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("...while parsing {}", path.display()))
+    }
+
+    /// Lists how the project has changed since the plan was made, in ways
+    /// that would make carrying it out differ from what was reviewed.
+    fn drift(&self) -> Vec<String> {
+        let mut drift = Vec::new();
+        for rw in &self.rewrites {
+            let fp = self.project.join(&rw.path);
+            match fs::read(&fp) {
+                Ok(current) if hash(&current) == rw.original_hash => {}
+                Ok(_) => drift.push(format!("  {} has changed", fp.display())),
+                Err(_) => drift.push(format!("  {} is gone", fp.display())),
+            }
+        }
+        for rn in &self.renames {
+            if !rn.from.exists() {
+                drift.push(format!("  {} is gone", rn.from.display()));
+            }
+        }
+        drift
+    }
+}
+
+/// Carries out the plan in `plan_path`. With `verify`, the project is first
+/// checked to be as it was when the plan was made.
+pub fn apply(sh: &Shell, plan_path: &Path, verify: bool) -> anyhow::Result<()> {
+    let plan = Plan::read(plan_path)?;
+    // Planned with --no-rename-dir, the project stays where it is.
+    let moves = plan.destination != plan.project;
+    if !plan.project.is_dir() {
+        bail!("{} no longer exists.", plan.project.display());
+    } else if moves && plan.destination.exists() {
+        bail!("{} already exists!", plan.destination.display());
+    }
+    if verify {
+        let drift = plan.drift();
+        if !drift.is_empty() {
+            bail!(
+                "Refusing to apply the plan, the project has changed since it was made:\n{}",
+                drift.join("\n")
+            );
+        }
+    }
+    let encoding = plan
+        .encoding
+        .as_deref()
+        .map(|label| {
+            Encoding::for_label(label.as_bytes())
+                .with_context(|| format!("Unknown encoding {label:?} in plan."))
+        })
+        .transpose()?;
+
+    if moves {
+        println!(
+            "Moving from {} to {}.",
+            plan.project.display(),
+            plan.destination.display()
+        );
+        if let Some(parent) = plan.destination.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create directory {}.", parent.display())
+            })?;
+        }
+        relocate::move_dir(
+            &plan.project,
+            &plan.destination,
+            false,
+            false,
+            false,
+        )?;
+    }
+
+    for rw in &plan.rewrites {
+        let fp = plan.destination.join(&rw.path);
+        let contents = match encoding {
+            Some(encoding) => encode_text(&rw.contents, encoding)
+                .with_context(|| {
+                    format!(
+                        "The new contents of {} cannot be represented in {}.",
+                        fp.display(),
+                        encoding.name()
+                    )
+                })?,
+            None => rw.contents.clone().into_bytes(),
+        };
+        write_atomically(&fp, &contents)?;
+        println!("Rewrote {} ({} replacement(s)).", fp.display(), rw.count);
+    }
+
+    let path_renames: Vec<PathRename> = plan
+        .renames
+        .into_iter()
+        .map(|rn| PathRename {
+            from: rn.from,
+            to: rn.to,
+        })
+        .collect();
+    let path_renames =
+        renames::rebase(path_renames, &plan.project, &plan.destination);
+    renames::apply_path_renames(&path_renames, None)?;
+
+    if let Some(step) = plan.forge {
+        sh.change_dir(&plan.destination);
+        let (program, args) = (&step.program, &step.args);
+        cmd!(sh, "{program} {args...}")
+            .run()
+            .with_context(|| "...while renaming the repository")?;
+        if let (Some(name), Some(url)) = (&step.remote, &step.remote_url) {
+            forge::set_remote_url(sh, name, url)?;
+        }
+    }
+    Ok(())
+}
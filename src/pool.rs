@@ -0,0 +1,120 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Condvar, Mutex,
+    },
+    thread,
+};
+
+/// A counting semaphore, used to bound how many threads do IO at once.
+pub struct Semaphore {
+    permits: Mutex<usize>,
+    released: Condvar,
+}
+
+pub struct Permit<'a>(&'a Semaphore);
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Semaphore {
+            permits: Mutex::new(permits.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut permits = self.permits.lock().unwrap();
+        while *permits == 0 {
+            permits = self.released.wait(permits).unwrap();
+        }
+        *permits -= 1;
+        Permit(self)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.permits.lock().unwrap() += 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// The `--max-open-files` to use when it is not given: half the process's
+/// soft limit on open files, leaving the other half for the directories
+/// the walker has open, standard streams and the programs `pmv` runs. Where
+/// that limit cannot be read (or there is none), 256.
+pub fn max_open_files() -> usize {
+    soft_open_files_limit()
+        .map_or(256, |soft| usize::try_from(soft / 2).unwrap_or(usize::MAX))
+        .max(1)
+}
+
+#[cfg(unix)]
+fn soft_open_files_limit() -> Option<u64> {
+    let mut limit = std::mem::MaybeUninit::<libc::rlimit>::uninit();
+    // SAFETY: `limit` is only read once `getrlimit` has filled it in.
+    let limit = unsafe {
+        if libc::getrlimit(libc::RLIMIT_NOFILE, limit.as_mut_ptr()) != 0 {
+            return None;
+        }
+        limit.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast)]
+    (limit.rlim_cur != libc::RLIM_INFINITY).then_some(limit.rlim_cur as u64)
+}
+
+#[cfg(not(unix))]
+fn soft_open_files_limit() -> Option<u64> {
+    None
+}
+
+/// Runs `task` over `items` on `threads` worker threads, collecting the
+/// `Some` results in no particular order. The first error stops the
+/// remaining workers from picking up new items, and is returned.
+pub fn run_parallel<T, R, F>(
+    items: Vec<T>,
+    threads: usize,
+    task: F,
+) -> anyhow::Result<Vec<R>>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> anyhow::Result<Option<R>> + Sync,
+{
+    let (tx, rx) = crossbeam_channel::unbounded();
+    for item in items {
+        tx.send(item).unwrap();
+    }
+    drop(tx);
+
+    let failed = AtomicBool::new(false);
+    let per_thread: Vec<anyhow::Result<Vec<R>>> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    for item in rx.iter() {
+                        if failed.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        match task(item) {
+                            Ok(result) => results.extend(result),
+                            Err(err) => {
+                                failed.store(true, Ordering::Relaxed);
+                                return Err(err);
+                            }
+                        }
+                    }
+                    Ok(results)
+                })
+            })
+            .collect();
+        workers.into_iter().map(|w| w.join().unwrap()).collect()
+    });
+
+    let mut results = Vec::new();
+    for worker_results in per_thread {
+        results.extend(worker_results?);
+    }
+    Ok(results)
+}
@@ -0,0 +1,40 @@
+/// Lowers the priority of the process (and of the threads it starts from
+/// now on) so that a big run leaves the machine responsive for others: its
+/// CPU priority on Unix, and on Linux its IO priority too. Anything the
+/// platform does not support is skipped with a note.
+pub fn be_nice() {
+    #[cfg(unix)]
+    {
+        // SAFETY: `setpriority` only reads its arguments.
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 10) } != 0 {
+            println!(
+                "Warning: could not lower the CPU priority: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    #[cfg(target_os = "linux")]
+    {
+        // From `linux/ioprio.h`, which `libc` does not expose.
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        // SAFETY: `ioprio_set` only reads its arguments.
+        let set = unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                0,
+                IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+            )
+        };
+        if set != 0 {
+            println!(
+                "Warning: could not lower the IO priority: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    println!("Note: --nice cannot lower the IO priority on this platform.");
+}
@@ -0,0 +1,81 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    apply_rewrites, filter::WalkFilter, find_and_replace_in_dir, replace::Mode,
+    replace::Replacer, report::confirm, report_failures, RewriteOpts,
+};
+
+/// Canonicalizes `roots`, dropping any that are inside (or the same as)
+/// another, so that no file is walked twice.
+fn dedup_roots(roots: Vec<PathBuf>) -> anyhow::Result<Vec<PathBuf>> {
+    let mut canonical = roots
+        .iter()
+        .map(|root| {
+            root.canonicalize().with_context(|| {
+                format!("{} is not a directory.", root.display())
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    canonical.sort_by_key(|root| root.components().count());
+    let mut kept: Vec<PathBuf> = Vec::new();
+    for root in canonical {
+        if !kept.iter().any(|outer| root.starts_with(outer)) {
+            kept.push(root);
+        }
+    }
+    Ok(kept)
+}
+
+/// Replaces `from` with `to` in every text file under all of `roots` at
+/// once, without renaming any directory or repository.
+pub fn rebrand(
+    roots: Vec<PathBuf>,
+    from: &str,
+    to: &str,
+    dry_run: bool,
+    yes: bool,
+) -> anyhow::Result<()> {
+    let mut roots = dedup_roots(roots)?.into_iter();
+    let Some(first) = roots.next() else {
+        bail!("Give at least one --root to rebrand.");
+    };
+    let opts = RewriteOpts::plain(
+        WalkFilter::new(&first, &[])?.also_walk(roots.collect()),
+    );
+    let replacer = Replacer::new(from, to, Mode::Literal)?;
+    let mut rewrites =
+        find_and_replace_in_dir(first.clone(), &replacer, &opts)?;
+    // Files under the other roots keep their absolute paths.
+    let shown = |path: &Path| first.join(path);
+    rewrites.sort_by_key(|rw| shown(&rw.path));
+
+    if dry_run {
+        for rw in &rewrites {
+            println!(
+                "Would rewrite {} ({} replacement(s)).",
+                shown(&rw.path).display(),
+                rw.count
+            );
+        }
+        return report_failures(opts.failures.into_inner().unwrap());
+    }
+    if !yes
+        && !confirm(
+            format_args!(
+                "Replace {from:?} with {to:?} in {} file(s)?",
+                rewrites.len()
+            ),
+            "--yes",
+        )?
+    {
+        println!("Aborted: nothing was changed (pass --yes to skip asking).");
+        return Ok(());
+    }
+    for path in apply_rewrites(&first, rewrites, &opts)? {
+        println!("Rewrote {}.", shown(&path).display());
+    }
+    println!("{}.", opts.diffstat);
+    report_failures(opts.failures.into_inner().unwrap())
+}
@@ -0,0 +1,433 @@
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{bail, Context};
+
+use crate::report::confirm;
+
+/// The total size of the files under `dir`, not following symlinks.
+fn tree_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        size += if file_type.is_dir() {
+            tree_size(&entry.path())?
+        } else {
+            entry.metadata()?.len()
+        };
+    }
+    Ok(size)
+}
+
+/// Bytes free for unprivileged use on the filesystem holding `path`, if
+/// that can be found out.
+#[cfg(unix)]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated, and `stat` is only read once
+    // `statvfs` has filled it in.
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stat.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Refuses to copy `from` to `to` when the destination's filesystem (as
+/// far as can be told) has no room for it, rather than stranding a partial
+/// copy there.
+fn check_space(from: &Path, to: &Path) -> anyhow::Result<()> {
+    let Some(available) = to.parent().and_then(available_space) else {
+        return Ok(());
+    };
+    let needed = tree_size(from).with_context(|| {
+        format!("...while measuring the size of {}", from.display())
+    })?;
+    if needed > available {
+        bail!(
+            "Not enough space to copy the project to {}: it takes {needed} bytes, but only {available} are free.",
+            to.display()
+        );
+    }
+    Ok(())
+}
+
+/// The project being copied: from its `root` to `new_root`.
+struct Copy<'a> {
+    root: &'a Path,
+    new_root: &'a Path,
+    /// Copy what symlinks point at, rather than the symlinks themselves.
+    deref_symlinks: bool,
+}
+
+impl Copy<'_> {
+    /// Where the symlink `link`'s `target` should point in the copy: into
+    /// the copy wherever it pointed into the project, whether by an absolute
+    /// path or by a relative one that steps out of the project and back in
+    /// (through the project's old name). Anything else is kept as it is.
+    fn symlink_target(&self, link: &Path, target: PathBuf) -> PathBuf {
+        if target.is_absolute() {
+            return match target.strip_prefix(self.root) {
+                Ok(rest) => self.new_root.join(rest),
+                Err(_) => target,
+            };
+        }
+        let (Some(old_name), Some(new_name)) =
+            (self.root.file_name(), self.new_root.file_name())
+        else {
+            return target;
+        };
+        // How far below the project root the link's directory is, tracked
+        // as the target is followed.
+        let mut depth = link
+            .parent()
+            .and_then(|dir| dir.strip_prefix(self.root).ok())
+            .map_or(0, |dir| dir.components().count() as isize);
+        let mut rewritten = PathBuf::new();
+        for component in target.components() {
+            match component {
+                Component::ParentDir => depth -= 1,
+                Component::Normal(name) if depth == -1 && name == old_name => {
+                    rewritten.push(new_name);
+                    depth += 1;
+                    continue;
+                }
+                Component::Normal(_) => depth += 1,
+                _ => {}
+            }
+            rewritten.push(component);
+        }
+        rewritten
+    }
+}
+
+/// Copies the directory tree at `from` to `to` (which must not exist),
+/// keeping permissions, and recreating symlinks as symlinks (unless
+/// `copy.deref_symlinks`).
+fn copy_tree(from: &Path, to: &Path, copy: &Copy) -> anyhow::Result<()> {
+    fs::create_dir(to)
+        .with_context(|| format!("Failed to create {}.", to.display()))?;
+    fs::set_permissions(to, fs::metadata(from)?.permissions())?;
+    for entry in fs::read_dir(from)
+        .with_context(|| format!("Failed to list {}.", from.display()))?
+    {
+        let entry = entry?;
+        let (src, dst) = (entry.path(), to.join(entry.file_name()));
+        let mut file_type = entry.file_type()?;
+        if file_type.is_symlink() && copy.deref_symlinks {
+            file_type = fs::metadata(&src)
+                .with_context(|| {
+                    format!("Failed to follow the symlink {}.", src.display())
+                })?
+                .file_type();
+            let resolved = src.canonicalize()?;
+            if file_type.is_dir() && from.starts_with(&resolved) {
+                bail!(
+                    "Cannot follow the symlink {}, which points at a directory containing it.",
+                    src.display()
+                );
+            }
+        }
+        if file_type.is_dir() {
+            copy_tree(&src, &dst, copy)?;
+        } else if file_type.is_symlink() {
+            let target = copy.symlink_target(&src, fs::read_link(&src)?);
+            copy_symlink(&src, &target, &dst)?;
+        } else {
+            fs::copy(&src, &dst).with_context(|| {
+                format!(
+                    "Failed to copy {} to {}.",
+                    src.display(),
+                    dst.display()
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Recreates the symlink `src` at `dst`, pointing at `target`.
+#[cfg(unix)]
+fn copy_symlink(src: &Path, target: &Path, dst: &Path) -> anyhow::Result<()> {
+    std::os::unix::fs::symlink(target, dst).with_context(|| {
+        format!("Failed to copy the symlink {}.", src.display())
+    })
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(src: &Path, _target: &Path, dst: &Path) -> anyhow::Result<()> {
+    fs::copy(src, dst).with_context(|| {
+        format!("Failed to copy {} to {}.", src.display(), dst.display())
+    })?;
+    Ok(())
+}
+
+/// Whether `a` and `b` are the same file or directory, as with two spellings
+/// of a name that differ only in case on a case-insensitive filesystem.
+#[cfg(unix)]
+pub fn is_same_entry(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn is_same_entry(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Renames `from` to `to` when they are the same entry (see
+/// [`is_same_entry`]): renaming it to itself may do nothing, so it goes
+/// through a temporary name next to it.
+pub fn rename_case(from: &Path, to: &Path) -> anyhow::Result<()> {
+    let mut temp = from.as_os_str().to_owned();
+    temp.push(".pmv-rename");
+    let temp = Path::new(&temp);
+    fs::rename(from, temp).with_context(|| {
+        format!("Failed to rename {} to {}.", from.display(), temp.display())
+    })?;
+    fs::rename(temp, to).with_context(|| {
+        format!(
+            "Failed to rename {} to {} (it is left at {}).",
+            from.display(),
+            to.display(),
+            temp.display()
+        )
+    })
+}
+
+/// How the project gets to its new location.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Rename the directory, then rewrite it in place (copying it only when
+    /// it has to cross filesystems).
+    Rename,
+    /// Copy the project over, rewrite the copy, and only remove the original
+    /// once that has all succeeded.
+    Copy,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rename" => Ok(Strategy::Rename),
+            "copy" => Ok(Strategy::Copy),
+            _ => Err(format!("{s:?} is not a strategy: use rename or copy")),
+        }
+    }
+}
+
+/// Copies the project at `from` to `to`, leaving nothing behind at `to` if
+/// that fails. Symlinks are recreated as symlinks, those pointing into the
+/// project being repointed into the copy; with `deref_symlinks`, what they
+/// point at is copied instead.
+pub fn copy_dir(
+    from: &Path,
+    to: &Path,
+    deref_symlinks: bool,
+) -> anyhow::Result<()> {
+    check_space(from, to)?;
+    let copy = Copy {
+        root: from,
+        new_root: to,
+        deref_symlinks,
+    };
+    if let Err(err) = copy_tree(from, to, &copy) {
+        let _ = fs::remove_dir_all(to);
+        return Err(err.context("The original project was left untouched."));
+    }
+    Ok(())
+}
+
+/// Gets rid of the original project at `from`, now that it has been copied
+/// to `to`: it is moved to the trash (with `trash`) or, once the user agrees
+/// (or with `yes`), deleted.
+pub fn remove_original(
+    from: &Path,
+    to: &Path,
+    trash: bool,
+    yes: bool,
+) -> anyhow::Result<()> {
+    if trash {
+        trash::delete(from).with_context(|| {
+            format!("Failed to move {} to the trash.", from.display())
+        })?;
+        println!("Moved the original {} to the trash.", from.display());
+    } else if yes
+        || confirm(
+            format_args!(
+                "Copied the project to {}. Permanently delete the original {}?",
+                to.display(),
+                from.display()
+            ),
+            "--yes",
+        )?
+    {
+        fs::remove_dir_all(from).with_context(|| {
+            format!("Failed to delete the original {}.", from.display())
+        })?;
+    } else {
+        println!(
+            "Left the original {} in place (pass --trash to move it to the trash instead).",
+            from.display()
+        );
+    }
+    Ok(())
+}
+
+/// Moves the project at `from` to `to`. Where that crosses filesystems, it
+/// is copied over instead (see [`copy_dir`]), and the original then removed
+/// (see [`remove_original`]).
+pub fn move_dir(
+    from: &Path,
+    to: &Path,
+    deref_symlinks: bool,
+    trash: bool,
+    yes: bool,
+) -> anyhow::Result<()> {
+    match fs::rename(from, to) {
+        Ok(()) => return Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {}
+        Err(err) => {
+            return Err(err).with_context(|| {
+                format!(
+                    "Failed to rename {} to {}.",
+                    from.display(),
+                    to.display()
+                )
+            })
+        }
+    }
+    println!(
+        "{} is on another filesystem, copying the project over instead.",
+        to.display()
+    );
+    copy_dir(from, to, deref_symlinks)?;
+    remove_original(from, to, trash, yes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    /// Two names for one file, as a name and its other spellings are on a
+    /// case-insensitive filesystem.
+    fn two_names(dir: &TempDir) -> (PathBuf, PathBuf) {
+        let a = dir.write("a", "contents");
+        let b = dir.path().join("b");
+        fs::hard_link(&a, &b).unwrap();
+        (a, b)
+    }
+
+    #[test]
+    fn other_names_for_a_path_are_the_same_entry() {
+        let dir = TempDir::new();
+        let (a, b) = two_names(&dir);
+        let other = dir.write("other", "contents");
+        assert!(is_same_entry(&a, &a));
+        assert!(is_same_entry(&a, &b));
+        assert!(!is_same_entry(&a, &other));
+        assert!(!is_same_entry(&a, &dir.path().join("missing")));
+    }
+
+    #[test]
+    fn a_case_only_rename_goes_through() {
+        let dir = TempDir::new();
+        dir.write("Project/file", "contents");
+        let (from, to) =
+            (dir.path().join("Project"), dir.path().join("project"));
+        rename_case(&from, &to).unwrap();
+        assert_eq!(fs::read_to_string(to.join("file")).unwrap(), "contents");
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["project"]);
+    }
+
+    /// A project at `old` with a file, and symlinks to it by relative and
+    /// absolute paths, by a relative path through the project's own name,
+    /// and to a file outside the project.
+    #[cfg(unix)]
+    fn linked_project(dir: &TempDir) -> PathBuf {
+        use std::os::unix::fs::symlink;
+        let project = dir.path().join("old");
+        dir.write("old/sub/data", "data");
+        dir.write("outside", "outside");
+        symlink("data", project.join("sub/relative")).unwrap();
+        symlink(project.join("sub/data"), project.join("absolute")).unwrap();
+        symlink("../../old/sub/data", project.join("sub/through")).unwrap();
+        symlink(dir.path().join("outside"), project.join("outside")).unwrap();
+        project
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copies_recreate_symlinks_pointing_into_the_copy() {
+        let dir = TempDir::new();
+        let from = linked_project(&dir);
+        let to = dir.path().join("new");
+        copy_dir(&from, &to, false).unwrap();
+        let target = |link: &str| fs::read_link(to.join(link)).unwrap();
+        assert_eq!(target("sub/relative"), Path::new("data"));
+        assert_eq!(target("absolute"), to.join("sub/data"));
+        assert_eq!(target("sub/through"), Path::new("../../new/sub/data"));
+        assert_eq!(target("outside"), dir.path().join("outside"));
+        assert_eq!(fs::read_to_string(to.join("sub/through")).unwrap(), "data");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copies_can_follow_symlinks_instead() {
+        let dir = TempDir::new();
+        let from = linked_project(&dir);
+        std::os::unix::fs::symlink("sub", from.join("dir")).unwrap();
+        let to = dir.path().join("new");
+        copy_dir(&from, &to, true).unwrap();
+        for (path, contents) in [
+            ("sub/relative", "data"),
+            ("absolute", "data"),
+            ("outside", "outside"),
+            ("dir/data", "data"),
+        ] {
+            let copied = to.join(path);
+            assert!(!copied.is_symlink(), "{path} is still a symlink");
+            assert_eq!(fs::read_to_string(copied).unwrap(), contents);
+        }
+        assert!(!to.join("dir").is_symlink());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn following_a_symlink_into_a_loop_leaves_no_copy_behind() {
+        let dir = TempDir::new();
+        let from = linked_project(&dir);
+        std::os::unix::fs::symlink("..", from.join("sub/parent")).unwrap();
+        let to = dir.path().join("new");
+        let err = copy_dir(&from, &to, true).unwrap_err();
+        assert!(format!("{err:#}").contains("Cannot follow"), "{err:#}");
+        assert!(!to.exists());
+    }
+}
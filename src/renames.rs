@@ -0,0 +1,233 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    audit::{self, AuditLog},
+    filter::WalkFilter,
+    relocate,
+    replace::Replacer,
+};
+
+/// A planned rename of a file or directory inside the project.
+pub struct PathRename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Finds every file (that `filter` allows) and directory in the project
+/// `filter` walks (but not the project directory itself, nor deeper than
+/// `max_depth`) whose name contains the old name. Renames are ordered
+/// deepest-first, so applying them in order never invalidates a path that
+/// is still to come.
+pub fn plan_path_renames(
+    replacer: &Replacer,
+    filter: &WalkFilter,
+    max_depth: Option<usize>,
+) -> anyhow::Result<Vec<PathRename>> {
+    let mut renames = Vec::new();
+    for result in filter.walker().max_depth(max_depth).build() {
+        let entry = result?;
+        if entry.depth() == 0 {
+            continue;
+        }
+        let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
+        if !is_dir && !filter.allows(entry.path()) {
+            continue;
+        }
+        let Some(name) = entry.file_name().to_str() else {
+            continue;
+        };
+        if let (Cow::Owned(new_name), _) = replacer.replace(name) {
+            renames.push(PathRename {
+                to: entry.path().with_file_name(new_name),
+                from: entry.into_path(),
+            });
+        }
+    }
+    renames.sort_by_key(|rn| std::cmp::Reverse(rn.from.components().count()));
+    Ok(renames)
+}
+
+/// Refuses plans where two paths would be renamed to the same destination,
+/// or where a destination already exists.
+pub fn check_collisions(renames: &[PathRename]) -> anyhow::Result<()> {
+    let mut by_destination: HashMap<&Path, Vec<&Path>> = HashMap::new();
+    for rn in renames {
+        by_destination.entry(&rn.to).or_default().push(&rn.from);
+    }
+    let mut conflicts = Vec::new();
+    for rn in renames {
+        // A destination that is the path itself, spelled in another case
+        // on a case-insensitive filesystem, is just being recased.
+        if rn.to.exists() && !relocate::is_same_entry(&rn.from, &rn.to) {
+            conflicts.push(format!(
+                "  {} -> {} (destination already exists)",
+                rn.from.display(),
+                rn.to.display()
+            ));
+        }
+    }
+    for (to, froms) in &by_destination {
+        if froms.len() > 1 {
+            let froms = froms
+                .iter()
+                .map(|from| from.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            conflicts.push(format!("  {froms} -> {}", to.display()));
+        }
+    }
+    if !conflicts.is_empty() {
+        conflicts.sort();
+        bail!(
+            "Refusing to rename anything, {} conflict(s) found:\n{}",
+            conflicts.len(),
+            conflicts.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Moves planned renames from under `from` to under `to`, for when the
+/// project directory itself has been moved since they were planned.
+pub fn rebase(
+    renames: Vec<PathRename>,
+    from: &Path,
+    to: &Path,
+) -> Vec<PathRename> {
+    let rebase = |path: PathBuf| match path.strip_prefix(from) {
+        Ok(relative) => to.join(relative),
+        Err(_) => path,
+    };
+    renames
+        .into_iter()
+        .map(|rn| PathRename {
+            from: rebase(rn.from),
+            to: rebase(rn.to),
+        })
+        .collect()
+}
+
+/// Where `path` ends up once all of `renames` have been applied.
+pub fn renamed(renames: &[PathRename], path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    for rn in renames {
+        if let Ok(rest) = path.strip_prefix(&rn.from) {
+            // Joining an empty path would add a trailing separator.
+            path = if rest.as_os_str().is_empty() {
+                rn.to.clone()
+            } else {
+                rn.to.join(rest)
+            };
+        }
+    }
+    path
+}
+
+/// Writes where every renamed path was and where it is now to `file`, one
+/// tab-separated pair per line: the project directory itself (if it moved
+/// from `old_root` to `new_root`) and each of `renames`, which have been
+/// applied under `new_root`.
+pub fn write_rename_map(
+    file: &Path,
+    old_root: &Path,
+    new_root: &Path,
+    renames: &[PathRename],
+) -> anyhow::Result<()> {
+    let mut map = Vec::new();
+    if old_root != new_root {
+        map.push((old_root.to_path_buf(), new_root.to_path_buf()));
+    }
+    for rn in renames {
+        let relative = rn.from.strip_prefix(new_root).unwrap_or(&rn.from);
+        map.push((old_root.join(relative), renamed(renames, &rn.from)));
+    }
+    map.sort();
+    let text: String = map
+        .iter()
+        .map(|(old, new)| format!("{}\t{}\n", old.display(), new.display()))
+        .collect();
+    fs::write(file, text)
+        .with_context(|| format!("Could not write {}", file.display()))
+}
+
+pub fn print_path_renames(renames: &[PathRename]) {
+    for rn in renames {
+        println!("Would rename {} to {}.", rn.from.display(), rn.to.display());
+    }
+}
+
+pub fn apply_path_renames(
+    renames: &[PathRename],
+    audit: Option<&AuditLog>,
+) -> anyhow::Result<()> {
+    for rn in renames {
+        println!("Renaming {} to {}.", rn.from.display(), rn.to.display());
+        if relocate::is_same_entry(&rn.from, &rn.to) {
+            relocate::rename_case(&rn.from, &rn.to)?;
+        } else {
+            fs::rename(&rn.from, &rn.to).with_context(|| {
+                format!(
+                    "Failed to rename {} to {}.",
+                    rn.from.display(),
+                    rn.to.display()
+                )
+            })?;
+        }
+        audit::record(
+            audit,
+            "rename",
+            serde_json::json!({ "from": rn.from, "to": rn.to }),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn rename(from: PathBuf, to: PathBuf) -> PathRename {
+        PathRename { from, to }
+    }
+
+    #[test]
+    fn a_destination_that_is_the_path_itself_is_no_collision() {
+        let dir = TempDir::new();
+        let from = dir.write("Name.txt", "");
+        let to = dir.path().join("name.txt");
+        // Another name for the same file, as `name.txt` would be on a
+        // case-insensitive filesystem.
+        fs::hard_link(&from, &to).unwrap();
+        check_collisions(&[rename(from, to)]).unwrap();
+    }
+
+    #[test]
+    fn an_existing_destination_is_a_collision() {
+        let dir = TempDir::new();
+        let from = dir.write("Name.txt", "");
+        let to = dir.write("name.txt", "");
+        let err = check_collisions(&[rename(from, to)]).unwrap_err();
+        assert!(
+            err.to_string().contains("destination already exists"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn case_only_renames_are_applied() {
+        let dir = TempDir::new();
+        let from = dir.write("Name.txt", "contents");
+        let to = dir.path().join("name.txt");
+        apply_path_renames(&[rename(from.clone(), to.clone())], None).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(to).unwrap(), "contents");
+    }
+}
@@ -0,0 +1,625 @@
+use std::{
+    borrow::Cow,
+    ops::Range,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Context;
+use bpaf::Bpaf;
+use globset::{Glob, GlobMatcher};
+use regex::{Captures, Regex};
+
+use crate::names;
+
+/// How the old name is matched inside file contents.
+#[derive(Bpaf, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Treat the old name as a regular expression; the new name may refer to
+    /// capture groups (`$1`, `${name}`).
+    Regex,
+    /// Only match the old name where it is a complete identifier, i.e. not
+    /// directly preceded or followed by `[A-Za-z0-9_]`: `foo` matches in
+    /// `use foo;` but not in `foobar` or `foo_bar`.
+    Identifier,
+    /// Only match the old name where it is a whole word, i.e. not directly
+    /// preceded or followed by a letter or digit: `foo` matches in `foo_bar`
+    /// and `foo-bar` but not in `foobar`.
+    Word,
+    /// Match every way of writing the old name (`my-project`, `my_project`,
+    /// `MY_PROJECT`, `myProject` and `MyProject`; `foo`, `FOO` and `Foo` for
+    /// a single word), replacing each with the new name written the same
+    /// way.
+    AllCases,
+    /// Match the old name exactly, as a plain substring (the default).
+    #[bpaf(hide)]
+    Literal,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "literal" => Ok(Mode::Literal),
+            "identifier" => Ok(Mode::Identifier),
+            "word" => Ok(Mode::Word),
+            "all-cases" => Ok(Mode::AllCases),
+            "regex" => Ok(Mode::Regex),
+            _ => Err(format!(
+                "{s:?} is not a mode (expected literal, word, identifier, all-cases or regex)"
+            )),
+        }
+    }
+}
+
+/// Which [`Mode`] to match the old name with in some of the files: those
+/// matching a glob (relative to the project directory), or with an
+/// extension. Written `GLOB:MODE` or `ext=EXT:MODE`.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    glob: String,
+    mode: Mode,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((glob, mode)) = s.rsplit_once(':') else {
+            return Err(format!(
+                "expected GLOB:MODE or ext=EXT:MODE, got {s:?}"
+            ));
+        };
+        let glob = match glob.strip_prefix("ext=") {
+            Some(ext) => format!("*.{}", ext.trim_start_matches('.')),
+            None => glob.to_string(),
+        };
+        Ok(Rule {
+            glob,
+            mode: mode.parse()?,
+        })
+    }
+}
+
+#[derive(Clone)]
+enum Matcher {
+    Literal(String),
+    Identifier(String),
+    Word(String),
+    /// Each way of writing the old name, with the new name written the same
+    /// way (see [`Mode::AllCases`]).
+    AllCases(Vec<(String, String)>),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn new(from: &str, to: &str, mode: Mode) -> anyhow::Result<Self> {
+        Ok(match mode {
+            Mode::Literal => Matcher::Literal(from.to_string()),
+            Mode::Identifier => Matcher::Identifier(from.to_string()),
+            Mode::Word => Matcher::Word(from.to_string()),
+            Mode::AllCases => Matcher::AllCases(names::every_style(from, to)),
+            Mode::Regex => {
+                Matcher::Regex(Regex::new(from).with_context(|| {
+                    format!("...while compiling {from:?} as a regex")
+                })?)
+            }
+        })
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Start offsets of the occurrences of `token` in `haystack` that are not
+/// glued to other characters `is_part` takes as part of the same token.
+fn token_matches<'a>(
+    haystack: &'a str,
+    token: &'a str,
+    is_part: fn(char) -> bool,
+) -> impl Iterator<Item = usize> + 'a {
+    haystack.match_indices(token).filter_map(move |(start, _)| {
+        let end = start + token.len();
+        let before = haystack[..start].chars().next_back();
+        let after = haystack[end..].chars().next();
+        (!before.is_some_and(is_part) && !after.is_some_and(is_part))
+            .then_some(start)
+    })
+}
+
+/// Links to a repository on a forge (`https://host/owner/repo`,
+/// `git@host:owner/repo.git` and the like), whose repository part is to be
+/// replaced by `to`.
+#[derive(Clone)]
+struct UrlMatcher {
+    /// Matches up to and including the repository name.
+    re: Regex,
+    repo_len: usize,
+    to: String,
+}
+
+impl UrlMatcher {
+    fn new(host: &str, owner: &str, repo: &str, to: &str) -> Self {
+        let pattern = format!(
+            r"(?i:\b{})[:/](?i:{})/{}",
+            regex::escape(host),
+            regex::escape(owner),
+            regex::escape(repo)
+        );
+        UrlMatcher {
+            re: Regex::new(&pattern).unwrap(),
+            repo_len: repo.len(),
+            to: to.to_string(),
+        }
+    }
+
+    /// Byte ranges of the repository names in the links in `haystack`. A
+    /// link to a repository whose name merely starts with the old one is
+    /// left alone.
+    fn matches<'a>(
+        &'a self,
+        haystack: &'a str,
+    ) -> impl Iterator<Item = Range<usize>> + 'a {
+        self.re.find_iter(haystack).filter_map(|m| {
+            let after = &haystack[m.end()..];
+            let ends = after.starts_with(".git")
+                || !after.starts_with(|c: char| {
+                    c.is_ascii_alphanumeric() || "-_.".contains(c)
+                });
+            ends.then(|| m.end() - self.repo_len..m.end())
+        })
+    }
+}
+
+/// One replacement made by a [`Replacer`]: the bytes of the original that
+/// were replaced, and how long their replacement is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Substitution {
+    pub from: Range<usize>,
+    pub to_len: usize,
+}
+
+/// Puts back what `substitutions` (made in `original`, in order) replaced in
+/// `rewritten`, or returns `None` if they do not fit it. The rewrite only
+/// made those substitutions if this gives back `original`.
+pub fn undo(
+    original: &str,
+    rewritten: &str,
+    substitutions: &[Substitution],
+) -> Option<String> {
+    let mut undone = String::with_capacity(original.len());
+    let (mut last, mut pos) = (0, 0);
+    for sub in substitutions {
+        let kept = sub.from.start.checked_sub(last)?;
+        undone.push_str(rewritten.get(pos..pos + kept)?);
+        undone.push_str(original.get(sub.from.clone())?);
+        pos += kept + sub.to_len;
+        last = sub.from.end;
+    }
+    undone.push_str(rewritten.get(pos..)?);
+    Some(undone)
+}
+
+/// Replaces every occurrence of `from` with `to`, according to a [`Mode`].
+#[derive(Clone)]
+pub struct Replacer {
+    from: String,
+    matcher: Matcher,
+    to: String,
+    /// Maximum number of replacements per file; `0` means no limit.
+    limit: usize,
+    /// Forge links to rewrite as well, before (and instead of) any match of
+    /// `from` that overlaps them.
+    urls: Option<UrlMatcher>,
+    /// Other names to replace in the same pass, where they do not overlap
+    /// a match of `from` (or of an earlier one of them).
+    also: Vec<Replacer>,
+    /// Variants of this replacer, with a different [`Mode`], for the files
+    /// matching each glob: the first that matches wins.
+    rules: Vec<(GlobMatcher, Replacer)>,
+    /// Set once `from` has matched anywhere, by this replacer or a copy of
+    /// it (as the variants for `rules` are).
+    used: Arc<AtomicBool>,
+}
+
+/// Adds those of `new` that do not overlap any of `matches`.
+fn add_disjoint<'a>(
+    matches: &mut Vec<(Range<usize>, Cow<'a, str>)>,
+    new: impl Iterator<Item = (Range<usize>, Cow<'a, str>)>,
+) {
+    let n_old = matches.len();
+    for (range, to) in new {
+        if !matches[..n_old]
+            .iter()
+            .any(|(old, _)| old.start < range.end && range.start < old.end)
+        {
+            matches.push((range, to));
+        }
+    }
+}
+
+impl Replacer {
+    pub fn new(from: &str, to: &str, mode: Mode) -> anyhow::Result<Self> {
+        Ok(Replacer {
+            from: from.to_string(),
+            matcher: Matcher::new(from, to, mode)?,
+            to: to.to_string(),
+            limit: 0,
+            urls: None,
+            also: Vec::new(),
+            rules: Vec::new(),
+            used: Arc::default(),
+        })
+    }
+
+    /// Match in a different mode in the files that `rules` pick out. Set
+    /// this up last, since the variants are copies of the replacer as it is
+    /// now.
+    pub fn with_rules(self, rules: &[Rule]) -> anyhow::Result<Self> {
+        let mut variants = Vec::with_capacity(rules.len());
+        for rule in rules {
+            let glob = Glob::new(&rule.glob)
+                .with_context(|| format!("...while parsing {:?}", rule.glob))?
+                .compile_matcher();
+            variants.push((glob, self.in_mode(rule.mode)?));
+        }
+        Ok(Replacer {
+            rules: variants,
+            ..self
+        })
+    }
+
+    /// A copy of this replacer matching in `mode` instead, as do the names
+    /// it replaces alongside the old one (see [`Replacer::also`]).
+    fn in_mode(&self, mode: Mode) -> anyhow::Result<Self> {
+        let also = self
+            .also
+            .iter()
+            .map(|also| also.in_mode(mode))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Replacer {
+            matcher: Matcher::new(&self.from, &self.to, mode)?,
+            also,
+            rules: Vec::new(),
+            ..self.clone()
+        })
+    }
+
+    /// The replacer to use on the file at `path` (relative to the project
+    /// directory), according to the rules set with [`Replacer::with_rules`].
+    pub fn for_path(&self, path: &Path) -> &Replacer {
+        self.rules
+            .iter()
+            .find(|(glob, _)| glob.is_match(path))
+            .map_or(self, |(_, variant)| variant)
+    }
+
+    /// Also rewrite links to the repository `owner/repo` on `host` to point
+    /// at `new_repo`.
+    pub fn with_urls(
+        self,
+        host: &str,
+        owner: &str,
+        repo: &str,
+        new_repo: &str,
+    ) -> Self {
+        Replacer {
+            urls: Some(UrlMatcher::new(host, owner, repo, new_repo)),
+            ..self
+        }
+    }
+
+    /// Also replace `also`'s matches, in the same pass.
+    pub fn also(mut self, also: Replacer) -> Self {
+        self.also.push(also);
+        self
+    }
+
+    /// Only replace the first match in each file.
+    pub fn first_only(self) -> Self {
+        Replacer { limit: 1, ..self }
+    }
+
+    pub fn is_regex(&self) -> bool {
+        matches!(self.matcher, Matcher::Regex(_))
+    }
+
+    /// The names (with what they are replaced by) that this replacer, and
+    /// those it replaces alongside, have matched so far.
+    pub fn used_names(&self) -> Vec<(&str, &str)> {
+        let own = self.used.load(Ordering::Relaxed);
+        own.then_some((self.from.as_str(), self.to.as_str()))
+            .into_iter()
+            .chain(self.also.iter().flat_map(Replacer::used_names))
+            .collect()
+    }
+
+    /// Byte ranges of the matches in `haystack`, with what each one is to be
+    /// replaced by, in order.
+    fn matches<'a>(
+        &'a self,
+        haystack: &'a str,
+    ) -> Box<dyn Iterator<Item = (Range<usize>, Cow<'a, str>)> + 'a> {
+        if self.urls.is_none() && self.also.is_empty() {
+            return self.name_matches(haystack);
+        }
+        let mut matches = Vec::new();
+        if let Some(urls) = &self.urls {
+            matches.extend(
+                urls.matches(haystack)
+                    .map(|range| (range, Cow::Borrowed(urls.to.as_str()))),
+            );
+        }
+        add_disjoint(&mut matches, self.name_matches(haystack));
+        for also in &self.also {
+            add_disjoint(&mut matches, also.matches(haystack));
+        }
+        matches.sort_by_key(|(range, _)| range.start);
+        Box::new(matches.into_iter())
+    }
+
+    /// Like [`Replacer::matches`], for the old name alone.
+    fn name_matches<'a>(
+        &'a self,
+        haystack: &'a str,
+    ) -> Box<dyn Iterator<Item = (Range<usize>, Cow<'a, str>)> + 'a> {
+        Box::new(
+            self.matcher_matches(haystack)
+                .inspect(|_| self.used.store(true, Ordering::Relaxed)),
+        )
+    }
+
+    /// The matches of [`Replacer::name_matches`], as the matcher finds them.
+    fn matcher_matches<'a>(
+        &'a self,
+        haystack: &'a str,
+    ) -> Box<dyn Iterator<Item = (Range<usize>, Cow<'a, str>)> + 'a> {
+        let to = || Cow::Borrowed(self.to.as_str());
+        match &self.matcher {
+            Matcher::Literal(from) => Box::new(
+                haystack
+                    .match_indices(from.as_str())
+                    .map(move |(start, m)| (start..start + m.len(), to())),
+            ),
+            Matcher::Identifier(from) => Box::new(
+                token_matches(haystack, from, is_identifier_char)
+                    .map(move |start| (start..start + from.len(), to())),
+            ),
+            Matcher::Word(from) => Box::new(
+                token_matches(haystack, from, char::is_alphanumeric)
+                    .map(move |start| (start..start + from.len(), to())),
+            ),
+            Matcher::AllCases(variants) => {
+                let mut matches = Vec::new();
+                for (from, to) in variants {
+                    add_disjoint(
+                        &mut matches,
+                        haystack.match_indices(from.as_str()).map(
+                            |(start, m)| {
+                                (
+                                    start..start + m.len(),
+                                    Cow::Borrowed(to.as_str()),
+                                )
+                            },
+                        ),
+                    );
+                }
+                matches.sort_by_key(|(range, _)| range.start);
+                Box::new(matches.into_iter())
+            }
+            Matcher::Regex(re) => {
+                Box::new(re.captures_iter(haystack).map(|caps: Captures| {
+                    let mut dst = String::new();
+                    caps.expand(&self.to, &mut dst);
+                    (caps.get(0).unwrap().range(), Cow::Owned(dst))
+                }))
+            }
+        }
+    }
+
+    /// Replaces every match in `haystack`, returning the new contents and
+    /// the number of replacements made.
+    pub fn replace<'a>(&self, haystack: &'a str) -> (Cow<'a, str>, usize) {
+        let (replaced, substitutions) = self.replace_at(haystack);
+        (replaced, substitutions.len())
+    }
+
+    /// Like [`Replacer::replace`], but returns each replacement that was
+    /// made, in order.
+    pub fn replace_at<'a>(
+        &self,
+        haystack: &'a str,
+    ) -> (Cow<'a, str>, Vec<Substitution>) {
+        self.replace_matching(haystack, |_| true)
+    }
+
+    /// Like [`Replacer::replace_at`], but only replaces the matches lying
+    /// entirely within one of `within`, which are sorted byte ranges of
+    /// `haystack`.
+    pub fn replace_at_within<'a>(
+        &self,
+        haystack: &'a str,
+        within: &[Range<usize>],
+    ) -> (Cow<'a, str>, Vec<Substitution>) {
+        self.replace_matching(haystack, |m| {
+            let i = within.partition_point(|range| range.end < m.end);
+            within.get(i).is_some_and(|range| range.start <= m.start)
+        })
+    }
+
+    fn replace_matching<'a>(
+        &self,
+        haystack: &'a str,
+        keep: impl Fn(&Range<usize>) -> bool,
+    ) -> (Cow<'a, str>, Vec<Substitution>) {
+        let limit = if self.limit > 0 {
+            self.limit
+        } else {
+            usize::MAX
+        };
+        let mut replaced = String::new();
+        let mut last = 0;
+        let mut substitutions = Vec::new();
+        // A match that would be replaced with itself (as `also` variants or
+        // a regex can produce) is not a change, and not counted as one.
+        let matches = self.matches(haystack).filter(|(range, to)| {
+            keep(range) && haystack[range.clone()] != **to
+        });
+        for (range, to) in matches.take(limit) {
+            replaced.push_str(&haystack[last..range.start]);
+            replaced.push_str(&to);
+            last = range.end;
+            substitutions.push(Substitution {
+                from: range,
+                to_len: to.len(),
+            });
+        }
+        if substitutions.is_empty() {
+            return (Cow::Borrowed(haystack), substitutions);
+        }
+        replaced.push_str(&haystack[last..]);
+        if replaced == haystack {
+            return (Cow::Borrowed(haystack), Vec::new());
+        }
+        (Cow::Owned(replaced), substitutions)
+    }
+
+    /// Applies the replacement to its own output once more: a pattern is only
+    /// safe to re-run if `replace(replace(s)) == replace(s)`.
+    pub fn is_fixed_point(&self, replaced: &str) -> bool {
+        self.replace(replaced).0 == replaced
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replacing_a_match_with_itself_is_not_a_change() {
+        let replacer = Replacer::new("foo", "foo", Mode::Literal).unwrap();
+        let (replaced, count) = replacer.replace("a foo b");
+        assert!(matches!(replaced, Cow::Borrowed("a foo b")));
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn regex_matches_left_as_they_were_are_not_counted() {
+        let replacer = Replacer::new("f(o+)", "f$1", Mode::Regex).unwrap();
+        let (replaced, count) = replacer.replace("foo fooo");
+        assert!(matches!(replaced, Cow::Borrowed(_)));
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn only_real_changes_are_counted_alongside_no_ops() {
+        let replacer = Replacer::new("old", "new", Mode::Literal)
+            .unwrap()
+            .also(Replacer::new("same", "same", Mode::Literal).unwrap());
+        let (replaced, substitutions) = replacer.replace_at("old same old");
+        assert_eq!(replaced, "new same new");
+        let starts: Vec<_> =
+            substitutions.iter().map(|sub| sub.from.start).collect();
+        assert_eq!(starts, vec![0, 9]);
+    }
+
+    #[test]
+    fn undoing_the_substitutions_gives_back_the_original() {
+        let replacer =
+            Replacer::new("old-name", "the-new-name", Mode::Literal).unwrap();
+        let original = "old-name\nsee old-name\n\nno newline: old-name";
+        let (replaced, substitutions) = replacer.replace_at(original);
+        let undone = undo(original, &replaced, &substitutions);
+        assert_eq!(undone.as_deref(), Some(original));
+    }
+
+    #[test]
+    fn undoing_detects_any_other_change() {
+        let replacer = Replacer::new("old", "new", Mode::Literal).unwrap();
+        let original = "old\nold";
+        let (replaced, substitutions) = replacer.replace_at(original);
+        for tampered in [
+            format!("{replaced}\n"),
+            replaced.replace('\n', "\r\n"),
+            replaced[..replaced.len() - 1].to_string(),
+        ] {
+            assert_ne!(
+                undo(original, &tampered, &substitutions).as_deref(),
+                Some(original),
+                "{tampered:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn words_are_delimited_by_anything_but_letters_and_digits() {
+        let replacer = Replacer::new("foo", "bar", Mode::Word).unwrap();
+        let (replaced, _) = replacer.replace("foo_x foo-x foox xfoo (foo)");
+        assert_eq!(replaced, "bar_x bar-x foox xfoo (bar)");
+    }
+
+    #[test]
+    fn all_cases_replaces_each_way_of_writing_a_name_in_its_own() {
+        let replacer =
+            Replacer::new("my-app", "new-tool", Mode::AllCases).unwrap();
+        let (replaced, count) =
+            replacer.replace("my-app my_app MY_APP myApp MyApp");
+        assert_eq!(replaced, "new-tool new_tool NEW_TOOL newTool NewTool");
+        assert_eq!(count, 5);
+        let replacer = Replacer::new("pmv", "tool", Mode::AllCases).unwrap();
+        let (replaced, _) = replacer.replace("pmv, PMV and Pmv");
+        assert_eq!(replaced, "tool, TOOL and Tool");
+    }
+
+    #[test]
+    fn rules_match_the_names_replaced_alongside_in_their_mode_too() {
+        let mut styled = names::styled_variants("foo-bar", "baz_qux")
+            .unwrap()
+            .into_iter();
+        let (old, new) = styled.next().unwrap();
+        let mut replacer = Replacer::new(&old, &new, Mode::Literal).unwrap();
+        for (old, new) in styled {
+            replacer = replacer
+                .also(Replacer::new(&old, &new, Mode::Literal).unwrap());
+        }
+        let rules = ["ext=rs:identifier".parse().unwrap()];
+        let replacer = replacer.with_rules(&rules).unwrap();
+        let text = "foo_bar xfoo_bar foo-bar";
+        let in_rust = replacer.for_path(Path::new("src/lib.rs"));
+        assert_eq!(in_rust.replace(text).0, "baz_qux xfoo_bar baz-qux");
+        let elsewhere = replacer.for_path(Path::new("README"));
+        assert_eq!(elsewhere.replace(text).0, "baz_qux xbaz_qux baz-qux");
+    }
+
+    #[test]
+    fn rules_are_parsed_with_every_mode() {
+        for mode in ["literal", "word", "identifier", "all-cases", "regex"] {
+            let rule: Rule = format!("ext=md:{mode}").parse().unwrap();
+            assert_eq!(rule.glob, "*.md");
+        }
+        assert!("ext=md:fuzzy".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn only_the_names_matched_are_used() {
+        let replacer = Replacer::new("my-app", "new-app", Mode::Literal)
+            .unwrap()
+            .also(Replacer::new("my_app", "new_app", Mode::Literal).unwrap())
+            .also(Replacer::new("MyApp", "NewApp", Mode::Literal).unwrap());
+        assert!(replacer.used_names().is_empty());
+        replacer.replace("use my_app;");
+        let rules = ["ext=md:word".parse().unwrap()];
+        let replacer = replacer.with_rules(&rules).unwrap();
+        replacer.for_path(Path::new("README.md")).replace("MyApp");
+        assert_eq!(
+            replacer.used_names(),
+            [("my_app", "new_app"), ("MyApp", "NewApp")]
+        );
+    }
+}
@@ -0,0 +1,318 @@
+#[cfg(not(feature = "trace"))]
+use std::io::BufWriter;
+use std::{
+    fmt::{self, Arguments, Display},
+    io::{self, IsTerminal, Write},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+/// Asks a yes/no question on the terminal, where anything but a yes counts
+/// as a no. When stdin is not a terminal there is nobody to answer, so
+/// rather than taking that for a no (and quietly doing nothing), it fails,
+/// pointing at `skip_with`: the option that answers yes in advance.
+pub fn confirm(question: Arguments, skip_with: &str) -> io::Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Err(io::Error::other(format!(
+            "Cannot ask \"{question}\", since stdin is not a terminal: pass {skip_with} to go ahead without asking."
+        )));
+    }
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Sets up the `tracing` subscriber. Spans (with their timings) are only
+/// emitted when `RUST_LOG` is set or `--trace` was passed; otherwise only the
+/// regular progress messages are printed.
+#[cfg(feature = "trace")]
+pub fn init_tracing(trace: bool) {
+    use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::new(if trace { "pmv=trace" } else { "pmv=info" })
+    });
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_target(false)
+        .init();
+}
+
+/// How progress and results are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// As messages for people to read.
+    Text,
+    /// As one JSON object per line, each file's as soon as it is done (see
+    /// [`emit`]).
+    Jsonl,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "jsonl" => Ok(Format::Jsonl),
+            _ => Err(format!("{s:?} is not a format: use text or jsonl")),
+        }
+    }
+}
+
+/// Writes `event` to stdout as a line of its own, for `--format jsonl`.
+pub fn emit(event: Value) {
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{event}");
+}
+
+/// Where the collector thread sends its progress messages: straight to stdout,
+/// or through the `tracing` layer when the `trace` feature is enabled.
+pub struct Reporter {
+    verbose: bool,
+    format: Format,
+    /// Report nothing at all.
+    quiet: bool,
+    #[cfg(not(feature = "trace"))]
+    stdout: BufWriter<Box<dyn Write + Send>>,
+}
+
+impl Reporter {
+    pub fn new(verbose: bool, format: Format) -> Self {
+        Reporter {
+            verbose,
+            format,
+            quiet: false,
+            #[cfg(not(feature = "trace"))]
+            stdout: BufWriter::new(Box::new(std::io::stdout())),
+        }
+    }
+
+    /// A reporter that drops every message, for library calls that should
+    /// not print anything.
+    pub fn quiet() -> Self {
+        Reporter {
+            quiet: true,
+            ..Reporter::new(false, Format::Text)
+        }
+    }
+
+    /// A reporter writing to `out` instead of stdout.
+    #[cfg(all(test, not(feature = "trace")))]
+    pub fn with_writer(
+        verbose: bool,
+        format: Format,
+        out: impl Write + Send + 'static,
+    ) -> Self {
+        Reporter {
+            verbose,
+            format,
+            quiet: false,
+            stdout: BufWriter::new(Box::new(out)),
+        }
+    }
+
+    pub fn is_verbose(&self) -> bool {
+        self.verbose
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// A JSON line (see [`emit`]), written in order with the other messages.
+    pub fn event(&mut self, event: Value) -> io::Result<()> {
+        if self.quiet {
+            return Ok(());
+        }
+        #[cfg(feature = "trace")]
+        emit(event);
+        #[cfg(not(feature = "trace"))]
+        writeln!(self.stdout, "{event}")?;
+        Ok(())
+    }
+
+    /// Only reported under `--verbose`.
+    pub fn detail(&mut self, msg: Arguments) -> io::Result<()> {
+        if self.verbose {
+            self.info(msg)?;
+        }
+        Ok(())
+    }
+
+    pub fn info(&mut self, msg: Arguments) -> io::Result<()> {
+        if self.quiet {
+            return Ok(());
+        }
+        #[cfg(feature = "trace")]
+        tracing::info!("{msg}");
+        #[cfg(not(feature = "trace"))]
+        writeln!(self.stdout, "{msg}")?;
+        Ok(())
+    }
+
+    pub fn warn(&mut self, msg: Arguments) -> io::Result<()> {
+        if self.quiet {
+            return Ok(());
+        }
+        #[cfg(feature = "trace")]
+        tracing::warn!("{msg}");
+        #[cfg(not(feature = "trace"))]
+        writeln!(self.stdout, "{msg}")?;
+        Ok(())
+    }
+
+    /// Writes out what is buffered so far, before asking a question with
+    /// [`confirm`].
+    pub fn flush(&mut self) -> io::Result<()> {
+        #[cfg(not(feature = "trace"))]
+        self.stdout.flush()?;
+        Ok(())
+    }
+
+    /// Flushes buffered output, so that write errors surface here rather
+    /// than being swallowed when the reporter is dropped.
+    pub fn finish(self) -> io::Result<()> {
+        #[cfg(not(feature = "trace"))]
+        self.stdout.into_inner().map_err(|err| err.into_error())?;
+        Ok(())
+    }
+}
+
+/// Wall-clock time spent in each phase of a run, for `--timings`.
+pub struct Timings {
+    enabled: bool,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    pub fn new(enabled: bool) -> Self {
+        Timings {
+            enabled,
+            phases: Vec::new(),
+        }
+    }
+
+    /// Records `phase` as having run from `started` until now.
+    pub fn record(&mut self, phase: &'static str, started: Instant) {
+        self.phases.push((phase, started.elapsed()));
+    }
+
+    pub fn report(&self) {
+        if !self.enabled {
+            return;
+        }
+        println!("Timings:");
+        for (phase, duration) in &self.phases {
+            println!("  {phase:<8} {:.3}s", duration.as_secs_f64());
+        }
+    }
+}
+
+/// How much the rewritten files grew and shrank by, in bytes, as git's
+/// `--shortstat` reports insertions and deletions. Recorded as each file is
+/// written, from whichever threads write them.
+#[derive(Default)]
+pub struct DiffStat {
+    files: AtomicU64,
+    added: AtomicU64,
+    removed: AtomicU64,
+}
+
+impl DiffStat {
+    /// Records a file that was `before` bytes long being rewritten to be
+    /// `after` bytes long.
+    pub fn record(&self, before: u64, after: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        if after > before {
+            self.added.fetch_add(after - before, Ordering::Relaxed);
+        } else {
+            self.removed.fetch_add(before - after, Ordering::Relaxed);
+        }
+    }
+
+    pub fn added(&self) -> u64 {
+        self.added.load(Ordering::Relaxed)
+    }
+
+    pub fn removed(&self) -> u64 {
+        self.removed.load(Ordering::Relaxed)
+    }
+}
+
+impl Display for DiffStat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} file(s) changed, +{}/-{} bytes",
+            self.files.load(Ordering::Relaxed),
+            self.added(),
+            self.removed()
+        )
+    }
+}
+
+/// How long a run may take, for `--timeout`: a number of seconds, or a number
+/// followed by `ms`, `s`, `m` or `h`.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeout(Duration);
+
+impl FromStr for Timeout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (amount, unit) = s.split_at(split);
+        let amount: u64 = amount.parse().map_err(|_| {
+            format!("{s:?} is not a duration (such as 90s or 5m)")
+        })?;
+        let duration = match unit {
+            "ms" => Duration::from_millis(amount),
+            "" | "s" => Duration::from_secs(amount),
+            "m" => Duration::from_secs(amount * 60),
+            "h" => Duration::from_secs(amount * 60 * 60),
+            _ => {
+                return Err(format!(
+                    "{unit:?} is not a unit of time (use ms, s, m or h)"
+                ))
+            }
+        };
+        Ok(Timeout(duration))
+    }
+}
+
+impl Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1}s", self.0.as_secs_f64())
+    }
+}
+
+impl Timeout {
+    /// Sets `cancelled` (as Ctrl-C does) once the timeout is up, and
+    /// `timed_out` along with it.
+    pub fn start(
+        self,
+        cancelled: Arc<AtomicBool>,
+        timed_out: &'static AtomicBool,
+    ) {
+        thread::spawn(move || {
+            thread::sleep(self.0);
+            timed_out.store(true, Ordering::Relaxed);
+            if !cancelled.swap(true, Ordering::Relaxed) {
+                println!(
+                    "Timed out after {self}: cancelling once the files in progress are done."
+                );
+            }
+        });
+    }
+}
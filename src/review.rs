@@ -0,0 +1,190 @@
+use std::path::Path;
+
+use anyhow::Context;
+use encoding_rs::Encoding;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+
+use crate::{read_text, Rewrite};
+
+const HELP: &str = " ↑/↓ select · space toggle · PgUp/PgDn scroll diff · \
+                    a apply · q abort ";
+
+/// One entry of the review list.
+struct Item {
+    selected: bool,
+    diff: Vec<Line<'static>>,
+}
+
+struct Review<'a> {
+    rewrites: &'a [Rewrite],
+    items: Vec<Item>,
+    list: ListState,
+    scroll: u16,
+}
+
+/// Lines that differ between `original` and `rewritten`, paired up by line
+/// number. Replacements rarely add or remove lines, so this is close enough
+/// to a real diff for reviewing a rename.
+fn line_diff(original: &str, rewritten: &str) -> Vec<Line<'static>> {
+    let mut old = original.lines();
+    let mut new = rewritten.lines();
+    let mut diff = Vec::new();
+    for n in 1.. {
+        let (o, r) = (old.next(), new.next());
+        if o.is_none() && r.is_none() {
+            break;
+        }
+        if o == r {
+            continue;
+        }
+        diff.push(Line::styled(
+            format!("@@ line {n}"),
+            Style::new().fg(Color::Cyan),
+        ));
+        if let Some(o) = o {
+            diff.push(Line::styled(
+                format!("-{o}"),
+                Style::new().fg(Color::Red),
+            ));
+        }
+        if let Some(r) = r {
+            diff.push(Line::styled(
+                format!("+{r}"),
+                Style::new().fg(Color::Green),
+            ));
+        }
+    }
+    diff
+}
+
+impl Review<'_> {
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, help] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)])
+                .areas(frame.area());
+        let [files, diff] = Layout::horizontal([
+            Constraint::Percentage(40),
+            Constraint::Percentage(60),
+        ])
+        .areas(main);
+
+        let n_selected = self.items.iter().filter(|it| it.selected).count();
+        let list = List::new(self.rewrites.iter().zip(&self.items).map(
+            |(rw, item)| {
+                let mark = if item.selected { "[x]" } else { "[ ]" };
+                ListItem::new(Line::from(vec![
+                    Span::raw(format!("{mark} {} ", rw.path.display())),
+                    Span::styled(
+                        format!("({})", rw.count),
+                        Style::new().fg(Color::Yellow),
+                    ),
+                ]))
+            },
+        ))
+        .block(Block::bordered().title(format!(
+            " Files ({n_selected}/{} selected) ",
+            self.rewrites.len()
+        )))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, files, &mut self.list);
+
+        let lines = self
+            .list
+            .selected()
+            .map(|i| self.items[i].diff.clone())
+            .unwrap_or_default();
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(Block::bordered().title(" Diff "))
+                .scroll((self.scroll, 0)),
+            diff,
+        );
+        frame.render_widget(Line::raw(HELP), help);
+    }
+
+    /// Runs the event loop; `true` means the user chose to apply.
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<bool> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                KeyCode::Char('a') | KeyCode::Enter => return Ok(true),
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.list.select_next();
+                    self.scroll = 0;
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.list.select_previous();
+                    self.scroll = 0;
+                }
+                KeyCode::Char(' ') => {
+                    if let Some(item) =
+                        self.list.selected().and_then(|i| self.items.get_mut(i))
+                    {
+                        item.selected = !item.selected;
+                    }
+                }
+                KeyCode::PageDown => {
+                    self.scroll = self.scroll.saturating_add(10)
+                }
+                KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(10),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Lets the user go through the planned rewrites (which are relative to
+/// `root`) and pick which to apply. Returns `None` if the run was aborted.
+pub fn review(
+    root: &Path,
+    rewrites: Vec<Rewrite>,
+    encoding: Option<&'static Encoding>,
+) -> anyhow::Result<Option<Vec<Rewrite>>> {
+    let items = rewrites
+        .iter()
+        .map(|rw| {
+            let fp = root.join(&rw.path);
+            let original = read_text(&fp, encoding)?.with_context(|| {
+                format!("{} is no longer valid text.", fp.display())
+            })?;
+            Ok(Item {
+                selected: true,
+                diff: line_diff(&original, &rw.contents),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut review = Review {
+        rewrites: &rewrites,
+        items,
+        list: ListState::default().with_selected(Some(0)),
+        scroll: 0,
+    };
+    let mut terminal = ratatui::try_init()?;
+    let outcome = review.run(&mut terminal);
+    ratatui::try_restore()?;
+
+    let selected: Vec<bool> =
+        review.items.iter().map(|it| it.selected).collect();
+    Ok(outcome?.then(|| {
+        rewrites
+            .into_iter()
+            .zip(selected)
+            .filter_map(|(rw, selected)| selected.then_some(rw))
+            .collect()
+    }))
+}
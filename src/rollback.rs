@@ -0,0 +1,150 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{
+    audit::{self, AuditLog},
+    relocate,
+    renames::PathRename,
+    write_atomically,
+};
+
+/// What it takes to put a project back as it was before a run, for
+/// `--rollback-on-gh-failure`: the original contents of every file about to
+/// be rewritten, kept in memory.
+pub struct Backup {
+    /// Paths relative to the project, as they were before any of its paths
+    /// were renamed.
+    files: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl Backup {
+    /// Reads the files at `paths` (relative to `root`) as they are now.
+    pub fn take<'a>(
+        root: &Path,
+        paths: impl Iterator<Item = &'a Path>,
+    ) -> anyhow::Result<Self> {
+        let files = paths
+            .map(|path| {
+                let fp = root.join(path);
+                let contents = fs::read(&fp).with_context(|| {
+                    format!("Could not back up {}", fp.display())
+                })?;
+                Ok((path.to_path_buf(), contents))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Backup { files })
+    }
+
+    /// Undoes a run that moved the project from `old_root` to `new_root`
+    /// (unless the two are the same), rewrote the backed-up files and then
+    /// applied `path_renames` (under `new_root`), reporting each step.
+    pub fn restore(
+        self,
+        old_root: &Path,
+        new_root: &Path,
+        path_renames: &[PathRename],
+        audit: Option<&AuditLog>,
+    ) -> anyhow::Result<()> {
+        println!("Rolling back the changes made to the project:");
+        for rn in path_renames.iter().rev() {
+            println!(
+                "  Renaming {} back to {}.",
+                rn.to.display(),
+                rn.from.display()
+            );
+            fs::rename(&rn.to, &rn.from).with_context(|| {
+                format!(
+                    "Failed to rename {} back to {}.",
+                    rn.to.display(),
+                    rn.from.display()
+                )
+            })?;
+        }
+        for (path, contents) in &self.files {
+            println!("  Restoring {}.", path.display());
+            write_atomically(&new_root.join(path), contents)?;
+        }
+        if old_root != new_root {
+            println!(
+                "  Moving {} back to {}.",
+                new_root.display(),
+                old_root.display()
+            );
+            if relocate::is_same_entry(new_root, old_root) {
+                relocate::rename_case(new_root, old_root)?;
+            } else {
+                relocate::move_dir(new_root, old_root, false, false, true)?;
+            }
+        }
+        audit::record(
+            audit,
+            "rollback",
+            serde_json::json!({
+                "restored": self.files.len(),
+                "renames_undone": path_renames.len(),
+                "moved_back": old_root != new_root,
+            }),
+        )?;
+        println!(
+            "Rolled back {} rewritten file(s), {} renamed path(s){}.",
+            self.files.len(),
+            path_renames.len(),
+            if old_root != new_root {
+                " and the move"
+            } else {
+                ""
+            }
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn restoring_undoes_the_move_renames_and_rewrites() {
+        let dir = TempDir::new();
+        dir.write("old-name/old-name.txt", "old-name\n");
+        dir.write("old-name/src/lib.rs", "use old_name;\n");
+        dir.write("old-name/untouched", "old-name\n");
+        let (old, new) =
+            (dir.path().join("old-name"), dir.path().join("new-name"));
+        let rewritten = [Path::new("old-name.txt"), Path::new("src/lib.rs")];
+        let backup = Backup::take(&old, rewritten.into_iter()).unwrap();
+
+        fs::rename(&old, &new).unwrap();
+        dir.write("new-name/old-name.txt", "new-name\n");
+        dir.write("new-name/src/lib.rs", "use new_name;\n");
+        let renames = [PathRename {
+            from: new.join("old-name.txt"),
+            to: new.join("new-name.txt"),
+        }];
+        fs::rename(&renames[0].from, &renames[0].to).unwrap();
+
+        backup.restore(&old, &new, &renames, None).unwrap();
+        assert!(!new.exists());
+        let read = |path: &str| fs::read_to_string(old.join(path)).unwrap();
+        assert_eq!(read("old-name.txt"), "old-name\n");
+        assert_eq!(read("src/lib.rs"), "use old_name;\n");
+        assert_eq!(read("untouched"), "old-name\n");
+        assert!(!old.join("new-name.txt").exists());
+    }
+
+    #[test]
+    fn restoring_in_place_only_rewrites() {
+        let dir = TempDir::new();
+        let path = dir.write("file", "before");
+        let backup =
+            Backup::take(dir.path(), [Path::new("file")].into_iter()).unwrap();
+        fs::write(&path, "after").unwrap();
+        backup.restore(dir.path(), dir.path(), &[], None).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "before");
+    }
+}
@@ -0,0 +1,207 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use xshell::{cmd, Shell};
+
+use crate::{git, renames::PathRename, replace::Replacer};
+
+/// A submodule declared in a `.gitmodules` file.
+pub struct Submodule {
+    pub name: String,
+    /// Relative to the top of the repository declaring it.
+    pub path: PathBuf,
+    pub url: String,
+}
+
+/// The submodules declared in the `.gitmodules` at the top of `dir`, if it
+/// has one.
+pub fn list(sh: &Shell, dir: &Path) -> anyhow::Result<Vec<Submodule>> {
+    if !dir.join(".gitmodules").is_file() {
+        return Ok(Vec::new());
+    }
+    let _guard = sh.push_dir(dir);
+    let entries = cmd!(
+        sh,
+        "git config -f .gitmodules --get-regexp ^submodule\\..*\\.(path|url)$"
+    )
+    .quiet()
+    .ignore_status()
+    .read()
+    .with_context(|| {
+        format!("...while reading {}", dir.join(".gitmodules").display())
+    })?;
+    let mut submodules: Vec<Submodule> = Vec::new();
+    for line in entries.lines() {
+        let Some((key, value)) = line.split_once(' ') else {
+            continue;
+        };
+        let Some(key) = key.strip_prefix("submodule.") else {
+            continue;
+        };
+        let (name, field) = match key.rsplit_once('.') {
+            Some((name, field)) => (name, field),
+            None => continue,
+        };
+        let i = match submodules.iter().position(|s| s.name == name) {
+            Some(i) => i,
+            None => {
+                submodules.push(Submodule {
+                    name: name.to_string(),
+                    path: PathBuf::new(),
+                    url: String::new(),
+                });
+                submodules.len() - 1
+            }
+        };
+        match field {
+            "path" => submodules[i].path = PathBuf::from(value),
+            _ => submodules[i].url = value.to_string(),
+        }
+    }
+    submodules.retain(|s| !s.path.as_os_str().is_empty());
+    Ok(submodules)
+}
+
+/// A submodule whose URL mentions the old name.
+pub struct UrlUpdate {
+    pub name: String,
+    pub to: String,
+}
+
+pub fn plan_url_updates(
+    submodules: &[Submodule],
+    replacer: &Replacer,
+) -> Vec<UrlUpdate> {
+    submodules
+        .iter()
+        .filter_map(|s| {
+            let (url, count) = replacer.replace(&s.url);
+            (count > 0).then(|| UrlUpdate {
+                name: s.name.clone(),
+                to: url.into_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Whether renaming `path` (under `root`) moves one of `submodules`: it is
+/// one, or contains one. Those renames have to go through `git mv`, which
+/// also updates `.gitmodules`, the index and the submodule's own git
+/// directory.
+pub fn moves_submodule(
+    submodules: &[Submodule],
+    root: &Path,
+    path: &Path,
+) -> bool {
+    submodules
+        .iter()
+        .any(|s| root.join(&s.path).starts_with(path))
+}
+
+pub fn print_url_updates(updates: &[UrlUpdate]) {
+    for update in updates {
+        println!("Would point submodule {:?} at {}.", update.name, update.to);
+    }
+}
+
+/// Carries out `renames` (which move submodules of the repository at `root`)
+/// with `git mv`, points the submodules in `updates` at their new URLs, and
+/// then has git pick up the changes with `git submodule sync`.
+pub fn apply(
+    sh: &Shell,
+    root: &Path,
+    renames: &[PathRename],
+    updates: &[UrlUpdate],
+) -> anyhow::Result<()> {
+    if renames.is_empty() && updates.is_empty() {
+        return Ok(());
+    }
+    let _guard = sh.push_dir(root);
+    for rn in renames {
+        let (from, to) = (&rn.from, &rn.to);
+        println!(
+            "Renaming {} to {} (with git mv).",
+            from.display(),
+            to.display()
+        );
+        cmd!(sh, "git mv -- {from} {to}")
+            .quiet()
+            .run()
+            .with_context(|| {
+                format!("...while moving the submodule at {}", from.display())
+            })?;
+    }
+    for update in updates {
+        let (key, to) = (format!("submodule.{}.url", update.name), &update.to);
+        println!("Pointing submodule {:?} at {to}.", update.name);
+        cmd!(sh, "git config -f .gitmodules {key} {to}")
+            .quiet()
+            .run()
+            .with_context(|| {
+                format!(
+                    "...while updating the URL of submodule {:?}",
+                    update.name
+                )
+            })?;
+    }
+    sync(sh)
+}
+
+fn sync(sh: &Shell) -> anyhow::Result<()> {
+    cmd!(sh, "git submodule --quiet sync --recursive")
+        .quiet()
+        .run()
+        .with_context(|| "...while syncing the submodules")
+}
+
+/// The project at `dir`, as a submodule of the repository around it.
+pub struct Enclosing {
+    /// The top of the enclosing repository.
+    pub top: PathBuf,
+    pub submodule: Submodule,
+}
+
+/// Finds the repository that has the project at `dir` as one of its
+/// submodules, if any.
+pub fn enclosing(sh: &Shell, dir: &Path) -> anyhow::Result<Option<Enclosing>> {
+    let Some(top) = dir.parent().and_then(|parent| git::toplevel(sh, parent))
+    else {
+        return Ok(None);
+    };
+    let top = top.canonicalize().unwrap_or(top);
+    let submodule = list(sh, &top)?
+        .into_iter()
+        .find(|s| top.join(&s.path) == dir);
+    Ok(submodule.map(|submodule| Enclosing { top, submodule }))
+}
+
+/// Moves the project to `new_path` with `git mv` in the repository it is a
+/// submodule of, and rewrites its URL there with `replacer`. Fails (leaving
+/// everything as it was) if `new_path` is outside that repository.
+pub fn move_enclosed(
+    sh: &Shell,
+    enclosing: &Enclosing,
+    new_path: &Path,
+    replacer: &Replacer,
+) -> anyhow::Result<()> {
+    let from = enclosing.top.join(&enclosing.submodule.path);
+    if !new_path.starts_with(&enclosing.top) {
+        bail!(
+            "{} is a submodule of {}, and cannot be moved out of it to {}.",
+            from.display(),
+            enclosing.top.display(),
+            new_path.display()
+        );
+    }
+    let updates =
+        plan_url_updates(std::slice::from_ref(&enclosing.submodule), replacer);
+    apply(
+        sh,
+        &enclosing.top,
+        &[PathRename {
+            from,
+            to: new_path.to_path_buf(),
+        }],
+        &updates,
+    )
+}
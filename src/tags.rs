@@ -0,0 +1,279 @@
+use std::{collections::HashSet, path::Path, str::FromStr};
+
+use anyhow::{bail, Context};
+use regex::Regex;
+use xshell::{cmd, Shell};
+
+use crate::{
+    audit::{self, AuditLog},
+    report::confirm,
+};
+
+/// How to rename tags, given sed-style as `s/OLD/NEW/`: every tag with a
+/// match of the regex OLD in its name gets that match replaced by NEW (which
+/// may refer to capture groups as `$1`). Any character can stand in for
+/// the `/`.
+#[derive(Debug, Clone)]
+pub struct TagRename {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl FromStr for TagRename {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || format!("{s:?} is not of the form s/OLD/NEW/");
+        let rest = s.strip_prefix('s').ok_or_else(malformed)?;
+        let delimiter = rest.chars().next().ok_or_else(malformed)?;
+        let parts: Vec<&str> = rest[delimiter.len_utf8()..]
+            .strip_suffix(delimiter)
+            .unwrap_or(&rest[delimiter.len_utf8()..])
+            .split(delimiter)
+            .collect();
+        let [old, new] = parts[..] else {
+            return Err(malformed());
+        };
+        if old.is_empty() {
+            return Err(malformed());
+        }
+        let pattern = Regex::new(old)
+            .map_err(|err| format!("{old:?} is not a valid regex: {err}"))?;
+        Ok(TagRename {
+            pattern,
+            replacement: new.to_string(),
+        })
+    }
+}
+
+/// A planned rename of a local tag.
+pub struct TagMove {
+    pub from: String,
+    pub to: String,
+}
+
+/// Works out which of the tags in the repository around `dir` `rename`
+/// applies to, refusing plans where a new name is not a valid tag name, is
+/// already taken, or is shared by two tags.
+pub fn plan(
+    sh: &Shell,
+    dir: &Path,
+    rename: &TagRename,
+) -> anyhow::Result<Vec<TagMove>> {
+    let _guard = sh.push_dir(dir);
+    let tags = cmd!(sh, "git tag --list")
+        .quiet()
+        .read()
+        .with_context(|| "...while listing the tags")?;
+    let existing: HashSet<&str> = tags.lines().collect();
+    let mut moves = Vec::new();
+    let mut conflicts = Vec::new();
+    let mut taken = HashSet::new();
+    for tag in tags.lines() {
+        let to = rename.pattern.replace_all(tag, &rename.replacement);
+        if to == tag {
+            continue;
+        }
+        let to = to.into_owned();
+        let reference = format!("refs/tags/{to}");
+        if cmd!(sh, "git check-ref-format {reference}")
+            .quiet()
+            .ignore_stderr()
+            .run()
+            .is_err()
+        {
+            conflicts.push(format!("  {tag} -> {to} (not a valid tag name)"));
+        } else if existing.contains(to.as_str()) {
+            conflicts.push(format!("  {tag} -> {to} (tag already exists)"));
+        } else if !taken.insert(to.clone()) {
+            conflicts.push(format!(
+                "  {tag} -> {to} (another tag is renamed to it too)"
+            ));
+        }
+        moves.push(TagMove {
+            from: tag.to_string(),
+            to,
+        });
+    }
+    if !conflicts.is_empty() {
+        bail!(
+            "Refusing to rename any tags, {} conflict(s) found:\n{}",
+            conflicts.len(),
+            conflicts.join("\n")
+        );
+    }
+    Ok(moves)
+}
+
+pub fn print_moves(moves: &[TagMove], push: Option<&str>) {
+    for mv in moves {
+        println!("Would rename tag {} to {}.", mv.from, mv.to);
+    }
+    if let (Some(remote), false) = (push, moves.is_empty()) {
+        println!("Would push the renamed tags to {remote}, deleting the old ones there.");
+    }
+}
+
+/// Renames the tags in `moves` in the repository around `dir`, once the user
+/// agrees (or with `yes`), then pushes the new ones to the remote `push` and
+/// deletes the old ones there, if given. An annotated tag is recreated with
+/// its message, tagger and date (but not its signature, if it had one).
+pub fn apply(
+    sh: &Shell,
+    dir: &Path,
+    moves: &[TagMove],
+    push: Option<&str>,
+    yes: bool,
+    audit: Option<&AuditLog>,
+) -> anyhow::Result<()> {
+    if moves.is_empty() {
+        return Ok(());
+    }
+    for mv in moves {
+        println!("  {} -> {}", mv.from, mv.to);
+    }
+    let where_ = match push {
+        Some(remote) => format!(" locally and on {remote}"),
+        None => String::new(),
+    };
+    if !yes
+        && !confirm(
+            format_args!("Rename these {} tag(s){where_}?", moves.len()),
+            "--yes",
+        )?
+    {
+        println!("Left the tags as they were.");
+        return Ok(());
+    }
+    let _guard = sh.push_dir(dir);
+    for mv in moves {
+        let (from, to) = (&mv.from, &mv.to);
+        println!("Renaming tag {from} to {to}.");
+        retag(sh, from, to)
+            .with_context(|| format!("...while renaming tag {from} to {to}"))?;
+        audit::record(
+            audit,
+            "tag",
+            serde_json::json!({ "from": from, "to": to }),
+        )?;
+    }
+    if let Some(remote) = push {
+        let refspecs: Vec<String> = moves
+            .iter()
+            .flat_map(|mv| {
+                [
+                    format!("refs/tags/{}", mv.to),
+                    format!(":refs/tags/{}", mv.from),
+                ]
+            })
+            .collect();
+        println!("Pushing the renamed tags to {remote}.");
+        cmd!(sh, "git push --quiet --atomic {remote} {refspecs...}")
+            .run()
+            .with_context(|| {
+                format!("...while pushing the renamed tags to {remote}")
+            })?;
+    }
+    Ok(())
+}
+
+/// Creates the tag `to` as a copy of `from`, then deletes `from`.
+fn retag(sh: &Shell, from: &str, to: &str) -> anyhow::Result<()> {
+    let reference = format!("refs/tags/{from}");
+    let kind = cmd!(sh, "git for-each-ref --format=%(objecttype) {reference}")
+        .quiet()
+        .read()?;
+    if kind == "tag" {
+        let format = "%(taggername)%00%(taggeremail:trim)%00%(taggerdate:raw)%00%(contents:subject)%00%(contents:body)";
+        let fields = cmd!(sh, "git for-each-ref --format={format} {reference}")
+            .quiet()
+            .read()?;
+        let [name, email, date, subject, body] =
+            fields.splitn(5, '\0').collect::<Vec<_>>()[..]
+        else {
+            bail!("Could not read the annotated tag {from}.");
+        };
+        let message = format!("{subject}\n\n{body}");
+        let target = format!("{from}^{{}}");
+        cmd!(sh, "git tag -a -m {message} {to} {target}")
+            .env("GIT_COMMITTER_NAME", name)
+            .env("GIT_COMMITTER_EMAIL", email)
+            .env("GIT_COMMITTER_DATE", date)
+            .quiet()
+            .run()?;
+    } else {
+        cmd!(sh, "git tag {to} {from}").quiet().run()?;
+    }
+    cmd!(sh, "git tag --delete {from}")
+        .quiet()
+        .ignore_stdout()
+        .run()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    const GIT: [&str; 4] =
+        ["-c", "user.name=pmv", "-c", "user.email=pmv@localhost"];
+
+    /// A repository in `dir` with two commits, the first tagged `v1-old`
+    /// (annotated) and `latest-old` (lightweight).
+    fn repo(dir: &TempDir) -> Shell {
+        let sh = Shell::new().unwrap();
+        sh.change_dir(dir.path());
+        cmd!(sh, "git init -q").run().unwrap();
+        for message in ["first", "second"] {
+            dir.write("file", message);
+            cmd!(sh, "git add -A").run().unwrap();
+            cmd!(sh, "git {GIT...} commit -q -m {message}")
+                .run()
+                .unwrap();
+        }
+        cmd!(sh, "git {GIT...} tag -a v1-old HEAD~ -m First -m Details.")
+            .run()
+            .unwrap();
+        cmd!(sh, "git tag latest-old HEAD~").run().unwrap();
+        sh
+    }
+
+    /// The type, tagger and message of `tag`, and the commit it points to.
+    fn describe(sh: &Shell, tag: &str) -> String {
+        let reference = format!("refs/tags/{tag}");
+        let format = "%(objecttype) %(tagger) %(contents)";
+        let fields = cmd!(sh, "git for-each-ref --format={format} {reference}")
+            .read()
+            .unwrap();
+        let commit = format!("{tag}^{{}}");
+        let target = cmd!(sh, "git rev-parse {commit}").read().unwrap();
+        format!("{target} {fields}")
+    }
+
+    #[test]
+    fn retagging_keeps_the_target_and_message() {
+        let dir = TempDir::new();
+        let sh = repo(&dir);
+        let before = [describe(&sh, "v1-old"), describe(&sh, "latest-old")];
+        let rename: TagRename = "s/-old$/-new/".parse().unwrap();
+        let moves = plan(&sh, dir.path(), &rename).unwrap();
+        apply(&sh, dir.path(), &moves, None, true, None).unwrap();
+        let tags = cmd!(sh, "git tag --list").read().unwrap();
+        assert_eq!(tags.lines().collect::<Vec<_>>(), ["latest-new", "v1-new"]);
+        let after = [describe(&sh, "v1-new"), describe(&sh, "latest-new")];
+        assert_eq!(after, before);
+        assert!(after[0].contains(" tag pmv <pmv@localhost> "));
+        assert!(after[0].contains("First\n\nDetails."));
+    }
+
+    #[test]
+    fn conflicting_renames_are_refused() {
+        let dir = TempDir::new();
+        let sh = repo(&dir);
+        let err = plan(&sh, dir.path(), &"s/.*-/v1-/".parse().unwrap())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("latest-old -> v1-old"), "{err}");
+    }
+}
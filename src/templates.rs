@@ -0,0 +1,113 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{bail, Context};
+use globset::Glob;
+
+use crate::{filter::WalkFilter, manifest::changed_lines, Rewrite};
+
+/// Files to regenerate from a template, rather than patch: those matching
+/// `glob` (relative to the project directory). Written `GLOB=TEMPLATE`.
+#[derive(Debug, Clone)]
+pub struct TemplateFile {
+    glob: String,
+    template: PathBuf,
+}
+
+impl FromStr for TemplateFile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((glob, template))
+                if !glob.is_empty() && !template.is_empty() =>
+            {
+                Ok(TemplateFile {
+                    glob: glob.to_string(),
+                    template: PathBuf::from(template),
+                })
+            }
+            _ => Err(format!("expected GLOB=TEMPLATE, got {s:?}")),
+        }
+    }
+}
+
+/// What `{old}`, `{new}` and `{owner}` stand for in a template.
+pub struct Vars<'a> {
+    pub old: &'a str,
+    pub new: &'a str,
+    pub owner: Option<&'a str>,
+}
+
+impl Vars<'_> {
+    fn render(&self, template: &Path, text: &str) -> anyhow::Result<String> {
+        if text.contains("{owner}") && self.owner.is_none() {
+            bail!(
+                "{} refers to {{owner}}, but there is no remote to take it from (pass --remote).",
+                template.display()
+            );
+        }
+        Ok(text
+            .replace("{old}", self.old)
+            .replace("{new}", self.new)
+            .replace("{owner}", self.owner.unwrap_or_default()))
+    }
+}
+
+/// Regenerates the files of the project in `root` that `templates` pick out,
+/// on top of the planned `rewrites`: a file that is already being rewritten
+/// has its new contents replaced by the rendered template, and any other
+/// file gets a rewrite of its own. Files the template would leave as they
+/// are get none.
+pub fn apply_templates(
+    root: &Path,
+    templates: &[TemplateFile],
+    vars: &Vars,
+    filter: &WalkFilter,
+    rewrites: &mut Vec<Rewrite>,
+) -> anyhow::Result<()> {
+    if templates.is_empty() {
+        return Ok(());
+    }
+    let mut rendered = Vec::with_capacity(templates.len());
+    for template in templates {
+        let glob = Glob::new(&template.glob)
+            .with_context(|| format!("...while parsing {:?}", template.glob))?
+            .compile_matcher();
+        let text =
+            fs::read_to_string(&template.template).with_context(|| {
+                format!("Could not read {}", template.template.display())
+            })?;
+        rendered.push((glob, vars.render(&template.template, &text)?));
+    }
+    for entry in filter.walker().build() {
+        let entry = entry?;
+        if !entry.file_type().is_some_and(|ft| ft.is_file()) {
+            continue;
+        }
+        let path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        let Some((_, contents)) =
+            rendered.iter().find(|(glob, _)| glob.is_match(path))
+        else {
+            continue;
+        };
+        let original = fs::read_to_string(entry.path()).with_context(|| {
+            format!("Could not read {}", entry.path().display())
+        })?;
+        rewrites.retain(|rw| rw.path != path);
+        if *contents == original {
+            continue;
+        }
+        rewrites.push(Rewrite {
+            path: path.to_path_buf(),
+            lines: changed_lines(&original, contents),
+            contents: contents.clone(),
+            count: 1,
+        });
+    }
+    rewrites.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(())
+}